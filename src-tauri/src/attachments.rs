@@ -0,0 +1,412 @@
+use crate::chunker::DocumentKind;
+use crate::crypto;
+use crate::extract;
+use crate::knowledge;
+use crate::ollama_service::ChatMessage;
+use crate::vision;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// files and images attached to messages, the bytes live in a content addressed blob store next to the
+// database so the same file attached twice is stored once, rows in the attachments table point at them
+// database encryption covers names, extracted text and the blobs themselves
+
+pub const KIND_IMAGE: &str = "image";
+pub const KIND_DOCUMENT: &str = "document";
+
+const BLOB_DIR: &str = "attachments";
+const MAX_ATTACHMENT_BYTES: u64 = extract::MAX_DOCUMENT_BYTES;
+// document text inlined into a prompt is cut off here, the context window couldn't hold more anyway
+const MAX_INLINE_CHARS: usize = 100_000;
+// attachments uploaded for a prompt that was never sent are dropped after this long
+const STAGED_RETENTION_HOURS: i64 = 24;
+// blobs younger than this are left alone by the sweep, their row may have been added after it listed the hashes
+const BLOB_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(3600);
+
+const IMAGE_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("bmp", "image/bmp"),
+];
+const DOCUMENT_TYPES: &[(&str, &str)] = &[
+    ("pdf", "application/pdf"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("xhtml", "application/xhtml+xml"),
+    ("epub", "application/epub+zip"),
+    ("md", "text/markdown"),
+    ("json", "application/json"),
+    ("csv", "text/csv"),
+];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Attachment {
+    pub id: i64,
+    pub message_id: Option<i64>, // None while staged for a prompt that hasn't been sent yet
+    pub name: String,
+    pub mime_type: String,
+    pub kind: String, // "image" | "document"
+    pub size: i64,
+    pub hash: String,
+    pub created_at: String,
+}
+
+// an attachment read and converted, ready to be stored
+pub struct PreparedAttachment {
+    pub name: String,
+    pub mime_type: String,
+    pub kind: &'static str,
+    pub bytes: Vec<u8>,
+    pub text: Option<String>, // what gets inlined into the prompt, documents only
+}
+
+pub fn create_table(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS attachments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            message_id INTEGER,
+            hash TEXT NOT NULL,
+            name TEXT NOT NULL,
+            mime_type TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            text TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_attachments_message ON attachments (message_id);
+        CREATE INDEX IF NOT EXISTS idx_attachments_hash ON attachments (hash);"
+    )
+}
+
+pub fn blob_dir(app_data_dir: &Path) -> PathBuf {
+    app_data_dir.join(BLOB_DIR)
+}
+
+// blobs are fanned out by the first two hex digits so no directory grows too large
+fn blob_path(blob_dir: &Path, hash: &str) -> PathBuf {
+    blob_dir.join(&hash[..2]).join(hash)
+}
+
+fn sealed_blob_path(blob_dir: &Path, hash: &str) -> PathBuf {
+    blob_path(blob_dir, hash).with_extension(crypto::SEALED_BLOB_EXTENSION)
+}
+
+fn lookup(types: &[(&str, &'static str)], name: &str) -> Option<&'static str> {
+    let extension = Path::new(name).extension()?.to_str()?.to_lowercase();
+    types.iter().find(|(ext, _)| *ext == extension).map(|(_, mime)| *mime)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| path.display().to_string())
+}
+
+// reads a file from disk, images are kept as they are and documents are converted to text
+pub fn prepare_file(path: &Path) -> Result<PreparedAttachment, String> {
+    let name = file_name(path);
+    let size = std::fs::metadata(path)
+        .map_err(|e| format!("Failed to read {}: {}", name, e))?
+        .len();
+    if size > MAX_ATTACHMENT_BYTES {
+        return Err(format!("{} is larger than the {} MB attachment limit", name, MAX_ATTACHMENT_BYTES / (1024 * 1024)));
+    }
+
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    if let Some(mime_type) = lookup(IMAGE_TYPES, &name) {
+        return Ok(PreparedAttachment { name, mime_type: mime_type.to_string(), kind: KIND_IMAGE, bytes, text: None });
+    }
+
+    let text = if extract::is_extractable(path) {
+        let document = extract::extract_file(path)?;
+        if document.truncated {
+            println!("Attachment {} was truncated during extraction", name);
+        }
+        document.text()
+    } else {
+        match std::str::from_utf8(&bytes) {
+            Ok(text) => text.to_string(),
+            Err(_) if DocumentKind::from_path(path).is_some() => return Err(format!("{} is not UTF-8 text", name)),
+            Err(_) => return Err(format!("{} can't be attached, only images, documents and text files are supported", name)),
+        }
+    };
+
+    let mime_type = lookup(DOCUMENT_TYPES, &name).unwrap_or("text/plain").to_string();
+    Ok(PreparedAttachment { name, mime_type, kind: KIND_DOCUMENT, bytes, text: Some(text) })
+}
+
+// an image handed over as base64, for pasted screenshots that never existed as a file
+pub fn prepare_image_data(name: &str, data: &str) -> Result<PreparedAttachment, String> {
+    let Some(mime_type) = lookup(IMAGE_TYPES, name) else {
+        return Err(format!("{} is not a supported image type", name));
+    };
+    // data urls carry a "data:image/png;base64," prefix
+    let encoded = data.split_once(";base64,").map(|(_, encoded)| encoded).unwrap_or(data);
+    let bytes = BASE64.decode(encoded.trim()).map_err(|e| format!("Failed to decode image data: {}", e))?;
+    if bytes.len() as u64 > MAX_ATTACHMENT_BYTES {
+        return Err(format!("{} is larger than the {} MB attachment limit", name, MAX_ATTACHMENT_BYTES / (1024 * 1024)));
+    }
+    Ok(PreparedAttachment { name: name.to_string(), mime_type: mime_type.to_string(), kind: KIND_IMAGE, bytes, text: None })
+}
+
+fn write_blob(blob_dir: &Path, hash: &str, bytes: &[u8]) -> Result<(), String> {
+    if blob_path(blob_dir, hash).exists() || sealed_blob_path(blob_dir, hash).exists() {
+        return Ok(());
+    }
+    let (bytes, sealed) = crypto::seal_blob(bytes)?;
+    let path = match sealed {
+        true => sealed_blob_path(blob_dir, hash),
+        false => blob_path(blob_dir, hash),
+    };
+    let dir = path.parent().unwrap_or(blob_dir);
+    std::fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    // written next to the final name and renamed so a crash never leaves a partial blob behind
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, &bytes).map_err(|e| format!("Failed to store attachment: {}", e))?;
+    std::fs::rename(&partial, &path).map_err(|e| format!("Failed to store attachment: {}", e))
+}
+
+// records the attachment and writes the blob unless it is already there, the row goes in first so a sweep
+// running meanwhile sees the blob is referenced
+pub fn store(conn: &Connection, blob_dir: &Path, message_id: Option<i64>, prepared: PreparedAttachment) -> Result<Attachment, String> {
+    let hash = knowledge::content_hash(&prepared.bytes);
+    let text = prepared.text.as_deref().map(crypto::seal).transpose()?;
+    let created_at = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO attachments (message_id, hash, name, mime_type, kind, size, text, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![message_id, &hash, crypto::seal(&prepared.name)?, &prepared.mime_type, prepared.kind, prepared.bytes.len() as i64, text, &created_at],
+    ).map_err(|e| format!("Failed to save attachment: {}", e))?;
+    let id = conn.last_insert_rowid();
+
+    if let Err(e) = write_blob(blob_dir, &hash, &prepared.bytes) {
+        remove(conn, id)?;
+        return Err(e);
+    }
+
+    Ok(Attachment {
+        id,
+        message_id,
+        name: prepared.name,
+        mime_type: prepared.mime_type,
+        kind: prepared.kind.to_string(),
+        size: prepared.bytes.len() as i64,
+        hash,
+        created_at,
+    })
+}
+
+const ATTACHMENT_COLUMNS: &str = "attachments.id, attachments.message_id, attachments.name, attachments.mime_type, \
+     attachments.kind, attachments.size, attachments.hash, attachments.created_at";
+
+fn map_attachment(row: &rusqlite::Row) -> SqlResult<Attachment> {
+    let name: String = row.get(2)?;
+    Ok(Attachment {
+        id: row.get(0)?,
+        message_id: row.get(1)?,
+        name: crypto::open(&name)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into()))?,
+        mime_type: row.get(3)?,
+        kind: row.get(4)?,
+        size: row.get(5)?,
+        hash: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+// every attachment in a conversation grouped by message, in the order they were attached
+pub fn load_for_conversation(conn: &Connection, conversation_id: &str) -> SqlResult<HashMap<i64, Vec<Attachment>>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM attachments JOIN messages ON messages.id = attachments.message_id
+         WHERE messages.conversation_id = ?1 ORDER BY attachments.id",
+        ATTACHMENT_COLUMNS
+    ))?;
+    let mut by_message: HashMap<i64, Vec<Attachment>> = HashMap::new();
+    for attachment in stmt.query_map(params![conversation_id], map_attachment)? {
+        let attachment = attachment?;
+        if let Some(message_id) = attachment.message_id {
+            by_message.entry(message_id).or_default().push(attachment);
+        }
+    }
+    Ok(by_message)
+}
+
+pub fn load(conn: &Connection, attachment_id: i64) -> Result<Option<Attachment>, String> {
+    conn.query_row(
+        &format!("SELECT {} FROM attachments WHERE id = ?1", ATTACHMENT_COLUMNS),
+        params![attachment_id],
+        map_attachment,
+    ).optional().map_err(|e| format!("Failed to load attachment: {}", e))
+}
+
+// staged attachments by id, ids that are missing or already belong to a message are skipped
+pub fn load_staged(conn: &Connection, attachment_ids: &[i64]) -> Result<Vec<Attachment>, String> {
+    let mut staged = Vec::new();
+    for id in attachment_ids {
+        match load(conn, *id)? {
+            Some(attachment) if attachment.message_id.is_none() => staged.push(attachment),
+            _ => println!("Attachment {} is not staged, skipping it", id),
+        }
+    }
+    Ok(staged)
+}
+
+// hands staged attachments to the message they were sent with
+pub fn link(conn: &Connection, attachment_ids: &[i64], message_id: i64) -> Result<(), String> {
+    for id in attachment_ids {
+        conn.execute(
+            "UPDATE attachments SET message_id = ?1 WHERE id = ?2 AND message_id IS NULL",
+            params![message_id, id],
+        ).map_err(|e| format!("Failed to attach to message: {}", e))?;
+    }
+    Ok(())
+}
+
+// an edited prompt keeps the attachments of the message it branched from
+pub fn copy_to(conn: &Connection, from_message_id: i64, to_message_id: i64) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO attachments (message_id, hash, name, mime_type, kind, size, text, created_at)
+         SELECT ?1, hash, name, mime_type, kind, size, text, created_at FROM attachments
+         WHERE message_id = ?2 ORDER BY id",
+        params![to_message_id, from_message_id],
+    ).map_err(|e| format!("Failed to copy attachments: {}", e))?;
+    Ok(())
+}
+
+pub fn remove(conn: &Connection, attachment_id: i64) -> Result<bool, String> {
+    conn.execute("DELETE FROM attachments WHERE id = ?1", params![attachment_id])
+        .map(|removed| removed > 0)
+        .map_err(|e| format!("Failed to remove attachment: {}", e))
+}
+
+fn load_text(conn: &Connection, attachment_id: i64) -> Result<Option<String>, String> {
+    let stored: Option<String> = conn.query_row(
+        "SELECT text FROM attachments WHERE id = ?1",
+        params![attachment_id],
+        |row| row.get(0),
+    ).optional().map_err(|e| format!("Failed to load attachment: {}", e))?.flatten();
+    stored.map(|stored| crypto::open(&stored)).transpose()
+}
+
+pub fn read_blob(blob_dir: &Path, attachment: &Attachment) -> Result<Vec<u8>, String> {
+    let sealed_path = sealed_blob_path(blob_dir, &attachment.hash);
+    let sealed = sealed_path.exists();
+    let path = if sealed { sealed_path } else { blob_path(blob_dir, &attachment.hash) };
+    let stored = std::fs::read(path)
+        .map_err(|e| format!("Failed to read attachment {}: {}", attachment.name, e))?;
+    crypto::open_blob(stored, sealed)
+}
+
+// appends each document's text to the prompt, marked with its file name
+fn inline_documents(content: &str, documents: &[(String, String)]) -> String {
+    let mut inlined = content.to_string();
+    for (name, text) in documents {
+        let text = if text.chars().count() > MAX_INLINE_CHARS {
+            format!("{}\n[truncated]", text.chars().take(MAX_INLINE_CHARS).collect::<String>())
+        } else {
+            text.clone()
+        };
+        inlined.push_str(&format!("\n\n<attachment name=\"{}\">\n{}\n</attachment>", name, text.trim()));
+    }
+    inlined
+}
+
+// an image attachment preprocessed and base64 encoded, converted once per size limit
+fn image_data(blob_dir: &Path, attachment: &Attachment, max_image_dimension: u32) -> Result<String, String> {
+    vision::preprocess_cached(&attachment.hash, max_image_dimension, || read_blob(blob_dir, attachment))
+}
+
+// preprocesses the image attachments on a blocking thread, building the request afterwards only hits the cache
+pub async fn warm_images(blob_dir: PathBuf, attachments: Vec<Attachment>, max_image_dimension: u32) {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        for attachment in attachments.iter().filter(|a| a.kind == KIND_IMAGE) {
+            // failures are cached and reported when the message is built
+            let _ = image_data(&blob_dir, attachment, max_image_dimension);
+        }
+    }).await;
+    if let Err(e) = result {
+        println!("Failed to preprocess images: {}", e);
+    }
+}
+
+// a message ready for Ollama, images are preprocessed and sent base64 encoded, documents inlined into the text
+// an image that went missing or can't be decoded is logged and left out rather than failing the whole request
+pub fn chat_message(
+    conn: &Connection,
+    blob_dir: &Path,
+    max_image_dimension: u32,
+    role: String,
+    content: String,
+    attachments: &[Attachment],
+) -> Result<ChatMessage, String> {
+    let mut images = Vec::new();
+    let mut documents = Vec::new();
+    for attachment in attachments {
+        if attachment.kind == KIND_IMAGE {
+            match image_data(blob_dir, attachment, max_image_dimension) {
+                Ok(image) => images.push(image),
+                Err(e) => println!("Leaving out attachment {}: {}", attachment.name, e),
+            }
+        } else if let Some(text) = load_text(conn, attachment.id)? {
+            documents.push((attachment.name.clone(), text));
+        }
+    }
+
+    Ok(ChatMessage {
+        role,
+        content: inline_documents(&content, &documents),
+        images: if images.is_empty() { None } else { Some(images) },
+    })
+}
+
+// drops staged attachments that were never sent and every blob no attachment points at anymore
+pub fn sweep(conn: &Connection, blob_dir: &Path) -> Result<usize, String> {
+    let cutoff = (Utc::now() - chrono::Duration::hours(STAGED_RETENTION_HOURS)).to_rfc3339();
+    conn.execute(
+        "DELETE FROM attachments WHERE message_id IS NULL AND created_at < ?1",
+        params![&cutoff],
+    ).map_err(|e| format!("Failed to remove staged attachments: {}", e))?;
+
+    let referenced: HashSet<String> = {
+        let mut stmt = conn.prepare("SELECT DISTINCT hash FROM attachments")
+            .map_err(|e| format!("Failed to list attachments: {}", e))?;
+        let hashes = stmt.query_map([], |row| row.get(0))
+            .map_err(|e| format!("Failed to list attachments: {}", e))?;
+        hashes.collect::<SqlResult<_>>().map_err(|e| format!("Failed to list attachments: {}", e))?
+    };
+
+    let mut removed = 0;
+    let Ok(fans) = std::fs::read_dir(blob_dir) else {
+        return Ok(0);
+    };
+    for fan in fans.flatten() {
+        for blob in std::fs::read_dir(fan.path()).into_iter().flatten().flatten() {
+            let name = blob.file_name().to_string_lossy().to_string();
+            // blobs still being written or re-encrypted
+            if name.ends_with(".partial") {
+                continue;
+            }
+            let recent = blob.metadata()
+                .and_then(|m| m.modified())
+                .map(|modified| modified.elapsed().map_or(true, |age| age < BLOB_GRACE_PERIOD))
+                .unwrap_or(true);
+            if recent {
+                continue;
+            }
+            let hash = name.strip_suffix(&format!(".{}", crypto::SEALED_BLOB_EXTENSION)).unwrap_or(&name);
+            if !referenced.contains(hash) && std::fs::remove_file(blob.path()).is_ok() {
+                removed += 1;
+            }
+        }
+    }
+    Ok(removed)
+}
@@ -0,0 +1,344 @@
+use chrono::Utc;
+use rusqlite::{Connection, MAIN_DB};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+
+// version 2 added the attachment blobs
+pub const BACKUP_FORMAT_VERSION: u32 = 2;
+const MANIFEST_NAME: &str = "manifest.json";
+
+// every database in the app data directory that belongs in a backup
+pub const BACKUP_DATABASES: &[&str] = &["conversations.db", "editor_preferences.db"];
+// directories of plain files that belong in a backup, stored in the archive under their own name
+pub const BACKUP_DIRECTORIES: &[&str] = &["attachments"];
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupEntry {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub app_version: String,
+    pub created_at: String,
+    pub files: Vec<BackupEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BackupInfo {
+    pub path: String,
+    pub created_at: String,
+    pub size: u64,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+// a scratch directory next to the data so renames during restore stay on the same filesystem
+fn scratch_dir(app_data_dir: &Path, label: &str) -> Result<PathBuf, String> {
+    let dir = app_data_dir.join(format!(".{}-{}", label, Utc::now().timestamp_millis()));
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+// snapshots every database with SQLite's online backup API and writes them with a manifest into one zip
+pub fn create_backup(app_data_dir: &Path, destination: &Path) -> Result<BackupInfo, String> {
+    let scratch = scratch_dir(app_data_dir, "backup")?;
+    let result = write_backup_archive(app_data_dir, &scratch, destination);
+    let _ = std::fs::remove_dir_all(&scratch);
+    result
+}
+
+// every file below a backed up directory as (archive name, path), files still being written are left out
+fn directory_files(app_data_dir: &Path, directory: &str) -> Result<Vec<(String, PathBuf)>, String> {
+    let mut files = Vec::new();
+    let mut pending = vec![(directory.to_string(), app_data_dir.join(directory))];
+    while let Some((name, path)) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&path) else {
+            continue;
+        };
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            let entry_name = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            let file_type = entry.file_type().map_err(|e| format!("Failed to read {}: {}", entry_name, e))?;
+            if file_type.is_dir() {
+                pending.push((entry_name, entry.path()));
+            } else if file_type.is_file() && entry.path().extension().is_none_or(|ext| ext != "partial") {
+                files.push((entry_name, entry.path()));
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// a directory entry in the archive, only names below a backed up directory without any way out of it
+fn is_directory_entry(name: &str) -> bool {
+    let mut parts = name.split('/');
+    let Some(directory) = parts.next() else {
+        return false;
+    };
+    BACKUP_DIRECTORIES.contains(&directory)
+        && name.contains('/')
+        && parts.all(|part| !part.is_empty() && part != "." && part != ".." && !part.contains('\\'))
+}
+
+fn write_backup_archive(app_data_dir: &Path, scratch: &Path, destination: &Path) -> Result<BackupInfo, String> {
+    let created_at = Utc::now().to_rfc3339();
+    let mut snapshots = Vec::new();
+
+    for name in BACKUP_DATABASES {
+        let source_path = app_data_dir.join(name);
+        if !source_path.exists() {
+            continue;
+        }
+        let snapshot_path = scratch.join(name);
+        let source = Connection::open(&source_path)
+            .map_err(|e| format!("Failed to open {}: {}", name, e))?;
+        source.backup(MAIN_DB, &snapshot_path, None)
+            .map_err(|e| format!("Failed to snapshot {}: {}", name, e))?;
+        snapshots.push((name.to_string(), snapshot_path));
+    }
+    for directory in BACKUP_DIRECTORIES {
+        snapshots.extend(directory_files(app_data_dir, directory)?);
+    }
+
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create backup directory: {}", e))?;
+    }
+    // write to a temporary name first so a failed backup never leaves a truncated archive behind
+    let partial = destination.with_extension("partial");
+    let file = File::create(&partial)
+        .map_err(|e| format!("Failed to create {}: {}", partial.display(), e))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    // files are read one at a time so the attachments never have to fit in memory together
+    let mut files = Vec::new();
+    for (name, path) in &snapshots {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+        zip.start_file(name.as_str(), options).map_err(|e| e.to_string())?;
+        zip.write_all(&bytes).map_err(|e| e.to_string())?;
+        files.push(BackupEntry {
+            name: name.clone(),
+            size: bytes.len() as u64,
+            sha256: sha256_hex(&bytes),
+        });
+    }
+
+    let manifest = BackupManifest {
+        format_version: BACKUP_FORMAT_VERSION,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        created_at: created_at.clone(),
+        files,
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    zip.start_file(MANIFEST_NAME, options).map_err(|e| e.to_string())?;
+    zip.write_all(&manifest_json).map_err(|e| e.to_string())?;
+    zip.finish().map_err(|e| format!("Failed to finish backup archive: {}", e))?;
+
+    std::fs::rename(&partial, destination)
+        .map_err(|e| format!("Failed to move backup into place: {}", e))?;
+    let size = std::fs::metadata(destination).map(|m| m.len()).unwrap_or(0);
+
+    println!("Backup written to {}", destination.display());
+    Ok(BackupInfo {
+        path: destination.display().to_string(),
+        created_at,
+        size,
+    })
+}
+
+// reads the manifest and checks that every listed file is present and untampered, and that every database
+// passes an integrity check
+pub fn validate_backup(archive_path: &Path, scratch: &Path) -> Result<BackupManifest, String> {
+    let file = File::open(archive_path)
+        .map_err(|e| format!("Failed to open {}: {}", archive_path.display(), e))?;
+    let mut zip = zip::ZipArchive::new(file)
+        .map_err(|e| format!("Not a valid backup archive: {}", e))?;
+
+    let manifest: BackupManifest = {
+        let mut entry = zip.by_name(MANIFEST_NAME)
+            .map_err(|_| "Backup archive has no manifest".to_string())?;
+        let mut raw = String::new();
+        entry.read_to_string(&mut raw).map_err(|e| e.to_string())?;
+        serde_json::from_str(&raw).map_err(|e| format!("Invalid backup manifest: {}", e))?
+    };
+
+    if manifest.format_version > BACKUP_FORMAT_VERSION {
+        return Err(format!("Backup format {} is newer than this app supports", manifest.format_version));
+    }
+
+    for entry in &manifest.files {
+        let is_database = BACKUP_DATABASES.contains(&entry.name.as_str());
+        if !is_database && !is_directory_entry(&entry.name) {
+            return Err(format!("Unexpected file in backup: {}", entry.name));
+        }
+        let mut bytes = Vec::new();
+        zip.by_name(&entry.name)
+            .map_err(|_| format!("Backup is missing {}", entry.name))?
+            .read_to_end(&mut bytes)
+            .map_err(|e| e.to_string())?;
+        if sha256_hex(&bytes) != entry.sha256 {
+            return Err(format!("Checksum mismatch for {}", entry.name));
+        }
+
+        let extracted = scratch.join(&entry.name);
+        if let Some(parent) = extracted.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to extract {}: {}", entry.name, e))?;
+        }
+        std::fs::write(&extracted, &bytes)
+            .map_err(|e| format!("Failed to extract {}: {}", entry.name, e))?;
+        if !is_database {
+            continue;
+        }
+        let conn = Connection::open(&extracted)
+            .map_err(|e| format!("Failed to open {} from backup: {}", entry.name, e))?;
+        let check: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))
+            .map_err(|e| format!("Failed to check {}: {}", entry.name, e))?;
+        if check != "ok" {
+            return Err(format!("{} in backup is corrupt: {}", entry.name, check));
+        }
+    }
+
+    Ok(manifest)
+}
+
+// validates the archive and swaps its databases and directories in, the current ones are kept as *.pre-restore
+// backups made before attachments were included leave the current attachments in place
+pub fn restore_backup(app_data_dir: &Path, archive_path: &Path) -> Result<BackupManifest, String> {
+    let scratch = scratch_dir(app_data_dir, "restore")?;
+    let mut swaps: Vec<Swap> = Vec::new();
+    let result = (|| {
+        let manifest = validate_backup(archive_path, &scratch)?;
+        for directory in BACKUP_DIRECTORIES {
+            if scratch.join(directory).exists() {
+                swap_in(app_data_dir, &scratch, directory, &mut swaps)?;
+            }
+        }
+        for entry in manifest.files.iter().filter(|entry| BACKUP_DATABASES.contains(&entry.name.as_str())) {
+            swap_in(app_data_dir, &scratch, &entry.name, &mut swaps)?;
+        }
+        Ok(manifest)
+    })();
+    // a failed step puts every live file and directory set aside so far back in place
+    if result.is_err() {
+        for swap in swaps.iter().rev() {
+            roll_back(swap);
+        }
+    }
+    let _ = std::fs::remove_dir_all(&scratch);
+    result
+}
+
+// a live path replaced by its restored copy, previous is where the live one was set aside
+struct Swap {
+    live: PathBuf,
+    previous: Option<PathBuf>,
+}
+
+// sets the live copy of name aside as name.pre-restore and moves the restored one into its place
+fn swap_in(app_data_dir: &Path, scratch: &Path, name: &str, swaps: &mut Vec<Swap>) -> Result<(), String> {
+    let live = app_data_dir.join(name);
+    let mut previous = None;
+    if live.exists() {
+        let aside = app_data_dir.join(format!("{}.pre-restore", name));
+        if aside.exists() {
+            remove_path(&aside).map_err(|e| format!("Failed to remove {}: {}", aside.display(), e))?;
+        }
+        std::fs::rename(&live, &aside).map_err(|e| format!("Failed to set aside {}: {}", name, e))?;
+        previous = Some(aside);
+    }
+    // recorded before the restored copy moves in, so a failed move still gets the live one back
+    swaps.push(Swap { live: live.clone(), previous });
+    std::fs::rename(scratch.join(name), &live).map_err(|e| format!("Failed to restore {}: {}", name, e))
+}
+
+fn roll_back(swap: &Swap) {
+    // anything at the live path now is the restored copy, the original was moved aside
+    if swap.live.exists() {
+        if let Err(e) = remove_path(&swap.live) {
+            println!("Failed to remove restored {}: {}", swap.live.display(), e);
+            return;
+        }
+    }
+    if let Some(previous) = &swap.previous {
+        if let Err(e) = std::fs::rename(previous, &swap.live) {
+            println!("Failed to put back {}: {}", previous.display(), e);
+        }
+    }
+}
+
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+fn list_backups(directory: &Path) -> Vec<(PathBuf, std::time::SystemTime)> {
+    let Ok(entries) = std::fs::read_dir(directory) else { return Vec::new() };
+    let mut backups: Vec<(PathBuf, std::time::SystemTime)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with("jarvish-backup-") && n.ends_with(".zip"))
+                .unwrap_or(false)
+        })
+        .filter_map(|path| {
+            let modified = std::fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect();
+    backups.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+    backups
+}
+
+pub fn backup_file_name() -> String {
+    format!("jarvish-backup-{}.zip", Utc::now().format("%Y%m%d-%H%M%S"))
+}
+
+// makes a rolling backup when the newest one is older than the interval, then prunes beyond the retention count
+pub fn run_scheduled_backup(app_data_dir: &Path, directory: &Path, interval_hours: i64, keep: usize) -> Result<Option<BackupInfo>, String> {
+    if interval_hours <= 0 {
+        return Ok(None);
+    }
+
+    let existing = list_backups(directory);
+    let due = match existing.first() {
+        Some((_, modified)) => modified
+            .elapsed()
+            .map(|age| age.as_secs() >= interval_hours as u64 * 3600)
+            .unwrap_or(true),
+        None => true,
+    };
+    if !due {
+        return Ok(None);
+    }
+
+    let info = create_backup(app_data_dir, &directory.join(backup_file_name()))?;
+
+    for (path, _) in list_backups(directory).into_iter().skip(keep.max(1)) {
+        if let Err(e) = std::fs::remove_file(&path) {
+            println!("Failed to remove old backup {}: {}", path.display(), e);
+        }
+    }
+
+    Ok(Some(info))
+}
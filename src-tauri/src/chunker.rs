@@ -0,0 +1,239 @@
+use crate::tokenizer;
+use std::path::Path;
+
+// splits documents into pieces small enough to embed, along the structure of the file so a chunk
+// rarely starts in the middle of a section, a paragraph or a function
+
+const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown", "mdx"];
+const CODE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "js", "mjs", "ts", "jsx", "tsx", "go", "java", "kt", "scala", "swift", "c", "h", "cc",
+    "cpp", "hpp", "cs", "rb", "php", "lua", "sh", "bash", "sql", "css", "scss", "vue", "svelte",
+];
+const TEXT_EXTENSIONS: &[&str] = &["txt", "rst", "org", "adoc", "json", "toml", "yaml", "yml", "ini", "cfg", "csv"];
+// longest code signature kept as a chunk heading
+pub const MAX_HEADING_CHARS: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DocumentKind {
+    Markdown,
+    Code,
+    Text,
+}
+
+impl DocumentKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        if MARKDOWN_EXTENSIONS.contains(&extension.as_str()) {
+            Some(DocumentKind::Markdown)
+        } else if CODE_EXTENSIONS.contains(&extension.as_str()) {
+            Some(DocumentKind::Code)
+        } else if TEXT_EXTENSIONS.contains(&extension.as_str()) {
+            Some(DocumentKind::Text)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub heading: Option<String>, // "Install > Linux" for markdown, the signature line for code
+    pub content: String,
+    pub start_line: usize, // 1-based, inclusive
+    pub end_line: usize,
+}
+
+// a run of lines that should stay together, packed into chunks afterwards
+pub struct Block {
+    pub heading: Option<String>,
+    pub start: usize, // 0-based line indices, inclusive
+    pub end: usize,
+}
+
+fn markdown_heading(line: &str) -> Option<(usize, String)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &line[level..];
+    if !rest.is_empty() && !rest.starts_with(' ') {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim().to_string()))
+}
+
+// paragraphs, fenced code blocks and headings, each tagged with the heading path it sits under
+fn markdown_blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut headings: Vec<(usize, String)> = Vec::new();
+    let mut current: Option<Block> = None;
+    let mut fence: Option<&str> = None;
+
+    let heading_path = |headings: &[(usize, String)]| {
+        if headings.is_empty() {
+            None
+        } else {
+            Some(headings.iter().map(|(_, title)| title.as_str()).collect::<Vec<_>>().join(" > "))
+        }
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence {
+            if let Some(block) = current.as_mut() {
+                block.end = i;
+            }
+            if trimmed.starts_with(marker) {
+                fence = None;
+                blocks.extend(current.take());
+            }
+            continue;
+        }
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            blocks.extend(current.take());
+            fence = Some(&trimmed[..3]);
+            current = Some(Block { heading: heading_path(&headings), start: i, end: i });
+            continue;
+        }
+        if let Some((level, title)) = markdown_heading(trimmed) {
+            blocks.extend(current.take());
+            headings.retain(|(l, _)| *l < level);
+            headings.push((level, title));
+            blocks.push(Block { heading: heading_path(&headings), start: i, end: i });
+            continue;
+        }
+        if trimmed.is_empty() {
+            blocks.extend(current.take());
+            continue;
+        }
+        match current.as_mut() {
+            Some(block) => block.end = i,
+            None => current = Some(Block { heading: heading_path(&headings), start: i, end: i }),
+        }
+    }
+    blocks.extend(current);
+    blocks
+}
+
+fn is_comment(line: &str) -> bool {
+    ["//", "#", "/*", "*", "--", "\"\"\"", "'''"].iter().any(|marker| line.starts_with(marker))
+}
+
+// a new block starts at every unindented line that follows a blank line, which keeps functions,
+// classes and their doc comments together in most languages, closing brackets stay with their block
+fn code_blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut after_blank = true;
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            after_blank = true;
+            continue;
+        }
+        let top_level = !line.starts_with(char::is_whitespace);
+        let closing = line.starts_with(['}', ')', ']']);
+        match blocks.last_mut() {
+            Some(block) if !(after_blank && top_level && !closing) => block.end = i,
+            _ => blocks.push(Block { heading: None, start: i, end: i }),
+        }
+        after_blank = false;
+    }
+
+    for block in &mut blocks {
+        block.heading = lines[block.start..=block.end]
+            .iter()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty() && !is_comment(line))
+            .map(|line| line.chars().take(MAX_HEADING_CHARS).collect());
+    }
+    blocks
+}
+
+fn paragraph_blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut after_blank = true;
+    for (i, line) in lines.iter().enumerate() {
+        if line.trim().is_empty() {
+            after_blank = true;
+            continue;
+        }
+        match blocks.last_mut() {
+            Some(block) if !after_blank => block.end = i,
+            _ => blocks.push(Block { heading: None, start: i, end: i }),
+        }
+        after_blank = false;
+    }
+    blocks
+}
+
+fn make_chunk(lines: &[&str], heading: Option<String>, start: usize, end: usize) -> Chunk {
+    Chunk {
+        heading,
+        content: lines[start..=end].join("\n"),
+        start_line: start + 1,
+        end_line: end + 1,
+    }
+}
+
+// merges neighbouring blocks up to max_tokens, markdown only within a section, code keeps the heading of
+// its first block, a block that is too big on its own is cut at line boundaries
+fn pack(lines: &[&str], blocks: Vec<Block>, max_tokens: usize, across_headings: bool) -> Vec<Chunk> {
+    let line_tokens: Vec<usize> = lines.iter().map(|line| tokenizer::heuristic_count(line) + 1).collect();
+    let block_tokens = |start: usize, end: usize| line_tokens[start..=end].iter().sum::<usize>();
+
+    let mut chunks = Vec::new();
+    let mut pending: Option<(Block, usize)> = None;
+
+    for block in blocks {
+        let tokens = block_tokens(block.start, block.end);
+        if let Some((open, open_tokens)) = pending.as_mut() {
+            if (across_headings || open.heading == block.heading) && *open_tokens + tokens <= max_tokens {
+                open.end = block.end;
+                *open_tokens += tokens;
+                continue;
+            }
+        }
+        if let Some((open, _)) = pending.take() {
+            chunks.push(make_chunk(lines, open.heading, open.start, open.end));
+        }
+        if tokens <= max_tokens {
+            pending = Some((block, tokens));
+            continue;
+        }
+
+        let mut start = block.start;
+        let mut running = 0;
+        for (i, &line) in line_tokens.iter().enumerate().take(block.end + 1).skip(block.start) {
+            if running > 0 && running + line > max_tokens {
+                chunks.push(make_chunk(lines, block.heading.clone(), start, i - 1));
+                start = i;
+                running = 0;
+            }
+            running += line;
+        }
+        chunks.push(make_chunk(lines, block.heading, start, block.end));
+    }
+    if let Some((open, _)) = pending {
+        chunks.push(make_chunk(lines, open.heading, open.start, open.end));
+    }
+
+    chunks.retain(|chunk| !chunk.content.trim().is_empty());
+    chunks
+}
+
+pub fn chunk_document(kind: DocumentKind, text: &str, max_tokens: usize) -> Vec<Chunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    let blocks = match kind {
+        DocumentKind::Markdown => markdown_blocks(&lines),
+        DocumentKind::Code => code_blocks(&lines),
+        DocumentKind::Text => paragraph_blocks(&lines),
+    };
+    pack(&lines, blocks, max_tokens.max(1), kind != DocumentKind::Markdown)
+}
+
+// packs blocks found by another parser, like the symbols of a syntax tree, only neighbours under the
+// same heading are merged
+pub fn chunk_blocks(text: &str, blocks: Vec<Block>, max_tokens: usize) -> Vec<Chunk> {
+    let lines: Vec<&str> = text.lines().collect();
+    pack(&lines, blocks, max_tokens.max(1), false)
+}
@@ -0,0 +1,504 @@
+use crate::chunker::{self, Block, Chunk, DocumentKind};
+use crate::db::ensure_column;
+use crate::knowledge::{self, KnowledgeMatch};
+use crate::tokenizer;
+use ignore::WalkBuilder;
+use rusqlite::{params, Connection, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use tree_sitter::{Node, Parser};
+
+// git repositories in the knowledge base, walked the way git sees them, with source files chunked
+// along their top-level symbols so an excerpt is a whole function or type rather than a run of lines
+
+pub const KIND_FOLDER: &str = "folder";
+pub const KIND_REPOSITORY: &str = "repository";
+
+// symbols that are chunked together with their neighbours instead of on their own
+const MINOR_KINDS: &[&str] = &["const", "static", "variable"];
+// added to the similarity of a chunk that defines a symbol named in the query
+const SYMBOL_MATCH_BOOST: f32 = 0.25;
+// shorter words in a query are too common to be looked up as symbol names
+const MIN_SYMBOL_QUERY_CHARS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    Python,
+    JavaScript,
+    TypeScript,
+    Tsx,
+    Go,
+}
+
+impl Language {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "rs" => Some(Language::Rust),
+            "py" => Some(Language::Python),
+            "js" | "mjs" | "jsx" => Some(Language::JavaScript),
+            "ts" => Some(Language::TypeScript),
+            "tsx" => Some(Language::Tsx),
+            "go" => Some(Language::Go),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Rust => "rust",
+            Language::Python => "python",
+            Language::JavaScript => "javascript",
+            Language::TypeScript => "typescript",
+            Language::Tsx => "tsx",
+            Language::Go => "go",
+        }
+    }
+
+    fn grammar(self) -> tree_sitter::Language {
+        match self {
+            Language::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Language::Python => tree_sitter_python::LANGUAGE.into(),
+            Language::JavaScript => tree_sitter_javascript::LANGUAGE.into(),
+            Language::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Language::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+            Language::Go => tree_sitter_go::LANGUAGE.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: String, // "function", "method", "struct", "impl", "class", ...
+    pub parent: Option<String>, // the type or module a method or nested item belongs to
+    pub start_line: usize, // 1-based, inclusive, with doc comments and attributes
+    pub end_line: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SymbolMatch {
+    pub source_id: i64,
+    pub path: String,
+    pub name: String,
+    pub kind: String,
+    pub parent: Option<String>,
+    pub start_line: i64,
+    pub end_line: i64,
+}
+
+pub struct SourceIndex {
+    pub chunks: Vec<Chunk>,
+    pub symbols: Vec<Symbol>,
+}
+
+pub fn create_tables(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS knowledge_symbols (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            document_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            parent TEXT,
+            start_line INTEGER NOT NULL,
+            end_line INTEGER NOT NULL,
+            FOREIGN KEY (document_id) REFERENCES knowledge_documents(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_knowledge_symbols_document ON knowledge_symbols (document_id);
+        CREATE INDEX IF NOT EXISTS idx_knowledge_symbols_name ON knowledge_symbols (name COLLATE NOCASE);"
+    )?;
+
+    if ensure_column(conn, "knowledge_sources", "kind", &format!("TEXT NOT NULL DEFAULT '{}'", KIND_FOLDER))? {
+        let mut stmt = conn.prepare("SELECT id, path FROM knowledge_sources")?;
+        let sources = stmt
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<SqlResult<Vec<_>>>()?;
+        for (id, path) in sources.iter().filter(|(_, path)| is_repository(Path::new(path))) {
+            println!("Marking knowledge source {} as a repository", path);
+            conn.execute("UPDATE knowledge_sources SET kind = ?1 WHERE id = ?2", params![KIND_REPOSITORY, id])?;
+        }
+    }
+    // source files indexed before symbol chunking are re-chunked on the next sync
+    if ensure_column(conn, "knowledge_documents", "language", "TEXT")? {
+        for extension in ["rs", "py", "js", "mjs", "jsx", "ts", "tsx", "go"] {
+            conn.execute(
+                "UPDATE knowledge_documents SET content_hash = '' WHERE path LIKE '%.' || ?1",
+                params![extension],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn is_repository(path: &Path) -> bool {
+    path.join(".git").exists()
+}
+
+// stored with a document and used to fence its excerpts, other code files go by their extension
+pub fn language_name(path: &Path) -> Option<String> {
+    match Language::from_path(path) {
+        Some(language) => Some(language.name().to_string()),
+        None if DocumentKind::from_path(path) == Some(DocumentKind::Code) => {
+            path.extension().map(|e| e.to_string_lossy().to_lowercase())
+        }
+        None => None,
+    }
+}
+
+// every indexable file git doesn't ignore, the folders the knowledge base always skips are left out too
+pub fn collect_files(root: &Path) -> Result<Vec<PathBuf>, String> {
+    if !root.is_dir() {
+        return Err(format!("Failed to read {}: not a folder", root.display()));
+    }
+    let walker = WalkBuilder::new(root)
+        .hidden(true)
+        .follow_links(false)
+        .filter_entry(|entry| entry.depth() == 0 || !knowledge::is_skipped_name(&entry.file_name().to_string_lossy()))
+        .build();
+
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                println!("Skipping unreadable entry in {}: {}", root.display(), e);
+                continue;
+            }
+        };
+        if entry.file_type().is_some_and(|t| t.is_file()) && knowledge::is_indexable(entry.path()) {
+            files.push(entry.into_path());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn field_text<'a>(node: Node, field: &str, text: &'a str) -> Option<&'a str> {
+    node.child_by_field_name(field).map(|child| &text[child.byte_range()])
+}
+
+// the item inside an export or behind decorators
+fn definition(node: Node) -> Node {
+    let field = match node.kind() {
+        "decorated_definition" => "definition",
+        "export_statement" => "declaration",
+        _ => return node,
+    };
+    node.child_by_field_name(field).map(definition).unwrap_or(node)
+}
+
+// "Config" for `impl<T> Display for Config<T>` and `func (c *Config) Load()`
+fn type_name(node: Node, text: &str) -> String {
+    match node.kind() {
+        "generic_type" | "reference_type" | "pointer_type" => node
+            .child_by_field_name("type")
+            .or_else(|| node.named_child(0))
+            .map(|inner| type_name(inner, text))
+            .unwrap_or_else(|| text[node.byte_range()].to_string()),
+        "scoped_type_identifier" => field_text(node, "name", text).unwrap_or(&text[node.byte_range()]).to_string(),
+        _ => text[node.byte_range()].to_string(),
+    }
+}
+
+fn go_receiver(node: Node, text: &str) -> Option<String> {
+    let receiver = node.child_by_field_name("receiver")?;
+    let mut cursor = receiver.walk();
+    let parameter = receiver.named_children(&mut cursor).next()?;
+    parameter.child_by_field_name("type").map(|t| type_name(t, text))
+}
+
+fn describe_variable(node: Node, text: &str) -> Option<(&'static str, String)> {
+    let mut cursor = node.walk();
+    let declarator = node.named_children(&mut cursor).find(|child| child.kind() == "variable_declarator")?;
+    let name = declarator.child_by_field_name("name").filter(|name| name.kind() == "identifier")?;
+    let kind = match declarator.child_by_field_name("value").map(|value| value.kind()) {
+        Some("arrow_function" | "function_expression" | "function" | "generator_function") => "function",
+        Some("class") => "class",
+        _ => "variable",
+    };
+    Some((kind, text[name.byte_range()].to_string()))
+}
+
+fn describe_go_type(node: Node, text: &str) -> Option<(&'static str, String)> {
+    let mut cursor = node.walk();
+    let spec = node.named_children(&mut cursor).find(|child| matches!(child.kind(), "type_spec" | "type_alias"))?;
+    let kind = match spec.child_by_field_name("type").map(|t| t.kind()) {
+        Some("struct_type") => "struct",
+        Some("interface_type") => "interface",
+        _ => "type",
+    };
+    Some((kind, field_text(spec, "name", text)?.to_string()))
+}
+
+// the kind and name of a node that defines something, None for imports, expressions and the like
+fn describe(node: Node, text: &str) -> Option<(&'static str, String)> {
+    let node = definition(node);
+    let kind = match node.kind() {
+        "function_item" | "function_signature_item" | "function_declaration" | "generator_function_declaration"
+        | "function_definition" => "function",
+        "method_declaration" | "method_definition" | "method_signature" | "abstract_method_signature" => "method",
+        "struct_item" => "struct",
+        "enum_item" | "enum_declaration" => "enum",
+        "union_item" => "union",
+        "trait_item" => "trait",
+        "impl_item" => return Some(("impl", type_name(node.child_by_field_name("type")?, text))),
+        "mod_item" | "internal_module" | "module" => "module",
+        "const_item" => "const",
+        "static_item" => "static",
+        "type_item" | "type_alias_declaration" => "type",
+        "macro_definition" => "macro",
+        "class_definition" | "class_declaration" | "abstract_class_declaration" => "class",
+        "interface_declaration" => "interface",
+        "lexical_declaration" | "variable_declaration" => return describe_variable(node, text),
+        "type_declaration" => return describe_go_type(node, text),
+        _ => return None,
+    };
+    Some((kind, field_text(node, "name", text)?.to_string()))
+}
+
+// the body holding the members of a type or module, which a big one is split along
+fn container_body(node: Node) -> Option<Node> {
+    let node = definition(node);
+    match node.kind() {
+        "impl_item" | "trait_item" | "mod_item" | "internal_module" | "module" | "class_definition"
+        | "class_declaration" | "abstract_class_declaration" | "interface_declaration" => node.child_by_field_name("body"),
+        _ => None,
+    }
+}
+
+// comments and attributes directly above an item belong to it
+fn is_leading(node: Node) -> bool {
+    node.kind().ends_with("comment") || matches!(node.kind(), "attribute_item" | "decorator")
+}
+
+// 0-based and inclusive, a node that ends with its line break ends on the line before
+fn end_row(node: Node) -> usize {
+    let end = node.end_position();
+    if end.column == 0 && end.row > node.start_position().row {
+        end.row - 1
+    } else {
+        end.row
+    }
+}
+
+// the first line of the item, like the signature heading the plain code chunker uses
+fn signature(node: Node, text: &str, parent: Option<&str>) -> String {
+    let node = if node.kind() == "decorated_definition" { definition(node) } else { node };
+    let line = text[node.start_byte()..].lines().next().unwrap_or("").trim();
+    let heading = match parent {
+        Some(parent) => format!("{} > {}", parent, line),
+        None => line.to_string(),
+    };
+    heading.chars().take(chunker::MAX_HEADING_CHARS).collect()
+}
+
+fn collect_blocks(
+    scope: Node,
+    text: &str,
+    parent: Option<&str>,
+    max_tokens: usize,
+    blocks: &mut Vec<Block>,
+    symbols: &mut Vec<Symbol>,
+) {
+    let mut cursor = scope.walk();
+    let children: Vec<Node> = scope.named_children(&mut cursor).collect();
+    let mut leading: Option<usize> = None;
+    let mut previous_end: Option<usize> = None;
+
+    for node in children {
+        let node_start = node.start_position().row;
+        let end = end_row(node);
+        if is_leading(node) {
+            // a comment after code on the same line stays with that code
+            if leading.is_none() && previous_end.is_some_and(|previous| node_start <= previous) {
+                if let Some(block) = blocks.last_mut() {
+                    block.end = block.end.max(end);
+                }
+                previous_end = Some(end);
+                continue;
+            }
+            // a blank line between comments and the next item means they aren't its documentation
+            if let (Some(first), Some(previous)) = (leading, previous_end) {
+                if node_start > previous + 1 {
+                    blocks.push(Block { heading: None, start: first, end: previous });
+                    leading = None;
+                }
+            }
+            leading.get_or_insert(node_start);
+            previous_end = Some(end);
+            continue;
+        }
+        if let (Some(first), Some(previous)) = (leading, previous_end) {
+            if node_start > previous + 1 {
+                blocks.push(Block { heading: None, start: first, end: previous });
+                leading = None;
+            }
+        }
+        previous_end = Some(end);
+        let start = leading.take().unwrap_or(node_start);
+
+        let Some((kind, name)) = describe(node, text) else {
+            blocks.push(Block { heading: None, start, end });
+            continue;
+        };
+        let kind = if parent.is_some() && kind == "function" { "method" } else { kind };
+        symbols.push(Symbol {
+            name: name.clone(),
+            kind: kind.to_string(),
+            parent: parent.map(str::to_string).or_else(|| go_receiver(definition(node), text)),
+            start_line: start + 1,
+            end_line: end + 1,
+        });
+        if MINOR_KINDS.contains(&kind) {
+            blocks.push(Block { heading: None, start, end });
+            continue;
+        }
+
+        let heading = signature(node, text, parent);
+        let body = container_body(node).filter(|_| tokenizer::heuristic_count(&text[node.byte_range()]) > max_tokens);
+        let Some(body) = body else {
+            blocks.push(Block { heading: Some(heading), start, end });
+            continue;
+        };
+
+        // a type or module too big for one chunk is split along its members, the signature opens the first
+        // member's chunk and loose fields or constants go under the type's own heading
+        let first = blocks.len();
+        collect_blocks(body, text, Some(&name), max_tokens, blocks, symbols);
+        let members = &mut blocks[first..];
+        if members.is_empty() {
+            blocks.push(Block { heading: Some(heading), start, end });
+            continue;
+        }
+        for member in members.iter_mut().filter(|member| member.heading.is_none()) {
+            member.heading = Some(heading.clone());
+        }
+        members[0].start = start;
+        let last = members.len() - 1;
+        members[last].end = end;
+    }
+
+    if let (Some(first), Some(previous)) = (leading, previous_end) {
+        blocks.push(Block { heading: None, start: first, end: previous });
+    }
+}
+
+// chunks a source file along its syntax tree, None when the grammar can't be loaded or parsing gives up
+pub fn chunk_source(language: Language, text: &str, max_tokens: usize) -> Option<SourceIndex> {
+    let mut parser = Parser::new();
+    parser.set_language(&language.grammar()).ok()?;
+    let tree = parser.parse(text, None)?;
+
+    let mut blocks = Vec::new();
+    let mut symbols = Vec::new();
+    collect_blocks(tree.root_node(), text, None, max_tokens.max(1), &mut blocks, &mut symbols);
+
+    // rows past the last line only come from a file ending without a line break
+    let last_line = text.lines().count().saturating_sub(1);
+    blocks.retain(|block| block.start <= last_line);
+    for block in &mut blocks {
+        block.end = block.end.min(last_line);
+    }
+    Some(SourceIndex { chunks: chunker::chunk_blocks(text, blocks, max_tokens), symbols })
+}
+
+pub fn store_symbols(conn: &Connection, document_id: i64, symbols: &[Symbol]) -> Result<(), String> {
+    conn.execute("DELETE FROM knowledge_symbols WHERE document_id = ?1", params![document_id])
+        .map_err(|e| format!("Failed to clear symbols: {}", e))?;
+    let mut stmt = conn.prepare(
+        "INSERT INTO knowledge_symbols (document_id, name, kind, parent, start_line, end_line)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    for symbol in symbols {
+        stmt.execute(params![
+            document_id,
+            &symbol.name,
+            &symbol.kind,
+            &symbol.parent,
+            symbol.start_line as i64,
+            symbol.end_line as i64,
+        ]).map_err(|e| format!("Failed to save symbol: {}", e))?;
+    }
+    Ok(())
+}
+
+// words of a query that could name a symbol, "Config::load()" gives Config and load
+fn query_identifiers(query: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    query
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.chars().count() >= MIN_SYMBOL_QUERY_CHARS && !word.starts_with(|c: char| c.is_ascii_digit()))
+        .filter(|word| seen.insert(word.to_lowercase()))
+        .map(str::to_string)
+        .collect()
+}
+
+// symbols with one of the given names, matched ignoring case
+pub fn find_symbols(conn: &Connection, names: &[String], source_id: Option<i64>) -> SqlResult<Vec<(i64, SymbolMatch)>> {
+    let mut stmt = conn.prepare(
+        "SELECT y.document_id, d.source_id, s.path, d.path, y.name, y.kind, y.parent, y.start_line, y.end_line
+         FROM knowledge_symbols y
+         JOIN knowledge_documents d ON d.id = y.document_id
+         JOIN knowledge_sources s ON s.id = d.source_id
+         WHERE y.name = ?1 COLLATE NOCASE AND (?2 IS NULL OR d.source_id = ?2)
+         ORDER BY s.path, d.path, y.start_line"
+    )?;
+    let mut found = Vec::new();
+    for name in names {
+        let rows = stmt.query_map(params![name, source_id], |row| {
+            let root: String = row.get(2)?;
+            let path: String = row.get(3)?;
+            Ok((row.get(0)?, SymbolMatch {
+                source_id: row.get(1)?,
+                path: Path::new(&root).join(&path).to_string_lossy().to_string(),
+                name: row.get(4)?,
+                kind: row.get(5)?,
+                parent: row.get(6)?,
+                start_line: row.get(7)?,
+                end_line: row.get(8)?,
+            }))
+        })?;
+        found.extend(rows.collect::<SqlResult<Vec<_>>>()?);
+    }
+    Ok(found)
+}
+
+// raises the chunks that define a symbol the query names, so asking about `parse_args` finds its
+// definition ahead of code that merely reads similarly
+pub fn boost_symbol_matches(conn: &Connection, query: &str, scored: &mut [(i64, KnowledgeMatch)]) -> SqlResult<()> {
+    let names = query_identifiers(query);
+    if names.is_empty() {
+        return Ok(());
+    }
+    let mut definitions: HashMap<i64, Vec<(i64, i64)>> = HashMap::new();
+    for (document_id, symbol) in find_symbols(conn, &names, None)? {
+        definitions.entry(document_id).or_default().push((symbol.start_line, symbol.end_line));
+    }
+    for (document_id, m) in scored.iter_mut() {
+        let defines = definitions
+            .get(document_id)
+            .is_some_and(|ranges| ranges.iter().any(|(start, end)| *start <= m.end_line && *end >= m.start_line));
+        if defines {
+            m.score += SYMBOL_MATCH_BOOST;
+        }
+    }
+    Ok(())
+}
+
+// code excerpts from repositories only, or from one of them
+pub fn search(
+    conn: &Connection,
+    model: &str,
+    query: &[f32],
+    query_text: &str,
+    source_id: Option<i64>,
+    limit: usize,
+) -> SqlResult<Vec<KnowledgeMatch>> {
+    let mut scored = knowledge::scored_chunks(conn, model, query, Some(KIND_REPOSITORY), source_id)?;
+    boost_symbol_matches(conn, query_text, &mut scored)?;
+    let mut matches: Vec<KnowledgeMatch> = scored.into_iter().map(|(_, m)| m).collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit);
+    Ok(matches)
+}
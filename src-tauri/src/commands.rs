@@ -1,16 +1,39 @@
-use crate::ollama_service::{OllamaService, ModelInfo};
+use crate::ollama_service::{OllamaService, ModelInfo, ChatMessage};
+use crate::message_tree::{MessageNode, MessageTree};
+use crate::db::ensure_column;
 use std::collections::HashMap;
 use crate::ConversationState;
-use serde_json::Value;
+use serde_json::{json, Value};
 use serde::{Deserialize, Serialize};
 use futures_util::StreamExt;
 use tauri::{Emitter, Window, AppHandle, Manager};
 use tokio_util::sync::CancellationToken;
-use rusqlite::{params, Connection, Result as SqlResult};
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
 use chrono::Utc;
 use rand::Rng;
 use std::path::PathBuf;
 use crate::logger::process_log; 
+use crate::settings::{self, AppSettings};
+use crate::export::{self, ConversationExport, ExportFormat};
+use crate::import::{self, ImportedConversation, ImportFileReport};
+use crate::dataset::{self, DatasetOptions, DatasetSummary};
+use crate::backup::{self, BackupInfo, BackupManifest};
+use crate::crypto::{self, EncryptionStatus, Secret};
+use crate::tokenizer;
+use crate::context_window::{self, ContextUsage, TrimStrategy};
+use crate::summary::{self, ConversationSummary};
+use crate::titles::{self, RetitleSummary};
+use crate::chunker::{self, DocumentKind};
+use crate::knowledge::{self, EmbeddedDocument, IndexReport, KnowledgeMatch, KnowledgeSource};
+use crate::code_index::{self, SymbolMatch};
+use crate::retrieval::{self, Citation, CitationsEvent};
+use crate::semantic_search::{self, SemanticSearchHit};
+use crate::watcher;
+use crate::extract::{self, ExtractedDocument};
+use crate::attachments::{self, Attachment};
+use crate::vision;
+use crate::personas::{self, Persona, PersonaDraft};
+use crate::templates::{self, PromptTemplate, RenderedTemplate, TemplateDraft, TemplateImportReport};
 
 fn get_app_data_dir(app_handle: &AppHandle) -> Result<PathBuf, String> {
     app_handle
@@ -24,6 +47,31 @@ pub struct ConversationMessage {
     pub role: String, // "user" | "assistant" | "system"
     pub content: String,
     pub timestamp: String,
+    #[serde(default)]
+    pub id: Option<i64>,
+    #[serde(default)]
+    pub parent_id: Option<i64>,
+    // ids of every branch at this point in the tree (including this message), in creation order
+    #[serde(default)]
+    pub sibling_ids: Vec<i64>,
+    // model that produced an assistant message, when known
+    #[serde(default)]
+    pub model: Option<String>,
+    // "complete" | "streaming" | "cancelled" | "errored" | "truncated"
+    #[serde(default = "default_message_status")]
+    pub status: String,
+    // user feedback on the message, 1 thumbs up, -1 thumbs down, 0 unrated
+    #[serde(default)]
+    pub rating: i64,
+    // knowledge base excerpts the reply was given, by the marker it cites them with
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+fn default_message_status() -> String {
+    "complete".to_string()
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -35,6 +83,8 @@ pub struct Conversation {
     pub created_at: String,
     pub updated_at: String,
     pub token_count: i64,
+    #[serde(default)]
+    pub persona_id: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -47,12 +97,76 @@ pub struct ConversationPreview {
     pub updated_at: String,
     pub token_count: i64,
     pub message_count: i64,
+    pub pinned: bool,
+    pub archived: bool,
+    pub tags: Vec<String>,
+    pub folder_ids: Vec<i64>,
+    pub deleted_at: Option<String>,
+}
+
+// filters and paging options for get_conversations, every field is optional
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ConversationQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>,
+    pub sort_by: Option<String>, // "updated" | "created" | "title" | "token_count"
+    pub sort_order: Option<String>, // "asc" | "desc"
+    pub model: Option<String>,
+    pub updated_after: Option<String>,
+    pub updated_before: Option<String>,
+    pub tag: Option<String>,
+    pub folder_id: Option<i64>,
+    pub pinned: Option<bool>,
+    pub archived: Option<bool>, // archived conversations are left out unless this is set
+    pub trashed: Option<bool>, // list the trash instead of live conversations
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    pub color: Option<String>,
+    pub conversation_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Folder {
+    pub id: i64,
+    pub name: String,
+    pub parent_id: Option<i64>,
+    pub conversation_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConversationPage {
+    pub conversations: Vec<ConversationPreview>,
+    pub next_cursor: Option<String>,
+    pub total_count: i64,
+}
+
+// opaque position in a listing, the sort key of the last returned row plus its id as a tiebreaker
+#[derive(Serialize, Deserialize)]
+struct ConversationCursor {
+    key: Value,
+    id: String,
 }
 
 pub struct StreamState {
     pub cancellation_token: Option<CancellationToken>,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportFailure {
+    pub conversation_id: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportSummary {
+    pub exported: Vec<String>, // paths of the written files
+    pub failed: Vec<ExportFailure>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EditorPreferences {
     theme: String,
@@ -77,11 +191,192 @@ pub async fn stream_prompt(
     raw: Option<bool>,
     format: Option<String>,
     options: Option<HashMap<String, Value>>,
+    conversation_id: Option<String>,
+    knowledge: Option<bool>,
+    attachment_ids: Option<Vec<i64>>,
     state: tauri::State<'_, ConversationState>,
 ) -> Result<(), String> {
     println!("stream_prompt called with model: {} and prompt: {}", model, prompt);
     
     let context = state.context.lock().unwrap().clone();
+    let blob_dir = attachments::blob_dir(&get_app_data_dir(window.app_handle())?);
+    
+    // attachments staged for this prompt, a replayed conversation picks them up from its history once they
+    // are linked to the prompt, otherwise documents are inlined into the prompt here and images sent along
+    let attachment_ids = attachment_ids.unwrap_or_default();
+    let replays_history = conversation_id.is_some() && template.is_none() && !raw.unwrap_or(false);
+    
+    // a conversation's persona supplies its model, and the system prompt and options the request leaves out
+    let (persona, history_attachments) = match &conversation_id {
+        Some(id) => {
+            let conn = init_conversations_db(window.app_handle())
+                .map_err(|e| format!("Failed to open database: {}", e))?;
+            ensure_writable(&conn, id)?;
+            let persona = personas::for_conversation(&conn, id)
+                .map_err(|e| format!("Failed to load persona: {}", e))?;
+            let history_attachments: Vec<attachments::Attachment> = match replays_history {
+                true => attachments::load_for_conversation(&conn, id)
+                    .map_err(|e| format!("Failed to load attachments: {}", e))?
+                    .into_values()
+                    .flatten()
+                    .collect(),
+                false => Vec::new(),
+            };
+            (persona, history_attachments)
+        }
+        None => (None, Vec::new()),
+    };
+    let model = persona.as_ref().and_then(|p| p.model.clone()).unwrap_or(model);
+    let system = personas::system_prompt(persona.as_ref(), system);
+    let mut options = personas::options(persona.as_ref(), options);
+    let plan = plan_context_window(window.app_handle(), &model, &mut options).await?;
+    
+    let staged = if attachment_ids.is_empty() {
+        Vec::new()
+    } else {
+        let conn = init_conversations_db(window.app_handle())
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        attachments::load_staged(&conn, &attachment_ids)?
+    };
+    if plan.vision == Some(false) && staged.iter().any(|a| a.kind == attachments::KIND_IMAGE) {
+        return Err(no_vision_error(&model));
+    }
+    // images are converted off the async runtime, building the messages afterwards only hits the cache
+    let warm = history_attachments.into_iter().chain(staged.iter().cloned()).collect();
+    attachments::warm_images(blob_dir.clone(), warm, plan.image_max_dimension).await;
+    let staged_prompt = if replays_history || staged.is_empty() {
+        None
+    } else {
+        let conn = init_conversations_db(window.app_handle())
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        Some(attachments::chat_message(&conn, &blob_dir, plan.image_max_dimension, "user".to_string(), prompt.clone(), &staged)?)
+    };
+    let images = match images.filter(|images| !images.is_empty()) {
+        Some(_) if plan.vision == Some(false) => return Err(no_vision_error(&model)),
+        Some(images) => {
+            let max_dimension = plan.image_max_dimension;
+            let preprocessed = tauri::async_runtime::spawn_blocking(move || images
+                .iter()
+                .map(|image| vision::preprocess_base64(image, max_dimension))
+                .collect::<Result<Vec<_>, String>>())
+                .await
+                .map_err(|e| format!("Failed to preprocess images: {}", e))??;
+            Some(preprocessed)
+        }
+        None => None,
+    };
+    
+    // a failed lookup is reported with the citations, the prompt still goes out without excerpts
+    let (excerpts, retrieval_error) = match retrieve_knowledge(window.app_handle(), &prompt, knowledge).await {
+        Ok(excerpts) => (excerpts, None),
+        Err(e) => {
+            println!("Knowledge retrieval failed: {}", e);
+            (Vec::new(), Some(e))
+        }
+    };
+    let knowledge_context = if excerpts.is_empty() { None } else { Some(retrieval::context_block(&excerpts)) };
+    
+    // when the prompt belongs to a saved conversation, the reply is recorded as it streams
+    // so a cancel, an error or a window reload never loses what was already generated
+    let mut recorder = match &conversation_id {
+        Some(id) => {
+            let conn = init_conversations_db(window.app_handle())
+                .map_err(|e| format!("Failed to open database: {}", e))?;
+            Some(ResponseRecorder::start(conn, id, &prompt, &model)?)
+        }
+        None => None,
+    };
+    
+    // once the reply is recorded any failure before the request goes out marks it errored, so it isn't
+    // left streaming until the next start
+    let prepared = (|| -> Result<_, String> {
+        if let Some(recorder) = &recorder {
+            attachments::link(&recorder.conn, &attachment_ids, recorder.prompt_id)?;
+        }
+        
+        if !excerpts.is_empty() || retrieval_error.is_some() {
+            let citations = retrieval::citations(&excerpts);
+            if let Some(recorder) = &recorder {
+                recorder.set_citations(&citations)?;
+            }
+            window.emit("citations", CitationsEvent {
+                conversation_id: conversation_id.clone(),
+                message_id: recorder.as_ref().map(|r| r.message_id),
+                citations,
+                error: retrieval_error,
+            }).map_err(|e| format!("Failed to emit citations: {}", e))?;
+        }
+        
+        // saved conversations are replayed through /api/chat so their history can be trimmed to the window,
+        // a custom template or raw mode needs /api/generate and its opaque context instead
+        let conversation_history = match &recorder {
+            Some(recorder) if replays_history => {
+                let path: Vec<(i64, ChatMessage)> = read_conversation(&recorder.conn, &recorder.conversation_id)?
+                    .messages
+                    .into_iter()
+                    .filter(|m| m.id != Some(recorder.message_id))
+                    .filter_map(|m| Some((m.id?, m)))
+                    .map(|(id, m)| Ok((id, attachments::chat_message(
+                        &recorder.conn,
+                        &blob_dir,
+                        plan.image_max_dimension,
+                        m.role,
+                        m.content,
+                        &m.attachments,
+                    )?)))
+                    .collect::<Result<_, String>>()?;
+                let mut messages = summarized_history(&recorder.conn, &recorder.conversation_id, path, &plan)?;
+                check_images(&model, &plan, &mut messages)?;
+                if let Some(system) = &system {
+                    messages.insert(0, ChatMessage { role: "system".to_string(), content: system.clone(), images: None });
+                }
+                if let Some(knowledge_context) = &knowledge_context {
+                    retrieval::insert_context(&mut messages, knowledge_context);
+                }
+                if let (Some(last), Some(images)) = (messages.last_mut().filter(|m| m.role == "user"), &images) {
+                    last.images.get_or_insert_with(Vec::new).extend(images.iter().cloned());
+                }
+                Some(messages)
+            }
+            _ => None,
+        };
+        let (prompt, images) = match staged_prompt {
+            Some(staged) => {
+                let images = match staged.images {
+                    Some(staged_images) => Some(images.unwrap_or_default().into_iter().chain(staged_images).collect()),
+                    None => images,
+                };
+                (staged.content, images)
+            }
+            None => (prompt, images),
+        };
+        Ok((prompt, images, conversation_history))
+    })();
+    let prepared = match prepared {
+        Ok((prompt, images, Some(messages))) => fit_to_context_window(&window, &model, messages, &plan, &options)
+            .await
+            .map(|(history, usage)| (prompt, images, Some(history), usage)),
+        Ok((prompt, images, None)) => {
+            let count = |text: &str| tokenizer::count_tokens(Some(&model), text);
+            let prompt_tokens = context.as_ref().map(|c| c.len() as i64).unwrap_or(0)
+                + count(&prompt)
+                + knowledge_context.as_deref().map(count).unwrap_or(0)
+                + system.as_deref().map(count).unwrap_or(0);
+            let fitted = context_window::FittedHistory { messages: Vec::new(), dropped: Vec::new(), prompt_tokens };
+            let usage = context_window::usage(&fitted, plan.window, plan.reserved, &plan.strategy);
+            emit_context_usage(&window, &usage).map(|_| (prompt, images, None, usage))
+        }
+        Err(e) => Err(e),
+    };
+    let (prompt, images, history, usage) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            if let Some(recorder) = recorder.as_mut() {
+                recorder.finish("", "errored", None)?;
+            }
+            return Err(e);
+        }
+    };
     
     let cancellation_token = CancellationToken::new();
     
@@ -93,88 +388,469 @@ pub async fn stream_prompt(
     let service = OllamaService::new(None);
 
     println!("Starting stream generation...");
-    let response = service
-        .generate_stream(
-            &model,
-            &prompt,
-            context,
-            system.as_deref(),
-            template.as_deref(),
-            images,
-            raw,
-            format.as_deref(),
-            options,
-        )
-        .await?;
+    let request = match &history {
+        Some(messages) => service.chat_stream(&model, messages, format.as_deref(), options).await,
+        None => {
+            let prompt = match &knowledge_context {
+                Some(knowledge_context) => retrieval::augment_prompt(&prompt, knowledge_context),
+                None => prompt.clone(),
+            };
+            service
+                .generate_stream(
+                    &model,
+                    &prompt,
+                    context,
+                    system.as_deref(),
+                    template.as_deref(),
+                    images,
+                    raw,
+                    format.as_deref(),
+                    options,
+                )
+                .await
+        }
+    };
+    let result = match request {
+        Ok(response) => {
+            println!("Got response, starting to process stream...");
+            Ok(relay_stream(&window, &state, response, &cancellation_token, recorder.as_mut()).await)
+        }
+        Err(e) => Err(e),
+    };
+
+    // reset the cancellation token in stream state after processing
+    {
+        let mut stream_state = state.stream_state.lock().await;
+        stream_state.cancellation_token = None;
+    }
+
+    if let Some(recorder) = recorder.as_mut() {
+        match &result {
+            Ok(reply) => recorder.finish(&reply.text, reply.outcome.status(), reply.eval_count)?,
+            Err(_) => recorder.finish("", "errored", None)?,
+        }
+        if plan.summaries {
+            spawn_summary_refresh(window.app_handle().clone(), recorder.conversation_id.clone());
+        }
+        spawn_title_generation(window.app_handle().clone(), recorder.conversation_id.clone());
+    }
+
+    // sent once the reply is saved, a window that cannot take it only misses the updated count
+    if let Some(measured) = result.as_ref().ok().and_then(|reply| context_window::measured(&usage, reply.prompt_eval_count)) {
+        if let Err(e) = emit_context_usage(&window, &measured) {
+            println!("Failed to emit measured context usage: {}", e);
+        }
+    }
+
+    result.map(|_| ())
+}
+
+// persists an assistant reply while it streams, the message is created up front with
+// status "streaming" and its content is flushed periodically until the stream ends
+pub struct ResponseRecorder {
+    conn: Connection,
+    conversation_id: String,
+    prompt_id: i64,
+    message_id: i64,
+    last_flush: std::time::Instant,
+}
+
+impl ResponseRecorder {
+    const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(750);
+
+    // adds the prompt (unless it is already the latest message) and an empty assistant reply to the active branch
+    fn start(conn: Connection, conversation_id: &str, prompt: &str, model: &str) -> Result<Self, String> {
+        let now = Utc::now().to_rfc3339();
+        conn.execute(
+            "INSERT INTO conversations (id, title, model, created_at, updated_at) 
+             VALUES (?1, 'New Conversation', ?2, ?3, ?3)
+             ON CONFLICT(id) DO UPDATE SET
+                updated_at = excluded.updated_at,
+                model = CASE WHEN conversations.model = '' THEN excluded.model ELSE conversations.model END",
+            params![conversation_id, model, &now],
+        ).map_err(|e| format!("Failed to save conversation: {}", e))?;
+
+        let active_leaf_id: Option<i64> = conn.query_row(
+            "SELECT active_leaf_id FROM conversations WHERE id = ?1",
+            params![conversation_id],
+            |row| row.get(0),
+        ).map_err(|e| format!("Failed to load conversation: {}", e))?;
+        let tree = load_message_tree(&conn, conversation_id)
+            .map_err(|e| format!("Failed to query messages: {}", e))?;
+        let leaf = active_leaf_id
+            .filter(|id| tree.contains(*id))
+            .or_else(|| tree.default_leaf());
+        let path = leaf.map(|id| tree.path_to(id)).unwrap_or_default();
+
+        // the frontend may already have saved the prompt before streaming, reuse it in that case
+        let (prompt_id, reply_position) = match path.last() {
+            Some(node) if node.role == "user" && node.content == prompt => (node.id, path.len() as i64),
+            _ => {
+                let message = ConversationMessage {
+                    role: "user".to_string(),
+                    content: prompt.to_string(),
+                    timestamp: now.clone(),
+                    id: None,
+                    parent_id: leaf,
+                    sibling_ids: Vec::new(),
+                    model: None,
+                    status: default_message_status(),
+                    rating: 0,
+                    citations: Vec::new(),
+                    attachments: Vec::new(),
+                };
+                let id = insert_message(&conn, conversation_id, leaf, path.len() as i64, &message)
+                    .map_err(|e| format!("Failed to save message: {}", e))?;
+                (id, path.len() as i64 + 1)
+            }
+        };
+
+        let reply = ConversationMessage {
+            role: "assistant".to_string(),
+            content: String::new(),
+            timestamp: now,
+            id: None,
+            parent_id: Some(prompt_id),
+            sibling_ids: Vec::new(),
+            model: Some(model.to_string()),
+            status: "streaming".to_string(),
+            rating: 0,
+            citations: Vec::new(),
+            attachments: Vec::new(),
+        };
+        let message_id = insert_message(&conn, conversation_id, Some(prompt_id), reply_position, &reply)
+            .map_err(|e| format!("Failed to save message: {}", e))?;
+        set_active_leaf(&conn, conversation_id, Some(message_id))
+            .map_err(|e| format!("Failed to update active branch: {}", e))?;
+
+        Ok(Self {
+            conn,
+            conversation_id: conversation_id.to_string(),
+            prompt_id,
+            message_id,
+            last_flush: std::time::Instant::now(),
+        })
+    }
+
+    // writes the text generated so far, at most once per flush interval
+    fn progress(&mut self, content: &str) {
+        if self.last_flush.elapsed() < Self::FLUSH_INTERVAL {
+            return;
+        }
+        self.last_flush = std::time::Instant::now();
+        let result = seal_content(content).and_then(|sealed| self.conn.execute(
+            "UPDATE messages SET content = ?1 WHERE id = ?2",
+            params![sealed, self.message_id],
+        ));
+        if let Err(e) = result {
+            println!("Failed to persist partial response: {}", e);
+        }
+    }
+
+    fn set_citations(&self, citations: &[Citation]) -> Result<(), String> {
+        let json = serde_json::to_string(citations)
+            .map_err(|e| format!("Failed to serialize citations: {}", e))?;
+        self.conn.execute(
+            "UPDATE messages SET citations = ?1 WHERE id = ?2",
+            params![crypto::seal(&json)?, self.message_id],
+        ).map_err(|e| format!("Failed to save citations: {}", e))?;
+        Ok(())
+    }
+
+    fn finish(&mut self, content: &str, status: &str, token_count: Option<i64>) -> Result<(), String> {
+        self.conn.execute(
+            "UPDATE messages SET content = ?1, status = ?2, token_count = ?3 WHERE id = ?4",
+            params![crypto::seal(content)?, status, token_count, self.message_id],
+        ).map_err(|e| format!("Failed to save response: {}", e))?;
+        set_active_leaf(&self.conn, &self.conversation_id, Some(self.message_id))
+            .map_err(|e| format!("Failed to update active branch: {}", e))
+    }
+}
+
+// replies left in "streaming" by a previous run can never finish, mark them as cancelled
+pub fn recover_interrupted_streams(app_handle: &AppHandle) -> Result<(), String> {
+    let conn = init_conversations_db(app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let recovered = conn.execute(
+        "UPDATE messages SET status = 'cancelled' WHERE status = 'streaming'",
+        [],
+    ).map_err(|e| format!("Failed to recover interrupted responses: {}", e))?;
+    if recovered > 0 {
+        println!("Recovered {} interrupted responses", recovered);
+    }
+    Ok(())
+}
+
+// how a relayed stream finished
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamOutcome {
+    Completed,
+    Truncated, // the model stopped because it hit its token limit
+    Cancelled,
+    Failed(String),
+}
+
+impl StreamOutcome {
+    // the message status stored for a reply that ended this way
+    pub fn status(&self) -> &'static str {
+        match self {
+            StreamOutcome::Completed => "complete",
+            StreamOutcome::Truncated => "truncated",
+            StreamOutcome::Cancelled => "cancelled",
+            StreamOutcome::Failed(_) => "errored",
+        }
+    }
+}
+
+// the context window a request runs with and how its history is trimmed to fit, and what the model
+// is able to take in besides text
+struct ContextPlan {
+    window: i64,
+    reserved: i64,
+    strategy: TrimStrategy,
+    summaries: bool, // rolling summaries replace the turns they cover
+    vision: Option<bool>, // None when Ollama doesn't say
+    image_max_dimension: u32,
+}
+
+// works out the window from the request options, the modelfile and the model's trained length,
+// and pins num_ctx to it so Ollama runs with the same window the history was fitted to
+async fn plan_context_window(app_handle: &AppHandle, model: &str, options: &mut Option<HashMap<String, Value>>) -> Result<ContextPlan, String> {
+    let settings = settings::load_settings(&settings::open_preferences_db(&get_app_data_dir(app_handle)?)?)?;
+    tokenizer::load(model).await;
+    
+    // an unreachable /api/show only means the window isn't known, the request itself will report the error
+    let info = OllamaService::new(None).get_model_info(model).await.ok();
+    let model_length = info.as_ref()
+        .and_then(|i| i.model_info.as_ref())
+        .and_then(context_window::model_context_length);
+    let modelfile_num_ctx = context_window::modelfile_num_ctx(info.as_ref().and_then(|i| i.parameters.as_deref()));
+    
+    let window = context_window::effective_window(options.as_ref(), modelfile_num_ctx, model_length, &settings);
+    options.get_or_insert_with(HashMap::new)
+        .entry("num_ctx".to_string())
+        .or_insert_with(|| Value::from(window));
+    
+    Ok(ContextPlan {
+        window,
+        reserved: context_window::reserved_tokens(window, &settings),
+        strategy: TrimStrategy::from_settings(&settings),
+        summaries: settings.summary_enabled,
+        vision: info.as_ref().and_then(vision::supports_vision),
+        image_max_dimension: settings.image_max_dimension.clamp(0, u32::MAX as i64) as u32,
+    })
+}
+
+fn no_vision_error(model: &str) -> String {
+    format!("{} can't read images, switch to a vision model to send them", model)
+}
+
+// a text-only model ignores images without a word, so images in the prompt itself are an error while
+// images further back in the conversation are left out so it can carry on
+fn check_images(model: &str, plan: &ContextPlan, messages: &mut [ChatMessage]) -> Result<(), String> {
+    if plan.vision != Some(false) {
+        return Ok(());
+    }
+    if messages.iter().rev().find(|m| m.role == "user").is_some_and(|m| m.images.is_some()) {
+        return Err(no_vision_error(model));
+    }
+    for message in messages.iter_mut() {
+        message.images = None;
+    }
+    Ok(())
+}
+
+// trims the history to the window with the configured strategy and tells the frontend how full it is
+async fn fit_to_context_window(
+    window: &Window,
+    model: &str,
+    messages: Vec<ChatMessage>,
+    plan: &ContextPlan,
+    options: &Option<HashMap<String, Value>>,
+) -> Result<(Vec<ChatMessage>, ContextUsage), String> {
+    let count = |text: &str| tokenizer::count_tokens(Some(model), text);
+    let budget = plan.window - plan.reserved;
+    // the summarize strategy keeps room for the summary that replaces the dropped turns
+    let summary_budget = match plan.strategy {
+        TrimStrategy::Summarize => (budget / 4).min(1024),
+        _ => 0,
+    };
+    
+    let mut fitted = context_window::fit_history(messages, budget - summary_budget, &plan.strategy, count);
+    
+    if plan.strategy == TrimStrategy::Summarize && !fitted.dropped.is_empty() {
+        // only the most recent dropped turns are summarized when all of them wouldn't fit the summarizer's window
+        let source = context_window::fit_history(
+            fitted.dropped.clone(),
+            plan.window - summary_budget - 256,
+            &TrimStrategy::DropOldest,
+            count,
+        ).messages;
+        let mut summary_options = options.clone().unwrap_or_default();
+        summary_options.insert("num_predict".to_string(), Value::from(summary_budget));
+        
+        match OllamaService::new(None).chat(model, &summary::rolling_summary_request(None, &source), Some(summary_options)).await {
+            Ok(summary) => {
+                fitted.prompt_tokens += context_window::message_tokens(
+                    &ChatMessage { role: "system".to_string(), content: summary.clone(), images: None },
+                    &count,
+                );
+                context_window::insert_summary(&mut fitted.messages, &summary);
+            }
+            Err(e) => println!("Failed to summarize trimmed messages, dropping them instead: {}", e),
+        }
+    }
+    
+    if !fitted.dropped.is_empty() {
+        println!("Trimmed {} messages to fit a {} token window", fitted.dropped.len(), plan.window);
+    }
+    let usage = context_window::usage(&fitted, plan.window, plan.reserved, &plan.strategy);
+    emit_context_usage(window, &usage)?;
+    Ok((fitted.messages, usage))
+}
+
+// swaps the turns covered by the branch's latest rolling summary for the summary itself
+fn summarized_history(
+    conn: &Connection,
+    conversation_id: &str,
+    path: Vec<(i64, ChatMessage)>,
+    plan: &ContextPlan,
+) -> Result<Vec<ChatMessage>, String> {
+    let ids: Vec<i64> = path.iter().map(|(id, _)| *id).collect();
+    let latest = if plan.summaries {
+        let summaries = summary::load_summaries(conn, conversation_id)
+            .map_err(|e| format!("Failed to load summaries: {}", e))?;
+        summary::latest_on_path(summaries, &ids).filter(|(_, s)| !s.stale)
+    } else {
+        None
+    };
+    
+    let mut messages: Vec<ChatMessage> = path.into_iter().map(|(_, message)| message).collect();
+    if let Some((index, latest)) = latest {
+        let system: Vec<ChatMessage> = messages[..=index].iter().filter(|m| m.role == "system").cloned().collect();
+        messages = system.into_iter().chain(messages.drain(index + 1..)).collect();
+        context_window::insert_summary(&mut messages, &latest.content);
+    }
+    Ok(messages)
+}
+
+fn emit_context_usage(window: &Window, usage: &ContextUsage) -> Result<(), String> {
+    window.emit("context-window", usage).map_err(|e| e.to_string())
+}
+
+// what a relayed stream produced
+pub struct StreamReply {
+    pub text: String,
+    pub outcome: StreamOutcome,
+    pub eval_count: Option<i64>, // tokens in the reply as counted by the model, sent with the final line
+    pub prompt_eval_count: Option<i64>, // prompt tokens the model read, also sent with the final line
+}
+
+// sends a stream event to the window, logging instead of failing when it is closed or reloading
+fn emit_stream_event<S: Serialize + Clone>(window: &Window, event: &str, payload: S) {
+    if let Err(e) = window.emit(event, payload) {
+        println!("Failed to emit {}: {}", event, e);
+    }
+}
 
-    println!("Got response, starting to process stream...");
+// forwards tokens from an Ollama streaming response to the frontend until it is done, cancelled or fails
+// handles both /api/generate lines ("response") and /api/chat lines ("message.content")
+// returns the full generated text together with how the stream ended
+// a window that goes away mid-stream does not stop it, so the recorder still saves the whole reply
+async fn relay_stream(
+    window: &Window,
+    state: &ConversationState,
+    response: reqwest::Response,
+    cancellation_token: &CancellationToken,
+    mut recorder: Option<&mut ResponseRecorder>,
+) -> StreamReply {
     let mut stream = response.bytes_stream();
+    let mut generated = String::new();
+    let mut eval_count = None;
+    let mut prompt_eval_count = None;
+    let mut pending: Vec<u8> = Vec::new(); // partial line carried over between chunks
 
-    loop {
+    let outcome = 'stream: loop {
         tokio::select! {
             _ = cancellation_token.cancelled() => {
                 println!("Stream was cancelled");
-                window.emit("ollama-cancelled", "Stream cancelled by user").map_err(|e| e.to_string())?;
-                break;
+                emit_stream_event(window, "ollama-cancelled", "Stream cancelled by user");
+                break 'stream StreamOutcome::Cancelled;
             }
             // process the stream items as long as we are not cancelled
             item = stream.next() => {
                 match item {
                     Some(Ok(chunk)) => {
-                        if let Ok(text) = std::str::from_utf8(&chunk) {
-                            for line in text.lines() {
-                                let line = line.trim();
-                                if !line.is_empty() {
-                                    if let Ok(v) = serde_json::from_str::<Value>(line) {
-                                        // if the line is valid response token, emit the token to the frontend
-                                        if let Some(token) = v.get("response").and_then(|r| r.as_str()) {
-                                            println!("Emitting token: {}", token);
-                                            window.emit("ollama-token", token).map_err(|e| e.to_string())?;
-                                        }
-                                        if let Some(new_context) = v.get("context").and_then(|c| c.as_array()) {
-                                            let context_bytes: Vec<u8> = new_context
-                                                .iter()
-                                                .filter_map(|x| x.as_u64().map(|n| n as u8))
-                                                .collect();
-                                            *state.context.lock().unwrap() = Some(context_bytes);
-                                        }
-                                        // check if the stream is done, and end the stream if so
-                                        if let Some(done) = v.get("done").and_then(|d| d.as_bool()) {
-                                            if done {
-                                                println!("Streaming completed");
-                                                window.emit("ollama-complete", "Stream completed").map_err(|e| e.to_string())?;
-                                                break;
-                                            }
-                                        }
-                                    } else {
-                                        println!("Failed to parse JSON line: {}", line);
-                                    }
+                        pending.extend_from_slice(&chunk);
+                        while let Some(newline) = pending.iter().position(|b| *b == b'\n') {
+                            let raw_line: Vec<u8> = pending.drain(..=newline).collect();
+                            let Ok(line) = std::str::from_utf8(&raw_line) else { continue };
+                            let line = line.trim();
+                            if line.is_empty() {
+                                continue;
+                            }
+                            let Ok(v) = serde_json::from_str::<Value>(line) else {
+                                println!("Failed to parse JSON line: {}", line);
+                                continue;
+                            };
+                            // if the line is valid response token, emit the token to the frontend
+                            let token = v.get("response")
+                                .or_else(|| v.get("message").and_then(|m| m.get("content")))
+                                .and_then(|r| r.as_str());
+                            if let Some(token) = token {
+                                generated.push_str(token);
+                                emit_stream_event(window, "ollama-token", token);
+                                if let Some(recorder) = recorder.as_deref_mut() {
+                                    recorder.progress(&generated);
+                                }
+                            }
+                            if let Some(new_context) = v.get("context").and_then(|c| c.as_array()) {
+                                let context_bytes: Vec<u8> = new_context
+                                    .iter()
+                                    .filter_map(|x| x.as_u64().map(|n| n as u8))
+                                    .collect();
+                                *state.context.lock().unwrap() = Some(context_bytes);
+                            }
+                            if let Some(error) = v.get("error").and_then(|e| e.as_str()) {
+                                let error_msg = format!("Ollama error: {}", error);
+                                println!("{}", error_msg);
+                                emit_stream_event(window, "ollama-error", &error_msg);
+                                break 'stream StreamOutcome::Failed(error_msg);
+                            }
+                            // check if the stream is done, and end the stream if so
+                            if v.get("done").and_then(|d| d.as_bool()) == Some(true) {
+                                println!("Streaming completed");
+                                eval_count = v.get("eval_count").and_then(|c| c.as_i64());
+                                prompt_eval_count = v.get("prompt_eval_count").and_then(|c| c.as_i64());
+                                emit_stream_event(window, "ollama-complete", "Stream completed");
+                                if v.get("done_reason").and_then(|r| r.as_str()) == Some("length") {
+                                    break 'stream StreamOutcome::Truncated;
                                 }
+                                break 'stream StreamOutcome::Completed;
                             }
                         }
                     }
                     Some(Err(e)) => {
                         let error_msg = format!("Stream error: {}", e);
                         println!("{}", error_msg);
-                        window.emit("ollama-error", &error_msg).map_err(|e| e.to_string())?;
-                        break;
+                        emit_stream_event(window, "ollama-error", &error_msg);
+                        break 'stream StreamOutcome::Failed(error_msg);
                     }
                     None => {
                         println!("Stream ended");
-                        break;
+                        break 'stream StreamOutcome::Completed;
                     }
                 }
             }
         }
-    }
+    };
 
-    // reset the cancellation token in stream state after processing
-    {
-        let mut stream_state = state.stream_state.lock().await;
-        stream_state.cancellation_token = None;
+    StreamReply {
+        text: generated,
+        outcome,
+        eval_count,
+        prompt_eval_count,
     }
-
-    Ok(())
 }
 
 // command to abort an ongoing stream
@@ -314,6 +990,7 @@ fn init_conversations_db(app_handle: &AppHandle) -> SqlResult<Connection> {
         ))?;
     let db_path = app_data_dir.join("conversations.db");
     let conn = Connection::open(db_path)?;
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
     
     conn.execute(
         "CREATE TABLE IF NOT EXISTS conversations (
@@ -345,191 +1022,867 @@ fn init_conversations_db(app_handle: &AppHandle) -> SqlResult<Connection> {
          ON messages (conversation_id, position)",
         [],
     )?;
+
+    // denormalized listing columns so get_conversations doesn't need to touch the messages table
+    if ensure_column(&conn, "conversations", "message_count", "INTEGER NOT NULL DEFAULT 0")? {
+        conn.execute(
+            "UPDATE conversations SET message_count = 
+             (SELECT COUNT(*) FROM messages WHERE conversation_id = conversations.id)",
+            [],
+        )?;
+    }
+    if ensure_column(&conn, "conversations", "preview", "TEXT NOT NULL DEFAULT ''")? {
+        conn.execute(
+            "UPDATE conversations SET preview = COALESCE(
+             (SELECT substr(content, 1, 100) FROM messages 
+              WHERE conversation_id = conversations.id 
+              ORDER BY position DESC LIMIT 1), '')",
+            [],
+        )?;
+    }
+
+    // exact reply lengths reported by Ollama, other messages are counted with the model's tokenizer
+    ensure_column(&conn, "messages", "token_count", "INTEGER")?;
+    ensure_column(&conn, "messages", "citations", "TEXT")?;
+
+    // messages form a tree so edits and regenerations can branch, existing flat histories become a single chain
+    if ensure_column(&conn, "messages", "parent_id", "INTEGER")? {
+        conn.execute(
+            "UPDATE messages SET parent_id = 
+             (SELECT prev.id FROM messages prev 
+              WHERE prev.conversation_id = messages.conversation_id AND prev.position < messages.position 
+              ORDER BY prev.position DESC LIMIT 1)",
+            [],
+        )?;
+    }
+    ensure_column(&conn, "conversations", "active_leaf_id", "INTEGER")?;
+    ensure_column(&conn, "messages", "model", "TEXT")?;
+    ensure_column(&conn, "messages", "status", "TEXT NOT NULL DEFAULT 'complete'")?;
+    ensure_column(&conn, "messages", "rating", "INTEGER NOT NULL DEFAULT 0")?;
+
+    ensure_column(&conn, "conversations", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(&conn, "conversations", "archived", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(&conn, "conversations", "deleted_at", "TEXT")?;
+    // generated, imported and user titles are kept when the frontend saves its own title, titles from
+    // before the column existed may have been chosen by hand, so they are kept as well
+    if ensure_column(&conn, "conversations", "title_source", "TEXT NOT NULL DEFAULT 'default'")? {
+        conn.execute(
+            "UPDATE conversations SET title_source = ?1 WHERE title <> 'New Conversation'",
+            params![titles::SOURCE_USER],
+        )?;
+    }
+
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tags (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            color TEXT,
+            created_at TEXT NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS conversation_tags (
+            conversation_id TEXT NOT NULL,
+            tag_id INTEGER NOT NULL,
+            PRIMARY KEY (conversation_id, tag_id),
+            FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE,
+            FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE
+         );
+         CREATE TABLE IF NOT EXISTS folders (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            parent_id INTEGER,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (parent_id) REFERENCES folders(id) ON DELETE CASCADE
+         );
+         CREATE TABLE IF NOT EXISTS conversation_folders (
+            conversation_id TEXT NOT NULL,
+            folder_id INTEGER NOT NULL,
+            PRIMARY KEY (conversation_id, folder_id),
+            FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE,
+            FOREIGN KEY (folder_id) REFERENCES folders(id) ON DELETE CASCADE
+         );
+         CREATE INDEX IF NOT EXISTS idx_conversation_tags_tag ON conversation_tags (tag_id);
+         CREATE INDEX IF NOT EXISTS idx_conversation_folders_folder ON conversation_folders (folder_id);
+         CREATE INDEX IF NOT EXISTS idx_conversations_flags ON conversations (archived, pinned, updated_at);
+         CREATE INDEX IF NOT EXISTS idx_conversations_deleted ON conversations (deleted_at);"
+    )?;
+
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_messages_parent ON messages (parent_id);
+         CREATE INDEX IF NOT EXISTS idx_conversations_updated ON conversations (updated_at, id);
+         CREATE INDEX IF NOT EXISTS idx_conversations_created ON conversations (created_at, id);
+         CREATE INDEX IF NOT EXISTS idx_conversations_title ON conversations (title COLLATE NOCASE, id);
+         CREATE INDEX IF NOT EXISTS idx_conversations_tokens ON conversations (token_count, id);
+         CREATE INDEX IF NOT EXISTS idx_conversations_model ON conversations (model, updated_at);"
+    )?;
+    
+    crypto::create_table(&conn)?;
+    summary::create_table(&conn)?;
+    semantic_search::create_tables(&conn)?;
+    attachments::create_table(&conn)?;
+    templates::create_table(&conn)?;
+    personas::create_table(&conn)?;
     
     Ok(conn)
 }
 
-fn estimate_token_count(text: &str) -> i64 {
-    (text.len() as f64 / 4.0).ceil() as i64
-}
-
-fn generate_title(first_message: &str) -> String {
-    let max_length = 50;
-    let cleaned = first_message.trim();
-    
-    if cleaned.len() <= max_length {
-        cleaned.to_string()
+fn truncate_preview(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
     } else {
-        format!("{}...", &cleaned[..max_length])
+        format!("{}...", text.chars().take(max_chars).collect::<String>())
     }
 }
 
+// saves the active branch of a conversation
+// messages are matched against the existing tree by id, or by role and content when no id is sent,
+// anything that doesn't match is added as a new branch so earlier branches are never lost
 #[tauri::command]
 pub async fn save_conversation(app_handle: AppHandle, conversation: Conversation) -> Result<String, String> {
     println!("save_conversation called for conversation: {}", conversation.id);
     
     let conn = init_conversations_db(&app_handle)
         .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation.id)?;
     
     let tx = conn.unchecked_transaction()
         .map_err(|e| format!("Failed to start transaction: {}", e))?;
     
-    let total_tokens: i64 = conversation.messages.iter()
-        .map(|msg| estimate_token_count(&msg.content))
-        .sum();
-    
     tx.execute(
-        "INSERT OR REPLACE INTO conversations (id, title, model, created_at, updated_at, token_count) 
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        "INSERT INTO conversations (id, title, model, created_at, updated_at) 
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(id) DO UPDATE SET 
+            title = CASE WHEN conversations.title_source IN ('generated', 'imported', 'user') 
+                         THEN conversations.title ELSE excluded.title END, 
+            model = excluded.model, 
+            updated_at = excluded.updated_at",
         params![
             &conversation.id,
             &conversation.title,
             &conversation.model,
             &conversation.created_at,
-            &conversation.updated_at,
-            total_tokens
+            &conversation.updated_at
         ],
     ).map_err(|e| format!("Failed to save conversation: {}", e))?;
     
-    tx.execute(
-        "DELETE FROM messages WHERE conversation_id = ?1",
-        params![&conversation.id],
-    ).map_err(|e| format!("Failed to delete old messages: {}", e))?;
+    let tree = load_message_tree(&tx, &conversation.id)
+        .map_err(|e| format!("Failed to load messages: {}", e))?;
     
+    let mut parent_id: Option<i64> = None;
     for (position, message) in conversation.messages.iter().enumerate() {
-        tx.execute(
-            "INSERT INTO messages (conversation_id, role, content, timestamp, position) 
-             VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![
-                &conversation.id,
-                &message.role,
-                &message.content,
-                &message.timestamp,
-                position as i64
-            ],
-        ).map_err(|e| format!("Failed to save message: {}", e))?;
+        let node = message.id
+            .filter(|id| tree.contains(*id))
+            .and_then(|id| tree.path_to(id).last().copied());
+        let existing = match node {
+            Some(node) if node.parent_id == parent_id && node.role == message.role && node.content == message.content => Some(node.id),
+            _ => tree.find_child(parent_id, &message.role, &message.content),
+        };
+        
+        // stored messages are never rewritten, an edited one is added next to it as a new branch
+        parent_id = Some(match (existing, node) {
+            (Some(id), _) => id,
+            (None, Some(node)) if node.parent_id == parent_id && node.role == message.role => {
+                add_message_branch(&tx, &conversation.id, node.id, &message.content, message.model.clone(), &message.status)?
+            }
+            (None, _) => insert_message(&tx, &conversation.id, parent_id, position as i64, message)
+                .map_err(|e| format!("Failed to save message: {}", e))?,
+        });
     }
     
+    set_active_leaf(&tx, &conversation.id, parent_id)
+        .map_err(|e| format!("Failed to update active branch: {}", e))?;
+    
     tx.commit()
         .map_err(|e| format!("Failed to commit transaction: {}", e))?;
     
     Ok(conversation.id)
 }
 
-
-#[tauri::command]
-pub async fn get_conversations(app_handle: AppHandle) -> Result<Vec<ConversationPreview>, String> {
-    println!("get_conversations called");
-    
-    let conn = init_conversations_db(&app_handle)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
-    
+fn load_message_tree(conn: &Connection, conversation_id: &str) -> SqlResult<MessageTree> {
     let mut stmt = conn.prepare(
-        "SELECT c.id, c.title, c.model, c.created_at, c.updated_at, c.token_count,
-                COUNT(m.id) as message_count,
-                (SELECT content FROM messages 
-                 WHERE conversation_id = c.id 
-                 ORDER BY position DESC LIMIT 1) as last_message
-         FROM conversations c
-         LEFT JOIN messages m ON c.id = m.conversation_id
-         GROUP BY c.id
-         ORDER BY c.updated_at DESC"
-    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        "SELECT id, parent_id, role, content, timestamp, model, status, rating, token_count, citations 
+         FROM messages 
+         WHERE conversation_id = ?1"
+    )?;
     
-    let conversations = stmt.query_map([], |row| {
-        let last_message: Option<String> = row.get(7)?;
-        let preview = last_message.unwrap_or_else(|| "Empty conversation".to_string());
-        
-        Ok(ConversationPreview {
-            id: row.get(0)?,
-            title: row.get(1)?,
-            model: row.get(2)?,
-            created_at: row.get(3)?,
-            updated_at: row.get(4)?,
-            token_count: row.get(5)?,
-            message_count: row.get(6)?,
-            preview: if preview.len() > 100 {
-                format!("{}...", &preview[..100])
-            } else {
-                preview
-            },
+    let mut by_message = attachments::load_for_conversation(conn, conversation_id)?;
+    let nodes = stmt.query_map(params![conversation_id], |row| {
+        let id: i64 = row.get(0)?;
+        Ok(MessageNode {
+            id,
+            parent_id: row.get(1)?,
+            role: row.get(2)?,
+            content: open_content(row, 3)?,
+            timestamp: row.get(4)?,
+            model: row.get(5)?,
+            status: row.get(6)?,
+            rating: row.get(7)?,
+            token_count: row.get(8)?,
+            citations: open_citations(row, 9)?,
+            attachments: by_message.remove(&id).unwrap_or_default(),
         })
-    }).map_err(|e| format!("Failed to query conversations: {}", e))?;
-    
-    let mut result = Vec::new();
-    for conv in conversations {
-        result.push(conv.map_err(|e| format!("Failed to map conversation: {}", e))?);
-    }
+    })?.collect::<SqlResult<Vec<_>>>()?;
     
-    Ok(result)
+    Ok(MessageTree::new(nodes))
 }
 
-#[tauri::command]
-pub async fn load_conversation(app_handle: AppHandle, conversation_id: String) -> Result<Conversation, String> {
-    println!("load_conversation called for id: {}", conversation_id);
+// content columns go through the crypto layer, its errors surface as SQL errors so the helpers keep their signatures
+fn seal_content(content: &str) -> SqlResult<String> {
+    crypto::seal(content).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))
+}
+
+fn open_content(row: &rusqlite::Row, column: usize) -> SqlResult<String> {
+    crypto::open(&row.get::<_, String>(column)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into()))
+}
+
+fn open_citations(row: &rusqlite::Row, column: usize) -> SqlResult<Vec<Citation>> {
+    let Some(stored) = row.get::<_, Option<String>>(column)? else {
+        return Ok(Vec::new());
+    };
+    crypto::open(&stored)
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, e.into()))
+}
+
+fn insert_message(
+    conn: &Connection,
+    conversation_id: &str,
+    parent_id: Option<i64>,
+    position: i64,
+    message: &ConversationMessage,
+) -> SqlResult<i64> {
+    conn.execute(
+        "INSERT INTO messages (conversation_id, parent_id, role, content, timestamp, position, model, status, rating) 
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            conversation_id,
+            parent_id,
+            &message.role,
+            seal_content(&message.content)?,
+            &message.timestamp,
+            position,
+            &message.model,
+            &message.status,
+            message.rating
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+// points the conversation at a new active branch and refreshes the listing columns from that branch
+fn set_active_leaf(conn: &Connection, conversation_id: &str, leaf_id: Option<i64>) -> SqlResult<()> {
+    let tree = load_message_tree(conn, conversation_id)?;
+    let path = leaf_id.map(|leaf| tree.path_to(leaf)).unwrap_or_default();
     
-    let conn = init_conversations_db(&app_handle)
-        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let model: String = conn.query_row(
+        "SELECT model FROM conversations WHERE id = ?1",
+        params![conversation_id],
+        |row| row.get(0),
+    )?;
+    let total_tokens: i64 = path.iter()
+        .map(|node| node.token_count.unwrap_or_else(|| {
+            tokenizer::count_tokens(Some(node.model.as_deref().unwrap_or(&model)), &node.content)
+        }))
+        .sum();
+    let preview = path.last()
+        .map(|node| truncate_preview(&node.content, 100))
+        .unwrap_or_default();
     
-    let conv = conn.query_row(
-        "SELECT id, title, model, created_at, updated_at, token_count 
+    conn.execute(
+        "UPDATE conversations 
+         SET active_leaf_id = ?1, token_count = ?2, message_count = ?3, preview = ?4 
+         WHERE id = ?5",
+        params![leaf_id, total_tokens, path.len() as i64, seal_content(&preview)?, conversation_id],
+    )?;
+    Ok(())
+}
+
+// trashed conversations stay read only until they are restored, unknown ids are new conversations
+fn ensure_writable(conn: &Connection, conversation_id: &str) -> Result<(), String> {
+    let deleted_at: Option<Option<String>> = conn.query_row(
+        "SELECT deleted_at FROM conversations WHERE id = ?1",
+        params![conversation_id],
+        |row| row.get(0),
+    ).optional().map_err(|e| format!("Failed to load conversation: {}", e))?;
+    if let Some(Some(_)) = deleted_at {
+        return Err(format!("Conversation {} is in the trash, restore it to make changes", conversation_id));
+    }
+    Ok(())
+}
+
+// same check for the conversation a message belongs to
+fn ensure_message_writable(conn: &Connection, message_id: i64) -> Result<(), String> {
+    let conversation_id: Option<String> = conn.query_row(
+        "SELECT conversation_id FROM messages WHERE id = ?1",
+        params![message_id],
+        |row| row.get(0),
+    ).optional().map_err(|e| format!("Failed to load message: {}", e))?;
+    match conversation_id {
+        Some(conversation_id) => ensure_writable(conn, &conversation_id),
+        None => Err(format!("Message {} not found", message_id)),
+    }
+}
+
+// reads a conversation with the messages along its active branch
+fn read_conversation(conn: &Connection, conversation_id: &str) -> Result<Conversation, String> {
+    let (mut conv, active_leaf_id) = conn.query_row(
+        "SELECT id, title, model, created_at, updated_at, token_count, active_leaf_id, persona_id
          FROM conversations WHERE id = ?1",
-        params![&conversation_id],
+        params![conversation_id],
         |row| {
-            Ok(Conversation {
+            Ok((Conversation {
                 id: row.get(0)?,
                 title: row.get(1)?,
                 model: row.get(2)?,
                 created_at: row.get(3)?,
                 updated_at: row.get(4)?,
                 token_count: row.get(5)?,
+                persona_id: row.get(7)?,
                 messages: Vec::new(),
-            })
+            }, row.get::<_, Option<i64>>(6)?))
         }
     ).map_err(|e| format!("Failed to load conversation: {}", e))?;
     
-    let mut stmt = conn.prepare(
-        "SELECT role, content, timestamp 
-         FROM messages 
-         WHERE conversation_id = ?1 
-         ORDER BY position ASC"
-    ).map_err(|e| format!("Failed to prepare messages query: {}", e))?;
-    
-    let messages = stmt.query_map(params![&conversation_id], |row| {
-        Ok(ConversationMessage {
-            role: row.get(0)?,
-            content: row.get(1)?,
-            timestamp: row.get(2)?,
-        })
-    }).map_err(|e| format!("Failed to query messages: {}", e))?;
+    let tree = load_message_tree(conn, conversation_id)
+        .map_err(|e| format!("Failed to query messages: {}", e))?;
     
-    let mut conv = conv;
-    for msg in messages {
-        conv.messages.push(msg.map_err(|e| format!("Failed to map message: {}", e))?);
+    let leaf = active_leaf_id
+        .filter(|id| tree.contains(*id))
+        .or_else(|| tree.default_leaf());
+    
+    if let Some(leaf) = leaf {
+        conv.messages = tree.path_to(leaf)
+            .into_iter()
+            .map(|node| ConversationMessage {
+                role: node.role.clone(),
+                content: node.content.clone(),
+                timestamp: node.timestamp.clone(),
+                id: Some(node.id),
+                parent_id: node.parent_id,
+                sibling_ids: tree.siblings(node.id),
+                model: node.model.clone(),
+                status: node.status.clone(),
+                rating: node.rating,
+                citations: node.citations.clone(),
+                attachments: node.attachments.clone(),
+            })
+            .collect();
     }
     
     Ok(conv)
 }
 
+// lists conversations one page at a time using keyset pagination on (sort column, id)
+// pass the returned next_cursor back in the query to fetch the following page
 #[tauri::command]
-pub async fn delete_conversation(app_handle: AppHandle, conversation_id: String) -> Result<(), String> {
-    println!("delete_conversation called for id: {}", conversation_id);
+pub async fn get_conversations(app_handle: AppHandle, query: Option<ConversationQuery>) -> Result<ConversationPage, String> {
+    println!("get_conversations called");
     
+    let query = query.unwrap_or_default();
     let conn = init_conversations_db(&app_handle)
         .map_err(|e| format!("Failed to open database: {}", e))?;
-    
-    conn.execute(
-        "DELETE FROM conversations WHERE id = ?1",
-        params![&conversation_id],
-    ).map_err(|e| format!("Failed to delete conversation: {}", e))?;
-    
-    Ok(())
-}
 
-#[tauri::command]
-pub async fn create_new_conversation(model: String) -> Result<Conversation, String> {
-    let timestamp = Utc::now().timestamp_millis();
-    let random: u32 = rand::thread_rng().gen();
+    let (sort_column, numeric_key) = match query.sort_by.as_deref().unwrap_or("updated") {
+        "updated" => ("updated_at", false),
+        "created" => ("created_at", false),
+        "title" => ("title COLLATE NOCASE", false),
+        "token_count" => ("token_count", true),
+        other => return Err(format!("Unknown sort field: {}", other)),
+    };
+    let descending = match query.sort_order.as_deref().unwrap_or("desc") {
+        "desc" => true,
+        "asc" => false,
+        other => return Err(format!("Unknown sort order: {}", other)),
+    };
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    let mut conditions: Vec<String> = Vec::new();
+    let mut filter_params: Vec<rusqlite::types::Value> = Vec::new();
+
+    if let Some(model) = &query.model {
+        conditions.push("model = ?".to_string());
+        filter_params.push(model.clone().into());
+    }
+    if let Some(after) = &query.updated_after {
+        conditions.push("updated_at >= ?".to_string());
+        filter_params.push(after.clone().into());
+    }
+    if let Some(before) = &query.updated_before {
+        conditions.push("updated_at < ?".to_string());
+        filter_params.push(before.clone().into());
+    }
+    if let Some(tag) = &query.tag {
+        conditions.push(
+            "id IN (SELECT ct.conversation_id FROM conversation_tags ct 
+                    JOIN tags t ON t.id = ct.tag_id WHERE t.name = ?)".to_string()
+        );
+        filter_params.push(tag.clone().into());
+    }
+    if let Some(folder_id) = query.folder_id {
+        conditions.push("id IN (SELECT conversation_id FROM conversation_folders WHERE folder_id = ?)".to_string());
+        filter_params.push(folder_id.into());
+    }
+    if let Some(pinned) = query.pinned {
+        conditions.push("pinned = ?".to_string());
+        filter_params.push((pinned as i64).into());
+    }
+    if query.trashed.unwrap_or(false) {
+        conditions.push("deleted_at IS NOT NULL".to_string());
+    } else {
+        conditions.push("deleted_at IS NULL".to_string());
+        conditions.push("archived = ?".to_string());
+        filter_params.push((query.archived.unwrap_or(false) as i64).into());
+    }
+
+    let filter_clause = format!("WHERE {}", conditions.join(" AND "));
+
+    let total_count: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM conversations {}", filter_clause),
+        rusqlite::params_from_iter(filter_params.iter()),
+        |row| row.get(0),
+    ).map_err(|e| format!("Failed to count conversations: {}", e))?;
+
+    let mut page_conditions = conditions.clone();
+    let mut page_params = filter_params.clone();
+
+    if let Some(cursor) = &query.cursor {
+        let cursor: ConversationCursor = serde_json::from_str(cursor)
+            .map_err(|e| format!("Invalid cursor: {}", e))?;
+        let key: rusqlite::types::Value = if numeric_key {
+            cursor.key.as_i64().ok_or("Invalid cursor: expected numeric key")?.into()
+        } else {
+            cursor.key.as_str().ok_or("Invalid cursor: expected text key")?.to_string().into()
+        };
+        page_conditions.push(format!(
+            "({}, id) {} (?, ?)",
+            sort_column,
+            if descending { "<" } else { ">" }
+        ));
+        page_params.push(key);
+        page_params.push(cursor.id.into());
+    }
+
+    let page_clause = format!("WHERE {}", page_conditions.join(" AND "));
+    let direction = if descending { "DESC" } else { "ASC" };
+
+    // fetch one extra row to know whether another page exists
+    page_params.push((limit + 1).into());
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, title, model, created_at, updated_at, token_count, message_count, preview, pinned, archived, deleted_at
+         FROM conversations
+         {}
+         ORDER BY {} {}, id {}
+         LIMIT ?",
+        page_clause, sort_column, direction, direction
+    )).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    
+    let conversations = stmt.query_map(rusqlite::params_from_iter(page_params.iter()), |row| {
+        let preview: String = row.get(7)?;
+        // the sidebar still lists a locked database, only the previews stay hidden
+        let preview = crypto::open(&preview).unwrap_or_else(|_| "Encrypted conversation".to_string());
+        
+        Ok(ConversationPreview {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            model: row.get(2)?,
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+            token_count: row.get(5)?,
+            message_count: row.get(6)?,
+            preview: if preview.is_empty() {
+                "Empty conversation".to_string()
+            } else {
+                preview
+            },
+            pinned: row.get::<_, i64>(8)? != 0,
+            archived: row.get::<_, i64>(9)? != 0,
+            tags: Vec::new(),
+            folder_ids: Vec::new(),
+            deleted_at: row.get(10)?,
+        })
+    }).map_err(|e| format!("Failed to query conversations: {}", e))?;
+    
+    let mut result = Vec::new();
+    for conv in conversations {
+        result.push(conv.map_err(|e| format!("Failed to map conversation: {}", e))?);
+    }
+
+    let has_more = result.len() as i64 > limit;
+    result.truncate(limit as usize);
+    attach_organization(&conn, &mut result)
+        .map_err(|e| format!("Failed to load tags and folders: {}", e))?;
+
+    let next_cursor = if has_more {
+        result.last().map(|last| {
+            let key = match query.sort_by.as_deref().unwrap_or("updated") {
+                "created" => Value::from(last.created_at.clone()),
+                "title" => Value::from(last.title.clone()),
+                "token_count" => Value::from(last.token_count),
+                _ => Value::from(last.updated_at.clone()),
+            };
+            serde_json::to_string(&ConversationCursor { key, id: last.id.clone() }).unwrap_or_default()
+        })
+    } else {
+        None
+    };
+    
+    Ok(ConversationPage {
+        conversations: result,
+        next_cursor,
+        total_count,
+    })
+}
+
+// fills in the tag names and folder ids for a page of conversations with one query each
+fn attach_organization(conn: &Connection, previews: &mut [ConversationPreview]) -> SqlResult<()> {
+    if previews.is_empty() {
+        return Ok(());
+    }
+    let placeholders = vec!["?"; previews.len()].join(", ");
+    let ids: Vec<String> = previews.iter().map(|p| p.id.clone()).collect();
+    let index: HashMap<String, usize> = previews.iter()
+        .enumerate()
+        .map(|(i, p)| (p.id.clone(), i))
+        .collect();
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT ct.conversation_id, t.name FROM conversation_tags ct 
+         JOIN tags t ON t.id = ct.tag_id 
+         WHERE ct.conversation_id IN ({}) 
+         ORDER BY t.name COLLATE NOCASE",
+        placeholders
+    ))?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(ids.iter()), |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (conversation_id, name) = row?;
+        if let Some(&i) = index.get(&conversation_id) {
+            previews[i].tags.push(name);
+        }
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT conversation_id, folder_id FROM conversation_folders WHERE conversation_id IN ({})",
+        placeholders
+    ))?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(ids.iter()), |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+    })?;
+    for row in rows {
+        let (conversation_id, folder_id) = row?;
+        if let Some(&i) = index.get(&conversation_id) {
+            previews[i].folder_ids.push(folder_id);
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn load_conversation(app_handle: AppHandle, conversation_id: String) -> Result<Conversation, String> {
+    println!("load_conversation called for id: {}", conversation_id);
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    read_conversation(&conn, &conversation_id)
+}
+
+// edits a past message by adding the new content as a sibling branch, the original and
+// everything after it stay in the tree and can be switched back to with switch_branch
+#[tauri::command]
+pub async fn edit_message(app_handle: AppHandle, conversation_id: String, message_id: i64, content: String) -> Result<Conversation, String> {
+    println!("edit_message called for message: {} in conversation: {}", message_id, conversation_id);
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+    
+    let new_id = add_message_branch(&tx, &conversation_id, message_id, &content, None, "complete")?;
+    attachments::copy_to(&tx, message_id, new_id)?;
+    
+    set_active_leaf(&tx, &conversation_id, Some(new_id))
+        .map_err(|e| format!("Failed to update active branch: {}", e))?;
+    
+    tx.commit()
+        .map_err(|e| format!("Failed to commit transaction: {}", e))?;
+    
+    read_conversation(&conn, &conversation_id)
+}
+
+// adds a sibling of an existing message with the same role and parent, returns the new message id
+fn add_message_branch(
+    conn: &Connection,
+    conversation_id: &str,
+    message_id: i64,
+    content: &str,
+    model: Option<String>,
+    status: &str,
+) -> Result<i64, String> {
+    let (parent_id, role, position): (Option<i64>, String, i64) = conn.query_row(
+        "SELECT parent_id, role, position FROM messages WHERE id = ?1 AND conversation_id = ?2",
+        params![message_id, conversation_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    ).map_err(|e| format!("Failed to find message: {}", e))?;
+    
+    let message = ConversationMessage {
+        role,
+        content: content.to_string(),
+        timestamp: Utc::now().to_rfc3339(),
+        id: None,
+        parent_id,
+        sibling_ids: Vec::new(),
+        model,
+        status: status.to_string(),
+        rating: 0,
+        citations: Vec::new(),
+        attachments: Vec::new(),
+    };
+    
+    insert_message(conn, conversation_id, parent_id, position, &message)
+        .map_err(|e| format!("Failed to save message: {}", e))
+}
+
+// makes the branch containing the given message active, continuing down its newest replies
+#[tauri::command]
+pub async fn switch_branch(app_handle: AppHandle, conversation_id: String, message_id: i64) -> Result<Conversation, String> {
+    println!("switch_branch called for message: {} in conversation: {}", message_id, conversation_id);
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    let tree = load_message_tree(&conn, &conversation_id)
+        .map_err(|e| format!("Failed to query messages: {}", e))?;
+    
+    if !tree.contains(message_id) {
+        return Err(format!("Message {} not found in conversation", message_id));
+    }
+    
+    set_active_leaf(&conn, &conversation_id, Some(tree.latest_leaf(message_id)))
+        .map_err(|e| format!("Failed to update active branch: {}", e))?;
+    
+    read_conversation(&conn, &conversation_id)
+}
+
+// replays the conversation up to an assistant message and streams a new reply, optionally with another
+// model or options, the new reply is stored as a sibling so earlier variants stay available via switch_branch
+#[tauri::command]
+pub async fn regenerate_message(
+    window: Window,
+    app_handle: AppHandle,
+    conversation_id: String,
+    message_id: i64,
+    model: Option<String>,
+    options: Option<HashMap<String, Value>>,
+    state: tauri::State<'_, ConversationState>,
+) -> Result<Conversation, String> {
+    println!("regenerate_message called for message: {} in conversation: {}", message_id, conversation_id);
+    
+    let (target, model, persona) = {
+        let conn = init_conversations_db(&app_handle)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        ensure_writable(&conn, &conversation_id)?;
+        let conversation = read_conversation(&conn, &conversation_id)?;
+        let persona = personas::for_conversation(&conn, &conversation_id)
+            .map_err(|e| format!("Failed to load persona: {}", e))?;
+        let tree = load_message_tree(&conn, &conversation_id)
+            .map_err(|e| format!("Failed to query messages: {}", e))?;
+        
+        let target: Vec<MessageNode> = tree.path_to(message_id).into_iter().cloned().collect();
+        match target.last() {
+            Some(node) if node.role == "assistant" => {}
+            Some(_) => return Err("Only assistant messages can be regenerated".to_string()),
+            None => return Err(format!("Message {} not found in conversation", message_id)),
+        }
+        // an explicit model wins, then the persona's, the same as stream_prompt
+        let model = model
+            .or_else(|| persona.as_ref().and_then(|p| p.model.clone()))
+            .unwrap_or(conversation.model);
+        (target, model, persona)
+    };
+    
+    let mut options = personas::options(persona.as_ref(), options);
+    let plan = plan_context_window(&app_handle, &model, &mut options).await?;
+    let blob_dir = attachments::blob_dir(&get_app_data_dir(&app_handle)?);
+    let warm = target.iter().flat_map(|node| node.attachments.iter().cloned()).collect();
+    attachments::warm_images(blob_dir.clone(), warm, plan.image_max_dimension).await;
+    let mut history = {
+        let conn = init_conversations_db(&app_handle)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        let history: Vec<(i64, ChatMessage)> = target[..target.len() - 1]
+            .iter()
+            .map(|node| Ok((node.id, attachments::chat_message(
+                &conn,
+                &blob_dir,
+                plan.image_max_dimension,
+                node.role.clone(),
+                node.content.clone(),
+                &node.attachments,
+            )?)))
+            .collect::<Result<_, String>>()?;
+        summarized_history(&conn, &conversation_id, history, &plan)?
+    };
+    check_images(&model, &plan, &mut history)?;
+    if let Some(system) = personas::system_prompt(persona.as_ref(), None) {
+        history.insert(0, ChatMessage { role: "system".to_string(), content: system, images: None });
+    }
+    let (history, usage) = fit_to_context_window(&window, &model, history, &plan, &options).await?;
+    
+    let cancellation_token = CancellationToken::new();
+    {
+        let mut stream_state = state.stream_state.lock().await;
+        stream_state.cancellation_token = Some(cancellation_token.clone());
+    }
+    
+    let service = OllamaService::new(None);
+    let result = match service.chat_stream(&model, &history, None, options).await {
+        Ok(response) => Ok(relay_stream(&window, &state, response, &cancellation_token, None).await),
+        Err(e) => Err(e),
+    };
+    
+    {
+        let mut stream_state = state.stream_state.lock().await;
+        stream_state.cancellation_token = None;
+    }
+    
+    let StreamReply { text: generated, outcome, eval_count, prompt_eval_count } = result?;
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    // partial variants from a cancel or error are kept too, flagged by their status
+    if !generated.is_empty() || outcome == StreamOutcome::Completed {
+        let tx = conn.unchecked_transaction()
+            .map_err(|e| format!("Failed to start transaction: {}", e))?;
+        let new_id = add_message_branch(&tx, &conversation_id, message_id, &generated, Some(model), outcome.status())?;
+        tx.execute(
+            "UPDATE messages SET token_count = ?1 WHERE id = ?2",
+            params![eval_count, new_id],
+        ).map_err(|e| format!("Failed to save token count: {}", e))?;
+        set_active_leaf(&tx, &conversation_id, Some(new_id))
+            .map_err(|e| format!("Failed to update active branch: {}", e))?;
+        tx.commit()
+            .map_err(|e| format!("Failed to commit transaction: {}", e))?;
+    }
+    
+    if plan.summaries {
+        spawn_summary_refresh(app_handle.clone(), conversation_id.clone());
+    }
+    spawn_title_generation(app_handle.clone(), conversation_id.clone());
+    
+    if let Some(measured) = context_window::measured(&usage, prompt_eval_count) {
+        if let Err(e) = emit_context_usage(&window, &measured) {
+            println!("Failed to emit measured context usage: {}", e);
+        }
+    }
+    
+    if let StreamOutcome::Failed(error) = outcome {
+        return Err(error);
+    }
+    
+    read_conversation(&conn, &conversation_id)
+}
+
+// moves a conversation to the trash, pass permanent to delete it right away
+#[tauri::command]
+pub async fn delete_conversation(app_handle: AppHandle, conversation_id: String, permanent: Option<bool>) -> Result<(), String> {
+    println!("delete_conversation called for id: {}", conversation_id);
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    if permanent.unwrap_or(false) {
+        conn.execute(
+            "DELETE FROM conversations WHERE id = ?1",
+            params![&conversation_id],
+        ).map_err(|e| format!("Failed to delete conversation: {}", e))?;
+        sweep_attachments(&app_handle, &conn);
+    } else {
+        conn.execute(
+            "UPDATE conversations SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            params![Utc::now().to_rfc3339(), &conversation_id],
+        ).map_err(|e| format!("Failed to move conversation to trash: {}", e))?;
+    }
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn restore_conversation(app_handle: AppHandle, conversation_id: String) -> Result<(), String> {
+    println!("restore_conversation called for id: {}", conversation_id);
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let restored = conn.execute(
+        "UPDATE conversations SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL",
+        params![&conversation_id],
+    ).map_err(|e| format!("Failed to restore conversation: {}", e))?;
+    
+    if restored == 0 {
+        return Err(format!("Conversation {} is not in the trash", conversation_id));
+    }
+    
+    Ok(())
+}
+
+// permanently deletes everything in the trash, returns how many conversations were removed
+#[tauri::command]
+pub async fn empty_trash(app_handle: AppHandle) -> Result<usize, String> {
+    println!("empty_trash called");
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let removed = conn.execute("DELETE FROM conversations WHERE deleted_at IS NOT NULL", [])
+        .map_err(|e| format!("Failed to empty trash: {}", e))?;
+    sweep_attachments(&app_handle, &conn);
+    Ok(removed)
+}
+
+// deletes trashed conversations older than the configured retention period, along with attachment
+// files nothing points at anymore
+fn purge_expired_trash(app_handle: &AppHandle) -> Result<(), String> {
+    let prefs_conn = settings::open_preferences_db(&get_app_data_dir(app_handle)?)?;
+    let retention_days = settings::load_settings(&prefs_conn)?.trash_retention_days;
+    
+    let conn = init_conversations_db(app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    if retention_days > 0 {
+        let cutoff = (Utc::now() - chrono::Duration::days(retention_days)).to_rfc3339();
+        let purged = conn.execute(
+            "DELETE FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at < ?1",
+            params![&cutoff],
+        ).map_err(|e| format!("Failed to purge trash: {}", e))?;
+        
+        if purged > 0 {
+            println!("Purged {} conversations from the trash", purged);
+        }
+    }
+    sweep_attachments(app_handle, &conn);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_app_settings(app_handle: AppHandle) -> Result<AppSettings, String> {
+    let conn = settings::open_preferences_db(&get_app_data_dir(&app_handle)?)?;
+    settings::load_settings(&conn)
+}
+
+#[tauri::command]
+pub fn save_app_settings(app_handle: AppHandle, settings: AppSettings) -> Result<(), String> {
+    let conn = settings::open_preferences_db(&get_app_data_dir(&app_handle)?)?;
+    settings::save_settings(&conn, &settings)
+}
+
+#[tauri::command]
+pub async fn create_new_conversation(model: String) -> Result<Conversation, String> {
+    let timestamp = Utc::now().timestamp_millis();
+    let random: u32 = rand::thread_rng().gen();
     let id = format!("conv-{}-{:x}", timestamp, random);
     
     let now = Utc::now().to_rfc3339();
@@ -542,6 +1895,7 @@ pub async fn create_new_conversation(model: String) -> Result<Conversation, Stri
         created_at: now.clone(),
         updated_at: now,
         token_count: 0,
+        persona_id: None,
     })
 }
 
@@ -549,13 +1903,1813 @@ pub async fn create_new_conversation(model: String) -> Result<Conversation, Stri
 pub async fn update_conversation_title(app_handle: AppHandle, conversation_id: String, first_message: String) -> Result<(), String> {
     let conn = init_conversations_db(&app_handle)
         .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
     
-    let title = generate_title(&first_message);
+    let title = titles::fallback_title(&first_message);
     
+    // any other title is better than the truncated first message, and one left by a failed generation
+    // already is the truncated first message
     conn.execute(
-        "UPDATE conversations SET title = ?1 WHERE id = ?2",
+        "UPDATE conversations SET title = ?1, title_source = 'fallback' 
+         WHERE id = ?2 AND title_source IN ('default', 'fallback')",
         params![&title, &conversation_id],
     ).map_err(|e| format!("Failed to update title: {}", e))?;
     
     Ok(())
-}
\ No newline at end of file
+}
+
+#[tauri::command]
+pub async fn set_conversation_pinned(app_handle: AppHandle, conversation_id: String, pinned: bool) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    conn.execute(
+        "UPDATE conversations SET pinned = ?1 WHERE id = ?2",
+        params![pinned as i64, &conversation_id],
+    ).map_err(|e| format!("Failed to update pinned state: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_conversation_archived(app_handle: AppHandle, conversation_id: String, archived: bool) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    conn.execute(
+        "UPDATE conversations SET archived = ?1 WHERE id = ?2",
+        params![archived as i64, &conversation_id],
+    ).map_err(|e| format!("Failed to update archived state: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_tags(app_handle: AppHandle) -> Result<Vec<Tag>, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.name, t.color, COUNT(ct.conversation_id) 
+         FROM tags t 
+         LEFT JOIN conversation_tags ct ON ct.tag_id = t.id 
+         GROUP BY t.id 
+         ORDER BY t.name COLLATE NOCASE"
+    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    
+    let tags = stmt.query_map([], |row| {
+        Ok(Tag {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            color: row.get(2)?,
+            conversation_count: row.get(3)?,
+        })
+    }).map_err(|e| format!("Failed to query tags: {}", e))?;
+    
+    tags.collect::<SqlResult<Vec<_>>>()
+        .map_err(|e| format!("Failed to map tag: {}", e))
+}
+
+#[tauri::command]
+pub async fn create_tag(app_handle: AppHandle, name: String, color: Option<String>) -> Result<Tag, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Tag name cannot be empty".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    conn.execute(
+        "INSERT INTO tags (name, color, created_at) VALUES (?1, ?2, ?3)",
+        params![&name, &color, Utc::now().to_rfc3339()],
+    ).map_err(|e| format!("Failed to create tag: {}", e))?;
+    
+    Ok(Tag {
+        id: conn.last_insert_rowid(),
+        name,
+        color,
+        conversation_count: 0,
+    })
+}
+
+#[tauri::command]
+pub async fn update_tag(app_handle: AppHandle, tag_id: i64, name: String, color: Option<String>) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Tag name cannot be empty".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    conn.execute(
+        "UPDATE tags SET name = ?1, color = ?2 WHERE id = ?3",
+        params![&name, &color, tag_id],
+    ).map_err(|e| format!("Failed to update tag: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_tag(app_handle: AppHandle, tag_id: i64) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    conn.execute("DELETE FROM tags WHERE id = ?1", params![tag_id])
+        .map_err(|e| format!("Failed to delete tag: {}", e))?;
+    
+    Ok(())
+}
+
+// tags a conversation by name, creating the tag if it doesn't exist yet
+#[tauri::command]
+pub async fn add_conversation_tag(app_handle: AppHandle, conversation_id: String, tag: String) -> Result<(), String> {
+    let tag = tag.trim().to_string();
+    if tag.is_empty() {
+        return Err("Tag name cannot be empty".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    conn.execute(
+        "INSERT OR IGNORE INTO tags (name, created_at) VALUES (?1, ?2)",
+        params![&tag, Utc::now().to_rfc3339()],
+    ).map_err(|e| format!("Failed to create tag: {}", e))?;
+    
+    conn.execute(
+        "INSERT OR IGNORE INTO conversation_tags (conversation_id, tag_id) 
+         SELECT ?1, id FROM tags WHERE name = ?2",
+        params![&conversation_id, &tag],
+    ).map_err(|e| format!("Failed to tag conversation: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_conversation_tag(app_handle: AppHandle, conversation_id: String, tag: String) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    conn.execute(
+        "DELETE FROM conversation_tags 
+         WHERE conversation_id = ?1 AND tag_id IN (SELECT id FROM tags WHERE name = ?2)",
+        params![&conversation_id, &tag],
+    ).map_err(|e| format!("Failed to untag conversation: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_folders(app_handle: AppHandle) -> Result<Vec<Folder>, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let mut stmt = conn.prepare(
+        "SELECT f.id, f.name, f.parent_id, COUNT(cf.conversation_id) 
+         FROM folders f 
+         LEFT JOIN conversation_folders cf ON cf.folder_id = f.id 
+         GROUP BY f.id 
+         ORDER BY f.name COLLATE NOCASE"
+    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    
+    let folders = stmt.query_map([], |row| {
+        Ok(Folder {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            parent_id: row.get(2)?,
+            conversation_count: row.get(3)?,
+        })
+    }).map_err(|e| format!("Failed to query folders: {}", e))?;
+    
+    folders.collect::<SqlResult<Vec<_>>>()
+        .map_err(|e| format!("Failed to map folder: {}", e))
+}
+
+#[tauri::command]
+pub async fn create_folder(app_handle: AppHandle, name: String, parent_id: Option<i64>) -> Result<Folder, String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Folder name cannot be empty".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    conn.execute(
+        "INSERT INTO folders (name, parent_id, created_at) VALUES (?1, ?2, ?3)",
+        params![&name, parent_id, Utc::now().to_rfc3339()],
+    ).map_err(|e| format!("Failed to create folder: {}", e))?;
+    
+    Ok(Folder {
+        id: conn.last_insert_rowid(),
+        name,
+        parent_id,
+        conversation_count: 0,
+    })
+}
+
+#[tauri::command]
+pub async fn rename_folder(app_handle: AppHandle, folder_id: i64, name: String) -> Result<(), String> {
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Folder name cannot be empty".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    conn.execute(
+        "UPDATE folders SET name = ?1 WHERE id = ?2",
+        params![&name, folder_id],
+    ).map_err(|e| format!("Failed to rename folder: {}", e))?;
+    
+    Ok(())
+}
+
+// deletes a folder and its subfolders, the conversations inside are kept
+#[tauri::command]
+pub async fn delete_folder(app_handle: AppHandle, folder_id: i64) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    conn.execute("DELETE FROM folders WHERE id = ?1", params![folder_id])
+        .map_err(|e| format!("Failed to delete folder: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn add_conversation_to_folder(app_handle: AppHandle, conversation_id: String, folder_id: i64) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    conn.execute(
+        "INSERT OR IGNORE INTO conversation_folders (conversation_id, folder_id) VALUES (?1, ?2)",
+        params![&conversation_id, folder_id],
+    ).map_err(|e| format!("Failed to add conversation to folder: {}", e))?;
+    
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn remove_conversation_from_folder(app_handle: AppHandle, conversation_id: String, folder_id: i64) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    conn.execute(
+        "DELETE FROM conversation_folders WHERE conversation_id = ?1 AND folder_id = ?2",
+        params![&conversation_id, folder_id],
+    ).map_err(|e| format!("Failed to remove conversation from folder: {}", e))?;
+    
+    Ok(())
+}
+
+// gathers the active branch, the full message tree and the organization of a conversation for export
+fn build_conversation_export(conn: &Connection, conversation_id: &str) -> Result<ConversationExport, String> {
+    let conversation = read_conversation(conn, conversation_id)?;
+    let tree = load_message_tree(conn, conversation_id)
+        .map_err(|e| format!("Failed to query messages: {}", e))?;
+    
+    let names = |sql: &str| -> Result<Vec<String>, String> {
+        let mut stmt = conn.prepare(sql)
+            .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        let rows = stmt.query_map(params![conversation_id], |row| row.get::<_, String>(0))
+            .map_err(|e| format!("Failed to query conversation organization: {}", e))?;
+        rows.collect::<SqlResult<Vec<_>>>()
+            .map_err(|e| format!("Failed to map conversation organization: {}", e))
+    };
+    
+    Ok(ConversationExport {
+        all_messages: tree.nodes().into_iter().cloned().collect(),
+        tags: names(
+            "SELECT t.name FROM tags t JOIN conversation_tags ct ON ct.tag_id = t.id 
+             WHERE ct.conversation_id = ?1 ORDER BY t.name COLLATE NOCASE"
+        )?,
+        folders: names(
+            "SELECT f.name FROM folders f JOIN conversation_folders cf ON cf.folder_id = f.id 
+             WHERE cf.conversation_id = ?1 ORDER BY f.name COLLATE NOCASE"
+        )?,
+        conversation,
+    })
+}
+
+fn write_conversation_export(conn: &Connection, conversation_id: &str, format: ExportFormat, path: &std::path::Path) -> Result<PathBuf, String> {
+    let export = build_conversation_export(conn, conversation_id)?;
+    let rendered = export::render(format, &export)?;
+    
+    // a directory gets a file named after the conversation
+    let path = if path.is_dir() {
+        path.join(export::file_name(&export.conversation, format))
+    } else {
+        path.to_path_buf()
+    };
+    
+    std::fs::write(&path, rendered)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+// exports a conversation as "markdown", "json", "html" or "text" to a file or into a directory
+#[tauri::command]
+pub async fn export_conversation(app_handle: AppHandle, conversation_id: String, format: String, path: String) -> Result<String, String> {
+    println!("export_conversation called for id: {} as {}", conversation_id, format);
+    
+    let format = ExportFormat::parse(&format)?;
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let written = write_conversation_export(&conn, &conversation_id, format, std::path::Path::new(&path))?;
+    Ok(written.display().to_string())
+}
+
+// exports many conversations into a directory, one file each, every live conversation when no ids are given
+#[tauri::command]
+pub async fn export_conversations(
+    app_handle: AppHandle,
+    conversation_ids: Option<Vec<String>>,
+    format: String,
+    directory: String,
+) -> Result<ExportSummary, String> {
+    println!("export_conversations called as {}", format);
+    
+    let format = ExportFormat::parse(&format)?;
+    let directory = PathBuf::from(directory);
+    std::fs::create_dir_all(&directory)
+        .map_err(|e| format!("Failed to create export directory: {}", e))?;
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let ids = match conversation_ids {
+        Some(ids) => ids,
+        None => {
+            let mut stmt = conn.prepare("SELECT id FROM conversations WHERE deleted_at IS NULL ORDER BY updated_at DESC")
+                .map_err(|e| format!("Failed to prepare statement: {}", e))?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))
+                .map_err(|e| format!("Failed to query conversations: {}", e))?;
+            rows.collect::<SqlResult<Vec<_>>>()
+                .map_err(|e| format!("Failed to map conversation: {}", e))?
+        }
+    };
+    
+    let mut summary = ExportSummary { exported: Vec::new(), failed: Vec::new() };
+    for id in ids {
+        match write_conversation_export(&conn, &id, format, &directory) {
+            Ok(path) => summary.exported.push(path.display().to_string()),
+            Err(error) => summary.failed.push(ExportFailure { conversation_id: id, error }),
+        }
+    }
+    
+    println!("Exported {} conversations, {} failed", summary.exported.len(), summary.failed.len());
+    Ok(summary)
+}
+
+// stores an imported conversation with its full message tree, returns false when it was imported before
+fn store_imported_conversation(conn: &Connection, imported: &ImportedConversation) -> Result<bool, String> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM conversations WHERE id = ?1)",
+        params![&imported.id],
+        |row| row.get(0),
+    ).map_err(|e| format!("Failed to check for existing conversation: {}", e))?;
+    if exists {
+        return Ok(false);
+    }
+    
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+    
+    tx.execute(
+        "INSERT INTO conversations (id, title, model, created_at, updated_at, title_source) 
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![&imported.id, &imported.title, &imported.model, &imported.created_at, &imported.updated_at, titles::SOURCE_IMPORTED],
+    ).map_err(|e| format!("Failed to save conversation: {}", e))?;
+    
+    // source keys map to the new row ids and their depth in the tree
+    let mut stored: HashMap<&str, (i64, i64)> = HashMap::new();
+    for message in &imported.messages {
+        let parent = message.parent_key.as_deref().and_then(|key| stored.get(key)).copied();
+        let row = ConversationMessage {
+            role: message.role.clone(),
+            content: message.content.clone(),
+            timestamp: message.timestamp.clone(),
+            id: None,
+            parent_id: parent.map(|(id, _)| id),
+            sibling_ids: Vec::new(),
+            model: message.model.clone(),
+            status: message.status.clone(),
+            rating: message.rating,
+            citations: Vec::new(),
+            attachments: Vec::new(),
+        };
+        let position = parent.map(|(_, depth)| depth + 1).unwrap_or(0);
+        let id = insert_message(&tx, &imported.id, row.parent_id, position, &row)
+            .map_err(|e| format!("Failed to save message: {}", e))?;
+        stored.insert(&message.key, (id, position));
+    }
+    
+    let tree = load_message_tree(&tx, &imported.id)
+        .map_err(|e| format!("Failed to query messages: {}", e))?;
+    let active_leaf = imported.active_key.as_deref()
+        .and_then(|key| stored.get(key))
+        .map(|(id, _)| *id)
+        .or_else(|| tree.default_leaf());
+    set_active_leaf(&tx, &imported.id, active_leaf)
+        .map_err(|e| format!("Failed to update active branch: {}", e))?;
+    
+    tx.commit()
+        .map_err(|e| format!("Failed to commit transaction: {}", e))?;
+    Ok(true)
+}
+
+// imports ChatGPT conversations.json exports, OpenAI-style JSONL logs and our own JSON exports
+// conversations that were already imported are skipped, every file gets its own report
+#[tauri::command]
+pub async fn import_conversations(app_handle: AppHandle, paths: Vec<String>) -> Result<Vec<ImportFileReport>, String> {
+    println!("import_conversations called for {} files", paths.len());
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let mut reports = Vec::new();
+    for path in paths {
+        let mut report = ImportFileReport { path: path.clone(), ..Default::default() };
+        
+        match import::parse_file(std::path::Path::new(&path)) {
+            Ok(conversations) => {
+                for parsed in conversations {
+                    match parsed.and_then(|conversation| store_imported_conversation(&conn, &conversation)) {
+                        Ok(true) => report.imported += 1,
+                        Ok(false) => report.skipped += 1,
+                        Err(e) => {
+                            report.failed += 1;
+                            report.errors.push(e);
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                report.failed += 1;
+                report.errors.push(e);
+            }
+        }
+        
+        println!("Imported {}: {} new, {} skipped, {} failed", path, report.imported, report.skipped, report.failed);
+        reports.push(report);
+    }
+    
+    Ok(reports)
+}
+
+// records thumbs up (1), thumbs down (-1) or clears the rating (0) of a message
+#[tauri::command]
+pub async fn rate_message(app_handle: AppHandle, conversation_id: String, message_id: i64, rating: i64) -> Result<(), String> {
+    if !(-1..=1).contains(&rating) {
+        return Err(format!("Invalid rating: {}", rating));
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    
+    let updated = conn.execute(
+        "UPDATE messages SET rating = ?1 WHERE id = ?2 AND conversation_id = ?3",
+        params![rating, message_id, &conversation_id],
+    ).map_err(|e| format!("Failed to rate message: {}", e))?;
+    
+    if updated == 0 {
+        return Err(format!("Message {} not found in conversation", message_id));
+    }
+    Ok(())
+}
+
+// writes the selected conversations as chat JSONL ({"messages": [...]} per line) for fine-tuning,
+// split into <name>_train.jsonl and <name>_validation.jsonl inside the directory
+#[tauri::command]
+pub async fn export_finetune_dataset(
+    app_handle: AppHandle,
+    options: DatasetOptions,
+    directory: String,
+    name: Option<String>,
+) -> Result<DatasetSummary, String> {
+    println!("export_finetune_dataset called");
+    
+    if !(0.0..1.0).contains(&options.validation_fraction) {
+        return Err("Validation fraction must be between 0 and 1".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    
+    let mut conditions = vec!["deleted_at IS NULL".to_string()];
+    let mut filter_params: Vec<rusqlite::types::Value> = Vec::new();
+    if let Some(ids) = &options.conversation_ids {
+        conditions.push(format!("id IN ({})", vec!["?"; ids.len().max(1)].join(", ")));
+        if ids.is_empty() {
+            filter_params.push(rusqlite::types::Value::Null);
+        }
+        filter_params.extend(ids.iter().map(|id| id.clone().into()));
+    }
+    if let Some(tag) = &options.tag {
+        conditions.push(
+            "id IN (SELECT ct.conversation_id FROM conversation_tags ct 
+                    JOIN tags t ON t.id = ct.tag_id WHERE t.name = ?)".to_string()
+        );
+        filter_params.push(tag.clone().into());
+    }
+    // encrypted content can't be matched in SQL, so the search is applied to the decrypted messages below
+    let encrypted = crypto::status(&conn)?.enabled;
+    if let Some(search) = options.search.as_ref().filter(|_| !encrypted) {
+        let pattern = format!("%{}%", search);
+        conditions.push(
+            "(title LIKE ? OR id IN (SELECT conversation_id FROM messages WHERE content LIKE ?))".to_string()
+        );
+        filter_params.push(pattern.clone().into());
+        filter_params.push(pattern.into());
+    }
+    
+    let ids: Vec<String> = {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id FROM conversations WHERE {} ORDER BY created_at",
+            conditions.join(" AND ")
+        )).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(filter_params.iter()), |row| row.get::<_, String>(0))
+            .map_err(|e| format!("Failed to query conversations: {}", e))?;
+        rows.collect::<SqlResult<Vec<_>>>()
+            .map_err(|e| format!("Failed to map conversation: {}", e))?
+    };
+    
+    let mut train = String::new();
+    let mut validation = String::new();
+    let mut summary = DatasetSummary::default();
+    for id in ids {
+        let conversation = read_conversation(&conn, &id)?;
+        if let Some(search) = options.search.as_ref().filter(|_| encrypted) {
+            let search = search.to_lowercase();
+            let matches = conversation.title.to_lowercase().contains(&search)
+                || load_message_tree(&conn, &id)
+                    .map_err(|e| format!("Failed to load messages: {}", e))?
+                    .nodes()
+                    .iter()
+                    .any(|node| node.content.to_lowercase().contains(&search));
+            if !matches {
+                continue;
+            }
+        }
+        let Some(example) = dataset::build_example(&conversation, &options) else {
+            summary.skipped_conversations += 1;
+            continue;
+        };
+        if dataset::is_validation(&id, &options) {
+            validation.push_str(&example);
+            validation.push('\n');
+            summary.validation_examples += 1;
+        } else {
+            train.push_str(&example);
+            train.push('\n');
+            summary.train_examples += 1;
+        }
+    }
+    
+    let directory = PathBuf::from(directory);
+    std::fs::create_dir_all(&directory)
+        .map_err(|e| format!("Failed to create export directory: {}", e))?;
+    let name = name.unwrap_or_else(|| "dataset".to_string());
+    
+    let train_path = directory.join(format!("{}_train.jsonl", name));
+    std::fs::write(&train_path, train)
+        .map_err(|e| format!("Failed to write {}: {}", train_path.display(), e))?;
+    summary.train_path = train_path.display().to_string();
+    
+    if options.validation_fraction > 0.0 {
+        let validation_path = directory.join(format!("{}_validation.jsonl", name));
+        std::fs::write(&validation_path, validation)
+            .map_err(|e| format!("Failed to write {}: {}", validation_path.display(), e))?;
+        summary.validation_path = Some(validation_path.display().to_string());
+    }
+    
+    println!(
+        "Dataset exported: {} train, {} validation, {} skipped",
+        summary.train_examples, summary.validation_examples, summary.skipped_conversations
+    );
+    Ok(summary)
+}
+
+fn backup_directory(app_handle: &AppHandle, settings: &AppSettings) -> Result<PathBuf, String> {
+    match &settings.backup_directory {
+        Some(directory) => Ok(PathBuf::from(directory)),
+        None => Ok(get_app_data_dir(app_handle)?.join("backups")),
+    }
+}
+
+// writes a single-archive backup of every database, into the backup directory when no path is given
+#[tauri::command]
+pub async fn create_backup(app_handle: AppHandle, path: Option<String>) -> Result<BackupInfo, String> {
+    println!("create_backup called");
+    
+    let app_data_dir = get_app_data_dir(&app_handle)?;
+    let destination = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+            backup_directory(&app_handle, &settings)?.join(backup::backup_file_name())
+        }
+    };
+    
+    backup::create_backup(&app_data_dir, &destination)
+}
+
+// validates a backup archive and swaps its databases in, the replaced files are kept as *.pre-restore
+#[tauri::command]
+pub async fn restore_backup(app_handle: AppHandle, path: String, state: tauri::State<'_, ConversationState>) -> Result<BackupManifest, String> {
+    println!("restore_backup called for {}", path);
+    
+    if state.stream_state.lock().await.cancellation_token.is_some() {
+        return Err("Cannot restore a backup while a response is streaming".to_string());
+    }
+    
+    let app_data_dir = get_app_data_dir(&app_handle)?;
+    let manifest = backup::restore_backup(&app_data_dir, std::path::Path::new(&path))?;
+    *state.context.lock().unwrap() = None;
+    
+    // the restored database may use a different key, or none at all
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    crypto::load_state(&conn)?;
+    crypto::lock(&conn)?;
+    
+    println!("Restored backup from {}", manifest.created_at);
+    Ok(manifest)
+}
+
+// purges expired trash at startup and then once an hour, so an app left running keeps to the retention period
+pub fn start_trash_purger(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            if let Err(e) = purge_expired_trash(&app_handle) {
+                println!("Failed to purge the trash: {}", e);
+            }
+        }
+    });
+}
+
+// checks once an hour whether a scheduled backup is due
+pub fn start_backup_scheduler(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            
+            let result = get_app_data_dir(&app_handle).and_then(|app_data_dir| {
+                let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+                let directory = backup_directory(&app_handle, &settings)?;
+                backup::run_scheduled_backup(
+                    &app_data_dir,
+                    &directory,
+                    settings.backup_interval_hours,
+                    settings.backup_retention_count.max(1) as usize,
+                )
+            });
+            
+            if let Err(e) = result {
+                println!("Scheduled backup failed: {}", e);
+            }
+        }
+    });
+}
+
+// reads the encryption key state at startup, an encrypted database stays locked until unlock_database is called
+pub fn load_encryption_state(app_handle: &AppHandle) -> Result<(), String> {
+    let conn = init_conversations_db(app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    crypto::load_state(&conn)
+}
+
+#[tauri::command]
+pub async fn get_encryption_status(app_handle: AppHandle) -> Result<EncryptionStatus, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    crypto::status(&conn)
+}
+
+#[tauri::command]
+pub async fn unlock_database(app_handle: AppHandle, secret: Secret) -> Result<EncryptionStatus, String> {
+    println!("unlock_database called");
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    crypto::unlock(&conn, &secret)?;
+    crypto::status(&conn)
+}
+
+#[tauri::command]
+pub async fn lock_database(app_handle: AppHandle, state: tauri::State<'_, ConversationState>) -> Result<EncryptionStatus, String> {
+    println!("lock_database called");
+    
+    if state.stream_state.lock().await.cancellation_token.is_some() {
+        return Err("Cannot lock the database while a response is streaming".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    crypto::lock(&conn)?;
+    *state.context.lock().unwrap() = None;
+    vision::clear_cache();
+    crypto::status(&conn)
+}
+
+// encrypts every existing message in place, the database file is vacuumed so no plaintext is left in free pages
+#[tauri::command]
+pub async fn enable_encryption(app_handle: AppHandle, secret: Secret, state: tauri::State<'_, ConversationState>) -> Result<EncryptionStatus, String> {
+    println!("enable_encryption called");
+    
+    if state.stream_state.lock().await.cancellation_token.is_some() {
+        return Err("Cannot enable encryption while a response is streaming".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let blob_dir = attachments::blob_dir(&get_app_data_dir(&app_handle)?);
+    // the keyword index is plaintext, it has to go before the vacuum at the end of enabling
+    semantic_search::clear_keyword_index(&conn)
+        .map_err(|e| format!("Failed to clear search index: {}", e))?;
+    let rewritten = match crypto::enable(&conn, &secret, &blob_dir) {
+        Ok(rewritten) => rewritten,
+        Err(e) => {
+            // still plaintext, let the worker rebuild the index
+            if let Err(e) = semantic_search::enqueue_all(&conn) {
+                println!("Failed to queue messages for indexing: {}", e);
+            }
+            return Err(e);
+        }
+    };
+    
+    println!("Encrypted {} values", rewritten);
+    crypto::status(&conn)
+}
+
+// switches to a new passphrase or keyfile and re-encrypts everything under a fresh data key
+#[tauri::command]
+pub async fn rotate_encryption_key(app_handle: AppHandle, secret: Secret, state: tauri::State<'_, ConversationState>) -> Result<EncryptionStatus, String> {
+    println!("rotate_encryption_key called");
+    
+    if state.stream_state.lock().await.cancellation_token.is_some() {
+        return Err("Cannot rotate the key while a response is streaming".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let blob_dir = attachments::blob_dir(&get_app_data_dir(&app_handle)?);
+    let rewritten = crypto::rotate(&conn, &secret, &blob_dir)?;
+    
+    println!("Re-encrypted {} values", rewritten);
+    crypto::status(&conn)
+}
+
+#[tauri::command]
+pub async fn disable_encryption(app_handle: AppHandle, state: tauri::State<'_, ConversationState>) -> Result<EncryptionStatus, String> {
+    println!("disable_encryption called");
+    
+    if state.stream_state.lock().await.cancellation_token.is_some() {
+        return Err("Cannot disable encryption while a response is streaming".to_string());
+    }
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let blob_dir = attachments::blob_dir(&get_app_data_dir(&app_handle)?);
+    let rewritten = crypto::disable(&conn, &blob_dir)?;
+    
+    println!("Decrypted {} values", rewritten);
+    crypto::status(&conn)
+}
+
+// counts tokens the way the model will, loading its tokenizer on first use
+#[tauri::command]
+pub async fn count_tokens(model: String, text: String) -> Result<i64, String> {
+    tokenizer::load(&model).await;
+    Ok(tokenizer::count_tokens(Some(&model), &text))
+}
+
+fn spawn_summary_refresh(app_handle: AppHandle, conversation_id: String) {
+    tauri::async_runtime::spawn(async move {
+        if let Err(e) = refresh_conversation_summary(&app_handle, &conversation_id, false).await {
+            println!("Failed to refresh summary for {}: {}", conversation_id, e);
+        }
+    });
+}
+
+// brings the rolling summary of the active branch up to date, a stale or forced summary is rebuilt from the
+// start of the branch, otherwise older turns are folded in once enough of them sit outside the recent window
+async fn refresh_conversation_summary(app_handle: &AppHandle, conversation_id: &str, force: bool) -> Result<Option<ConversationSummary>, String> {
+    let settings = settings::load_settings(&settings::open_preferences_db(&get_app_data_dir(app_handle)?)?)?;
+    
+    let (path, conversation_model, latest) = {
+        let conn = init_conversations_db(app_handle)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        let conversation = read_conversation(&conn, conversation_id)?;
+        let path: Vec<(i64, ChatMessage)> = conversation.messages
+            .into_iter()
+            .filter(|m| m.status != "streaming")
+            .filter_map(|m| Some((m.id?, ChatMessage { role: m.role, content: m.content, images: None })))
+            .collect();
+        let ids: Vec<i64> = path.iter().map(|(id, _)| *id).collect();
+        let summaries = summary::load_summaries(&conn, conversation_id)
+            .map_err(|e| format!("Failed to load summaries: {}", e))?;
+        (path, conversation.model, summary::latest_on_path(summaries, &ids))
+    };
+    
+    let boundary = path.len().saturating_sub(settings.summary_keep_recent.max(0) as usize);
+    let rebuild = force || latest.as_ref().is_some_and(|(_, latest)| latest.stale);
+    let (start, previous, end) = match &latest {
+        Some((index, _)) if rebuild => (0, None, boundary.max(index + 1)),
+        Some((index, latest)) => (index + 1, Some(latest.content.clone()), boundary),
+        None => (0, None, boundary),
+    };
+    if end <= start || (!rebuild && end - start < settings.summary_batch_size.max(1) as usize) {
+        return Ok(latest.map(|(_, latest)| latest));
+    }
+    
+    let model = settings.summary_model.clone().unwrap_or(conversation_model);
+    let mut options = None;
+    let plan = plan_context_window(app_handle, &model, &mut options).await?;
+    let count = |text: &str| tokenizer::count_tokens(Some(&model), text);
+    
+    // the turns have to fit the summarizer's own window next to the previous summary
+    let budget = plan.window - plan.reserved - previous.as_deref().map(count).unwrap_or(0);
+    let turns: Vec<ChatMessage> = path[start..end].iter().map(|(_, message)| message.clone()).collect();
+    let source = context_window::fit_history(turns, budget, &TrimStrategy::DropOldest, count).messages;
+    
+    println!("Summarizing {} messages of {} with {}", source.len(), conversation_id, model);
+    let content = OllamaService::new(None)
+        .chat(&model, &summary::rolling_summary_request(previous.as_deref(), &source), options)
+        .await?;
+    
+    let conn = init_conversations_db(app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let through_message_id = path[end - 1].0;
+    summary::store_summary(&conn, conversation_id, through_message_id, &content, &model, end as i64)?;
+    
+    let summaries = summary::load_summaries(&conn, conversation_id)
+        .map_err(|e| format!("Failed to load summaries: {}", e))?;
+    Ok(summaries.into_iter().find(|s| s.through_message_id == through_message_id))
+}
+
+// the rolling summary used for the conversation's active branch, if there is one
+#[tauri::command]
+pub async fn get_conversation_summary(app_handle: AppHandle, conversation_id: String) -> Result<Option<ConversationSummary>, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let conversation = read_conversation(&conn, &conversation_id)?;
+    let ids: Vec<i64> = conversation.messages.iter().filter_map(|m| m.id).collect();
+    let summaries = summary::load_summaries(&conn, &conversation_id)
+        .map_err(|e| format!("Failed to load summaries: {}", e))?;
+    Ok(summary::latest_on_path(summaries, &ids).map(|(_, latest)| latest))
+}
+
+// replaces a summary's text, later turns are folded into the edited version
+#[tauri::command]
+pub async fn update_conversation_summary(app_handle: AppHandle, summary_id: i64, content: String) -> Result<ConversationSummary, String> {
+    println!("update_conversation_summary called for summary: {}", summary_id);
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    summary::update_summary_content(&conn, summary_id, &content)?;
+    summary::load_summary(&conn, summary_id)
+        .map_err(|e| format!("Failed to load summary: {}", e))?
+        .ok_or_else(|| format!("Summary {} not found", summary_id))
+}
+
+// rebuilds the active branch's summary from scratch, discarding any edits
+#[tauri::command]
+pub async fn regenerate_conversation_summary(app_handle: AppHandle, conversation_id: String) -> Result<Option<ConversationSummary>, String> {
+    println!("regenerate_conversation_summary called for conversation: {}", conversation_id);
+    {
+        let conn = init_conversations_db(&app_handle)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        ensure_writable(&conn, &conversation_id)?;
+    }
+    refresh_conversation_summary(&app_handle, &conversation_id, true).await
+}
+
+// titles a conversation once its first reply is in, unless it already has a title that is kept or the model
+// failed on it twice
+fn spawn_title_generation(app_handle: AppHandle, conversation_id: String) {
+    tauri::async_runtime::spawn(async move {
+        let result = async {
+            let settings = settings::load_settings(&settings::open_preferences_db(&get_app_data_dir(&app_handle)?)?)?;
+            if !settings.title_generation_enabled {
+                return Ok(None);
+            }
+            let source: String = init_conversations_db(&app_handle)
+                .and_then(|conn| conn.query_row(
+                    "SELECT title_source FROM conversations WHERE id = ?1",
+                    params![&conversation_id],
+                    |row| row.get(0),
+                ))
+                .map_err(|e| format!("Failed to load conversation: {}", e))?;
+            if !titles::needs_title(&source) {
+                return Ok(None);
+            }
+            generate_conversation_title(&app_handle, &conversation_id, &settings, true).await
+        }.await;
+        if let Err(e) = result {
+            println!("Failed to title conversation {}: {}", conversation_id, e);
+        }
+    });
+}
+
+// asks the title model about the first exchange, falling back to the truncated first message when the
+// model is unavailable or answers with nothing usable, returns the source of the title that was stored
+async fn generate_conversation_title(
+    app_handle: &AppHandle,
+    conversation_id: &str,
+    settings: &AppSettings,
+    needs_reply: bool,
+) -> Result<Option<&'static str>, String> {
+    let conversation = {
+        let conn = init_conversations_db(app_handle)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        read_conversation(&conn, conversation_id)?
+    };
+    
+    let Some(first_prompt) = conversation.messages.iter().position(|m| m.role == "user") else {
+        return Ok(None);
+    };
+    let reply = conversation.messages[first_prompt..]
+        .iter()
+        .position(|m| m.role == "assistant" && m.status == "complete")
+        .map(|offset| first_prompt + offset);
+    if needs_reply && reply.is_none() {
+        return Ok(None);
+    }
+    let first_exchange: Vec<ChatMessage> = conversation.messages[..=reply.unwrap_or(first_prompt)]
+        .iter()
+        .filter(|m| m.role != "system")
+        .map(|m| ChatMessage { role: m.role.clone(), content: m.content.clone(), images: None })
+        .collect();
+    
+    let model = settings.title_model.clone().unwrap_or(conversation.model);
+    let mut options = HashMap::new();
+    options.insert("num_predict".to_string(), Value::from(32));
+    let generated = match OllamaService::new(None).chat(&model, &titles::title_request(&first_exchange), Some(options)).await {
+        Ok(raw) => titles::clean_title(&raw),
+        Err(e) => {
+            println!("Title model {} failed: {}", model, e);
+            None
+        }
+    };
+    
+    let conn = init_conversations_db(app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let (title, source) = match generated {
+        Some(title) => (title, titles::SOURCE_GENERATED),
+        None => {
+            let previous: String = conn.query_row(
+                "SELECT title_source FROM conversations WHERE id = ?1",
+                params![conversation_id],
+                |row| row.get(0),
+            ).map_err(|e| format!("Failed to load conversation: {}", e))?;
+            (titles::fallback_title(&conversation.messages[first_prompt].content), titles::failed_source(&previous))
+        }
+    };
+    conn.execute(
+        "UPDATE conversations SET title = ?1, title_source = ?2 WHERE id = ?3",
+        params![&title, source, conversation_id],
+    ).map_err(|e| format!("Failed to update title: {}", e))?;
+    
+    if let Err(e) = app_handle.emit("conversation-titled", json!({ "conversation_id": conversation_id, "title": &title })) {
+        println!("Failed to emit conversation-titled: {}", e);
+    }
+    Ok(Some(source))
+}
+
+// re-titles the given conversations, or every one outside the trash, generated, imported and user titles
+// are only replaced when overwrite is set
+#[tauri::command]
+pub async fn retitle_conversations(app_handle: AppHandle, conversation_ids: Option<Vec<String>>, overwrite: bool) -> Result<RetitleSummary, String> {
+    println!("retitle_conversations called (overwrite: {})", overwrite);
+    
+    let settings = settings::load_settings(&settings::open_preferences_db(&get_app_data_dir(&app_handle)?)?)?;
+    let ids: Vec<String> = {
+        let conn = init_conversations_db(&app_handle)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        let mut stmt = conn.prepare(
+            "SELECT id FROM conversations 
+             WHERE deleted_at IS NULL AND (?1 OR title_source NOT IN ('generated', 'imported', 'user')) 
+             ORDER BY updated_at DESC"
+        ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        let rows = stmt.query_map(params![overwrite], |row| row.get::<_, String>(0))
+            .map_err(|e| format!("Failed to query conversations: {}", e))?;
+        let candidates = rows.collect::<SqlResult<Vec<_>>>()
+            .map_err(|e| format!("Failed to map conversation: {}", e))?;
+        match conversation_ids {
+            Some(wanted) => candidates.into_iter().filter(|id| wanted.contains(id)).collect(),
+            None => candidates,
+        }
+    };
+    
+    let mut summary = RetitleSummary::default();
+    for id in ids {
+        match generate_conversation_title(&app_handle, &id, &settings, false).await {
+            Ok(Some(titles::SOURCE_GENERATED)) => summary.generated += 1,
+            Ok(Some(_)) => summary.fallback += 1,
+            Ok(None) => summary.skipped += 1,
+            Err(e) => {
+                println!("Failed to title {}: {}", id, e);
+                summary.failed.push(id);
+            }
+        }
+    }
+    
+    println!(
+        "Retitled conversations: {} generated, {} fallback, {} skipped, {} failed",
+        summary.generated, summary.fallback, summary.skipped, summary.failed.len()
+    );
+    Ok(summary)
+}
+
+// chunks sent to Ollama per embed request
+const EMBED_BATCH_SIZE: usize = 16;
+
+#[derive(Serialize, Clone)]
+struct IndexProgress {
+    source_id: i64,
+    processed: usize,
+    total: usize,
+    path: String,
+}
+
+// sent with "knowledge-source-status" whenever a source starts or finishes indexing or syncing
+#[derive(Serialize, Clone)]
+struct KnowledgeSourceStatus {
+    source: KnowledgeSource,
+    report: Option<IndexReport>,
+}
+
+enum FileSync {
+    Unchanged,
+    Unreadable(String),
+    Embedded(EmbeddedDocument),
+}
+
+// chunks and embeds a file unless its content hash and embedding model match what is indexed
+async fn embed_knowledge_file(
+    ollama: &OllamaService,
+    model: &str,
+    max_tokens: usize,
+    file: &std::path::Path,
+    path: &str,
+    indexed: &HashMap<String, (String, String)>,
+    force: bool,
+) -> Result<FileSync, String> {
+    let bytes = match std::fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(e) => return Ok(FileSync::Unreadable(e.to_string())),
+    };
+    let hash = knowledge::content_hash(&bytes);
+    if !force && indexed.get(path).is_some_and(|(h, m)| *h == hash && m == model) {
+        return Ok(FileSync::Unchanged);
+    }
+
+    // pdfs and office documents go through extraction and are chunked along their page and section anchors
+    let (kind, text) = if extract::is_extractable(file) {
+        let owned = file.to_path_buf();
+        match tauri::async_runtime::spawn_blocking(move || extract::extract_file(&owned)).await {
+            Ok(Ok(document)) => (DocumentKind::Markdown, document.to_markdown()),
+            Ok(Err(e)) => return Ok(FileSync::Unreadable(e)),
+            Err(e) => return Ok(FileSync::Unreadable(e.to_string())),
+        }
+    } else {
+        let Some(kind) = DocumentKind::from_path(file) else {
+            return Ok(FileSync::Unreadable("unsupported file type".to_string()));
+        };
+        match String::from_utf8(bytes) {
+            Ok(text) => (kind, text),
+            Err(_) => return Ok(FileSync::Unreadable("not UTF-8 text".to_string())),
+        }
+    };
+
+    // source code with a grammar is chunked along its symbols, the rest by blank lines and headings
+    let parsed = code_index::Language::from_path(file)
+        .and_then(|language| code_index::chunk_source(language, &text, max_tokens));
+    let (chunks, symbols) = match parsed {
+        Some(index) => (index.chunks, index.symbols),
+        None => (chunker::chunk_document(kind, &text, max_tokens), Vec::new()),
+    };
+    let inputs: Vec<String> = chunks.iter().map(|chunk| knowledge::embedding_input(path, chunk)).collect();
+    let mut embeddings = Vec::with_capacity(inputs.len());
+    for batch in inputs.chunks(EMBED_BATCH_SIZE) {
+        // a failed embed is an Ollama or model problem that every other file would hit too
+        embeddings.extend(ollama.embed(model, batch).await?);
+    }
+    Ok(FileSync::Embedded(EmbeddedDocument {
+        hash,
+        language: code_index::language_name(file),
+        chunks,
+        embeddings,
+        symbols,
+    }))
+}
+
+fn record_file_sync(
+    conn: &Connection,
+    report: &mut IndexReport,
+    source_id: i64,
+    model: &str,
+    path: &str,
+    outcome: FileSync,
+) -> Result<(), String> {
+    match outcome {
+        FileSync::Unchanged => report.unchanged += 1,
+        FileSync::Unreadable(reason) => report.failed.push(format!("{}: {}", path, reason)),
+        FileSync::Embedded(document) => {
+            knowledge::store_document(conn, source_id, path, model, &document)?;
+            report.indexed += 1;
+            report.chunks += document.chunks.len();
+        }
+    }
+    Ok(())
+}
+
+// marks a sync as finished or failed and tells the frontend
+fn finish_knowledge_sync(
+    app_handle: &AppHandle,
+    conn: &Connection,
+    source_id: i64,
+    model: &str,
+    result: &Result<IndexReport, String>,
+) -> Result<(), String> {
+    match result {
+        Ok(_) => knowledge::finish_source(conn, source_id, model)?,
+        Err(e) => knowledge::set_source_status(conn, source_id, "failed", Some(e.as_str()))?,
+    }
+    emit_knowledge_status(app_handle, conn, source_id, result.as_ref().ok().cloned());
+    Ok(())
+}
+
+fn emit_knowledge_status(app_handle: &AppHandle, conn: &Connection, source_id: i64, report: Option<IndexReport>) {
+    match knowledge::load_source(conn, source_id) {
+        Ok(Some(source)) => {
+            if let Err(e) = app_handle.emit("knowledge-source-status", KnowledgeSourceStatus { source, report }) {
+                println!("Failed to emit knowledge source status: {}", e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => println!("Failed to load knowledge source {}: {}", source_id, e),
+    }
+}
+
+// walks a knowledge source and embeds every file that is new, changed or was embedded with another model,
+// files that disappeared are dropped from the index
+async fn index_knowledge_source(app_handle: &AppHandle, source_id: i64, force: bool) -> Result<IndexReport, String> {
+    let app_data_dir = get_app_data_dir(app_handle)?;
+    let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+    let model = settings.embedding_model.clone();
+    let max_tokens = settings.knowledge_chunk_tokens.max(32) as usize;
+    
+    let conn = knowledge::open_knowledge_db(&app_data_dir)?;
+    let source = knowledge::load_source(&conn, source_id)
+        .map_err(|e| format!("Failed to load source: {}", e))?
+        .ok_or_else(|| format!("Knowledge source {} not found", source_id))?;
+    let root = PathBuf::from(&source.path);
+    knowledge::set_source_status(&conn, source_id, "indexing", None)?;
+    emit_knowledge_status(app_handle, &conn, source_id, None);
+    
+    // the indexing future owns its own connection, a borrowed one would make it !Send
+    let result: Result<IndexReport, String> = async {
+        let conn = knowledge::open_knowledge_db(&app_data_dir)?;
+        let files = if source.kind == code_index::KIND_REPOSITORY {
+            code_index::collect_files(&root)?
+        } else {
+            knowledge::collect_files(&root)?
+        };
+        let indexed = knowledge::document_states(&conn, source_id)
+            .map_err(|e| format!("Failed to load documents: {}", e))?;
+        let ollama = OllamaService::new(None);
+        let mut report = IndexReport { source_id, ..Default::default() };
+        let mut present = std::collections::HashSet::new();
+        
+        for (processed, file) in files.iter().enumerate() {
+            let path = knowledge::relative_path(&root, file);
+            present.insert(path.clone());
+            let _ = app_handle.emit("knowledge-index-progress", IndexProgress {
+                source_id,
+                processed,
+                total: files.len(),
+                path: path.clone(),
+            });
+            
+            let outcome = embed_knowledge_file(&ollama, &model, max_tokens, file, &path, &indexed, force).await?;
+            record_file_sync(&conn, &mut report, source_id, &model, &path, outcome)?;
+        }
+        
+        report.removed = knowledge::remove_missing_documents(&conn, source_id, &present)?;
+        Ok(report)
+    }.await;
+    
+    match &result {
+        Ok(report) => println!(
+            "Indexed {}: {} files embedded ({} chunks), {} unchanged, {} removed, {} failed",
+            source.path, report.indexed, report.chunks, report.unchanged, report.removed, report.failed.len()
+        ),
+        Err(e) => println!("Failed to index {}: {}", source.path, e),
+    }
+    finish_knowledge_sync(app_handle, &conn, source_id, &model, &result)?;
+    result
+}
+
+// re-indexes the files the watcher saw change, grouped by the source they belong to
+async fn sync_knowledge_paths(app_handle: &AppHandle, paths: std::collections::HashSet<PathBuf>) -> Result<(), String> {
+    let app_data_dir = get_app_data_dir(app_handle)?;
+    let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+    let model = settings.embedding_model.clone();
+    let max_tokens = settings.knowledge_chunk_tokens.max(32) as usize;
+    
+    let sources = {
+        let conn = knowledge::open_knowledge_db(&app_data_dir)?;
+        knowledge::load_sources(&conn).map_err(|e| format!("Failed to load sources: {}", e))?
+    };
+    let mut changes: HashMap<i64, (PathBuf, bool, Vec<PathBuf>)> = HashMap::new();
+    for path in paths {
+        // nested sources are allowed, the innermost one owns the file
+        let owner = sources
+            .iter()
+            .filter(|source| path.starts_with(&source.path))
+            .max_by_key(|source| source.path.len());
+        if let Some(source) = owner {
+            let repository = source.kind == code_index::KIND_REPOSITORY;
+            changes.entry(source.id).or_insert_with(|| (PathBuf::from(&source.path), repository, Vec::new())).2.push(path);
+        }
+    }
+    
+    for (source_id, (root, repository, paths)) in changes {
+        let result: Result<IndexReport, String> = async {
+            let conn = knowledge::open_knowledge_db(&app_data_dir)?;
+            let indexed = knowledge::document_states(&conn, source_id)
+                .map_err(|e| format!("Failed to load documents: {}", e))?;
+            // in a repository only files git doesn't ignore are synced, a fresh walk answers that for nested ignore files too
+            let tracked: Option<std::collections::HashSet<PathBuf>> = if repository {
+                Some(code_index::collect_files(&root)?.into_iter().collect())
+            } else {
+                None
+            };
+            let ollama = OllamaService::new(None);
+            let mut report = IndexReport { source_id, ..Default::default() };
+            
+            for changed in paths {
+                if knowledge::is_ignored(&root, &changed) {
+                    continue;
+                }
+                // a folder moved into the source only reports itself, not the files inside it
+                let files = if changed.is_dir() {
+                    match &tracked {
+                        Some(tracked) => tracked.iter().filter(|file| file.starts_with(&changed)).cloned().collect(),
+                        None => knowledge::collect_files(&changed)?,
+                    }
+                } else if changed.is_file()
+                    && knowledge::is_indexable(&changed)
+                    && tracked.as_ref().is_none_or(|tracked| tracked.contains(&changed))
+                {
+                    vec![changed]
+                } else {
+                    if !changed.exists() {
+                        report.removed += knowledge::remove_documents_under(&conn, source_id, &knowledge::relative_path(&root, &changed))?;
+                    }
+                    continue;
+                };
+                for file in files {
+                    let path = knowledge::relative_path(&root, &file);
+                    let outcome = embed_knowledge_file(&ollama, &model, max_tokens, &file, &path, &indexed, false).await?;
+                    record_file_sync(&conn, &mut report, source_id, &model, &path, outcome)?;
+                }
+            }
+            Ok(report)
+        }.await;
+        
+        let conn = knowledge::open_knowledge_db(&app_data_dir)?;
+        match &result {
+            Ok(report) if report.indexed + report.removed + report.failed.len() == 0 => continue,
+            Ok(report) => println!(
+                "Synced {}: {} files embedded, {} removed, {} failed",
+                root.display(), report.indexed, report.removed, report.failed.len()
+            ),
+            Err(e) => println!("Failed to sync {}: {}", root.display(), e),
+        }
+        finish_knowledge_sync(app_handle, &conn, source_id, &model, &result)?;
+    }
+    Ok(())
+}
+
+// watches every knowledge source for changes, and catches up on edits made while the app was closed
+pub fn start_knowledge_watcher(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let sources = get_app_data_dir(&app_handle).and_then(|app_data_dir| {
+            let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+            if !settings.knowledge_watch_enabled {
+                return Err("watching is turned off".to_string());
+            }
+            let conn = knowledge::open_knowledge_db(&app_data_dir)?;
+            knowledge::load_sources(&conn).map_err(|e| format!("Failed to load sources: {}", e))
+        });
+        let sources = match sources {
+            Ok(sources) => sources,
+            Err(e) => {
+                println!("Failed to start the knowledge base watcher: {}", e);
+                return;
+            }
+        };
+        
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        if let Err(e) = watcher::start(sender) {
+            println!("Failed to start the knowledge base watcher: {}", e);
+            return;
+        }
+        for source in &sources {
+            if let Err(e) = watcher::watch(std::path::Path::new(&source.path)) {
+                println!("Failed to watch {}: {}", source.path, e);
+            }
+        }
+        for source in &sources {
+            if let Err(e) = index_knowledge_source(&app_handle, source.id, false).await {
+                println!("Failed to catch up on {}: {}", source.path, e);
+            }
+        }
+        
+        while let Some(paths) = watcher::next_changes(&mut receiver).await {
+            if let Err(e) = sync_knowledge_paths(&app_handle, paths).await {
+                println!("Failed to sync the knowledge base: {}", e);
+            }
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn get_knowledge_sources(app_handle: AppHandle) -> Result<Vec<KnowledgeSource>, String> {
+    let conn = knowledge::open_knowledge_db(&get_app_data_dir(&app_handle)?)?;
+    knowledge::load_sources(&conn).map_err(|e| format!("Failed to load sources: {}", e))
+}
+
+#[tauri::command]
+pub async fn add_knowledge_source(app_handle: AppHandle, path: String) -> Result<IndexReport, String> {
+    println!("add_knowledge_source called with: {}", path);
+    let source_id = {
+        let conn = knowledge::open_knowledge_db(&get_app_data_dir(&app_handle)?)?;
+        knowledge::add_source(&conn, std::path::Path::new(&path))?
+    };
+    let report = index_knowledge_source(&app_handle, source_id, false).await;
+    if let Err(e) = watch_knowledge_source(&app_handle, source_id) {
+        println!("Failed to watch knowledge source {}: {}", source_id, e);
+    }
+    report
+}
+
+#[tauri::command]
+pub async fn remove_knowledge_source(app_handle: AppHandle, source_id: i64) -> Result<(), String> {
+    println!("remove_knowledge_source called for: {}", source_id);
+    let conn = knowledge::open_knowledge_db(&get_app_data_dir(&app_handle)?)?;
+    if let Some(source) = knowledge::load_source(&conn, source_id).map_err(|e| format!("Failed to load source: {}", e))? {
+        watcher::unwatch(std::path::Path::new(&source.path));
+    }
+    knowledge::remove_source(&conn, source_id)
+}
+
+fn watch_knowledge_source(app_handle: &AppHandle, source_id: i64) -> Result<(), String> {
+    let conn = knowledge::open_knowledge_db(&get_app_data_dir(app_handle)?)?;
+    match knowledge::load_source(&conn, source_id).map_err(|e| format!("Failed to load source: {}", e))? {
+        Some(source) => watcher::watch(std::path::Path::new(&source.path)),
+        None => Ok(()),
+    }
+}
+
+// force re-embeds every file, otherwise only new and changed files are
+#[tauri::command]
+pub async fn reindex_knowledge_source(app_handle: AppHandle, source_id: i64, force: bool) -> Result<IndexReport, String> {
+    println!("reindex_knowledge_source called for: {} (force: {})", source_id, force);
+    index_knowledge_source(&app_handle, source_id, force).await
+}
+
+async fn search_knowledge(app_data_dir: &std::path::Path, model: &str, query: &str, limit: usize) -> Result<Vec<KnowledgeMatch>, String> {
+    let embedding = OllamaService::new(None)
+        .embed(model, &[query.to_string()])
+        .await?
+        .remove(0);
+    
+    let conn = knowledge::open_knowledge_db(app_data_dir)?;
+    knowledge::search(&conn, model, &embedding, query, limit)
+        .map_err(|e| format!("Failed to search knowledge base: {}", e))
+}
+
+#[tauri::command]
+pub async fn search_knowledge_base(app_handle: AppHandle, query: String, limit: Option<usize>) -> Result<Vec<KnowledgeMatch>, String> {
+    let app_data_dir = get_app_data_dir(&app_handle)?;
+    let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+    search_knowledge(&app_data_dir, &settings.embedding_model, &query, limit.unwrap_or(8)).await
+}
+
+// code excerpts with their paths and line ranges from indexed repositories, or from one repository
+#[tauri::command]
+pub async fn search_code(app_handle: AppHandle, query: String, source_id: Option<i64>, limit: Option<usize>) -> Result<Vec<KnowledgeMatch>, String> {
+    let app_data_dir = get_app_data_dir(&app_handle)?;
+    let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+    let embedding = OllamaService::new(None)
+        .embed(&settings.embedding_model, std::slice::from_ref(&query))
+        .await?
+        .remove(0);
+    
+    let conn = knowledge::open_knowledge_db(&app_data_dir)?;
+    code_index::search(&conn, &settings.embedding_model, &embedding, &query, source_id, limit.unwrap_or(8))
+        .map_err(|e| format!("Failed to search code: {}", e))
+}
+
+// where a function, type or module is defined, matched by name ignoring case
+#[tauri::command]
+pub async fn find_code_symbols(app_handle: AppHandle, name: String, source_id: Option<i64>) -> Result<Vec<SymbolMatch>, String> {
+    let conn = knowledge::open_knowledge_db(&get_app_data_dir(&app_handle)?)?;
+    let found = code_index::find_symbols(&conn, &[name], source_id)
+        .map_err(|e| format!("Failed to look up symbols: {}", e))?;
+    Ok(found.into_iter().map(|(_, symbol)| symbol).collect())
+}
+
+#[tauri::command]
+pub async fn get_personas(app_handle: AppHandle) -> Result<Vec<Persona>, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    personas::load_personas(&conn).map_err(|e| format!("Failed to load personas: {}", e))
+}
+
+fn load_persona(conn: &Connection, persona_id: i64) -> Result<Persona, String> {
+    personas::load_persona(conn, persona_id)
+        .map_err(|e| format!("Failed to load persona: {}", e))?
+        .ok_or_else(|| format!("Persona {} not found", persona_id))
+}
+
+#[tauri::command]
+pub async fn create_persona(app_handle: AppHandle, persona: PersonaDraft) -> Result<Persona, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let persona_id = personas::create_persona(&conn, persona)?;
+    load_persona(&conn, persona_id)
+}
+
+// takes effect from the next turn of every conversation using the persona
+#[tauri::command]
+pub async fn update_persona(app_handle: AppHandle, persona_id: i64, persona: PersonaDraft) -> Result<Persona, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    personas::update_persona(&conn, persona_id, persona)?;
+    load_persona(&conn, persona_id)
+}
+
+#[tauri::command]
+pub async fn delete_persona(app_handle: AppHandle, persona_id: i64) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    personas::delete_persona(&conn, persona_id)
+}
+
+// attaches a persona to a conversation, None detaches it, returns the conversation with its new model
+#[tauri::command]
+pub async fn set_conversation_persona(app_handle: AppHandle, conversation_id: String, persona_id: Option<i64>) -> Result<Conversation, String> {
+    println!("set_conversation_persona called for: {} with persona: {:?}", conversation_id, persona_id);
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    ensure_writable(&conn, &conversation_id)?;
+    let persona = persona_id.map(|id| load_persona(&conn, id)).transpose()?;
+    personas::attach(&conn, &conversation_id, persona.as_ref())?;
+    read_conversation(&conn, &conversation_id)
+}
+
+#[tauri::command]
+pub async fn get_prompt_templates(app_handle: AppHandle) -> Result<Vec<PromptTemplate>, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    templates::load_templates(&conn).map_err(|e| format!("Failed to load templates: {}", e))
+}
+
+fn load_prompt_template(conn: &Connection, template_id: i64) -> Result<PromptTemplate, String> {
+    templates::load_template(conn, template_id)
+        .map_err(|e| format!("Failed to load template: {}", e))?
+        .ok_or_else(|| format!("Template {} not found", template_id))
+}
+
+#[tauri::command]
+pub async fn create_prompt_template(app_handle: AppHandle, template: TemplateDraft) -> Result<PromptTemplate, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let template_id = templates::create_template(&conn, template)?;
+    load_prompt_template(&conn, template_id)
+}
+
+#[tauri::command]
+pub async fn update_prompt_template(app_handle: AppHandle, template_id: i64, template: TemplateDraft) -> Result<PromptTemplate, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    templates::update_template(&conn, template_id, template)?;
+    load_prompt_template(&conn, template_id)
+}
+
+#[tauri::command]
+pub async fn delete_prompt_template(app_handle: AppHandle, template_id: i64) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    templates::delete_template(&conn, template_id)
+}
+
+// the template's body with its placeholders filled in, along with its default model and options
+// fails listing every variable without a value
+#[tauri::command]
+pub async fn render_prompt_template(app_handle: AppHandle, template_id: i64, values: HashMap<String, String>) -> Result<RenderedTemplate, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let template = load_prompt_template(&conn, template_id)?;
+    Ok(RenderedTemplate {
+        prompt: templates::render(&template.body, &values)?,
+        model: template.model,
+        options: template.options,
+    })
+}
+
+// writes the given templates, or all of them, to a template pack file, a directory gets prompt-templates.json
+#[tauri::command]
+pub async fn export_prompt_templates(app_handle: AppHandle, path: String, template_ids: Option<Vec<i64>>) -> Result<String, String> {
+    println!("export_prompt_templates called with: {}", path);
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let mut selected = templates::load_templates(&conn)
+        .map_err(|e| format!("Failed to load templates: {}", e))?;
+    if let Some(ids) = &template_ids {
+        selected.retain(|t| ids.contains(&t.id));
+    }
+    if selected.is_empty() {
+        return Err("No templates to export".to_string());
+    }
+    
+    let mut path = PathBuf::from(&path);
+    if path.is_dir() {
+        path = path.join("prompt-templates.json");
+    }
+    let json = serde_json::to_string_pretty(&templates::to_pack(&selected))
+        .map_err(|e| format!("Failed to serialize templates: {}", e))?;
+    std::fs::write(&path, json)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path.display().to_string())
+}
+
+// adds the templates of a pack file, ones whose name is taken are replaced only when replace is set
+#[tauri::command]
+pub async fn import_prompt_templates(app_handle: AppHandle, path: String, replace: Option<bool>) -> Result<TemplateImportReport, String> {
+    println!("import_prompt_templates called with: {}", path);
+    let drafts = templates::read_pack(std::path::Path::new(&path))?;
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    templates::import_drafts(&conn, drafts, replace.unwrap_or(false))
+}
+
+// text of a pdf, office document, web page or ebook, split by page or section
+#[tauri::command]
+pub async fn extract_document(path: String) -> Result<ExtractedDocument, String> {
+    tauri::async_runtime::spawn_blocking(move || extract::extract_file(std::path::Path::new(&path)))
+        .await
+        .map_err(|e| format!("Failed to extract document: {}", e))?
+}
+
+// attaches a file to a message, or stages it for the next prompt when no message is given
+// stage first and pass the returned ids to stream_prompt to send attachments with a new prompt
+#[tauri::command]
+pub async fn attach_file(app_handle: AppHandle, path: String, message_id: Option<i64>) -> Result<Attachment, String> {
+    let prepared = tauri::async_runtime::spawn_blocking(move || attachments::prepare_file(std::path::Path::new(&path)))
+        .await
+        .map_err(|e| format!("Failed to read attachment: {}", e))??;
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    if let Some(message_id) = message_id {
+        ensure_message_writable(&conn, message_id)?;
+    }
+    attachments::store(&conn, &attachments::blob_dir(&get_app_data_dir(&app_handle)?), message_id, prepared)
+}
+
+// same as attach_file for an image that only exists in memory, like a pasted screenshot
+#[tauri::command]
+pub async fn attach_image_data(app_handle: AppHandle, name: String, data: String, message_id: Option<i64>) -> Result<Attachment, String> {
+    let prepared = attachments::prepare_image_data(&name, &data)?;
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    if let Some(message_id) = message_id {
+        ensure_message_writable(&conn, message_id)?;
+    }
+    attachments::store(&conn, &attachments::blob_dir(&get_app_data_dir(&app_handle)?), message_id, prepared)
+}
+
+#[tauri::command]
+pub async fn remove_attachment(app_handle: AppHandle, attachment_id: i64) -> Result<(), String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    if !attachments::remove(&conn, attachment_id)? {
+        return Err(format!("Attachment {} not found", attachment_id));
+    }
+    sweep_attachments(&app_handle, &conn);
+    Ok(())
+}
+
+// the attachment's bytes base64 encoded, for previews
+#[tauri::command]
+pub async fn get_attachment_data(app_handle: AppHandle, attachment_id: i64) -> Result<String, String> {
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let attachment = attachments::load(&conn, attachment_id)?
+        .ok_or_else(|| format!("Attachment {} not found", attachment_id))?;
+    let bytes = attachments::read_blob(&attachments::blob_dir(&get_app_data_dir(&app_handle)?), &attachment)?;
+    Ok(base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes))
+}
+
+// removes blobs left behind by deleted messages, a failure only leaves files around so it is just logged
+fn sweep_attachments(app_handle: &AppHandle, conn: &Connection) {
+    let result = get_app_data_dir(app_handle)
+        .and_then(|app_data_dir| attachments::sweep(conn, &attachments::blob_dir(&app_data_dir)));
+    match result {
+        Ok(removed) if removed > 0 => println!("Removed {} unused attachment files", removed),
+        Ok(_) => {}
+        Err(e) => println!("Failed to clean up attachments: {}", e),
+    }
+}
+
+// the excerpts most relevant to a prompt, empty when retrieval is off for it or nothing scores high enough
+// requested overrides the retrieval setting for a single prompt
+async fn retrieve_knowledge(app_handle: &AppHandle, prompt: &str, requested: Option<bool>) -> Result<Vec<KnowledgeMatch>, String> {
+    let app_data_dir = get_app_data_dir(app_handle)?;
+    let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+    if !requested.unwrap_or(settings.retrieval_enabled) {
+        return Ok(Vec::new());
+    }
+    
+    let limit = settings.retrieval_top_k.max(1) as usize;
+    let matches = search_knowledge(&app_data_dir, &settings.embedding_model, prompt, limit).await?;
+    Ok(retrieval::relevant(matches, settings.retrieval_min_score as f32))
+}
+
+// messages embedded per request to Ollama
+const MESSAGE_EMBED_BATCH: usize = 16;
+// candidates taken from each ranking before fusing
+const SEARCH_CANDIDATES: usize = 50;
+
+// embeds queued messages in the background, the queue is a table so work left over at shutdown
+// is picked up on the next start
+pub fn start_embedding_worker(app_handle: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let requeued = get_app_data_dir(&app_handle).and_then(|app_data_dir| {
+            let settings = settings::load_settings(&settings::open_preferences_db(&app_data_dir)?)?;
+            let conn = init_conversations_db(&app_handle)
+                .map_err(|e| format!("Failed to open database: {}", e))?;
+            semantic_search::enqueue_stale(&conn, &settings.embedding_model)
+                .map_err(|e| format!("Failed to queue messages: {}", e))
+        });
+        if let Err(e) = requeued {
+            println!("Failed to queue messages for a new embedding model: {}", e);
+        }
+        
+        loop {
+            let delay = match embed_queued_messages(&app_handle).await {
+                Ok(0) => std::time::Duration::from_secs(15),
+                Ok(_) => std::time::Duration::ZERO,
+                // Ollama is down, the model isn't pulled or the database is locked, none of which fix themselves quickly
+                Err(e) => {
+                    println!("Message embedding paused: {}", e);
+                    std::time::Duration::from_secs(300)
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    });
+}
+
+// processes one batch of the queue, returns how many messages left it
+async fn embed_queued_messages(app_handle: &AppHandle) -> Result<usize, String> {
+    let settings = settings::load_settings(&settings::open_preferences_db(&get_app_data_dir(app_handle)?)?)?;
+    if !settings.semantic_search_enabled {
+        return Ok(0);
+    }
+    let model = settings.embedding_model;
+    
+    // split the batch into messages that need a new embedding and ones whose embedding is still current
+    let (batch, stale) = {
+        let conn = init_conversations_db(app_handle)
+            .map_err(|e| format!("Failed to open database: {}", e))?;
+        let batch = semantic_search::next_batch(&conn, MESSAGE_EMBED_BATCH)?;
+        let mut stale = Vec::new();
+        for (index, message) in batch.iter().enumerate() {
+            let hash = knowledge::content_hash(message.content.as_bytes());
+            let current = semantic_search::embedded_state(&conn, message.id)?
+                .is_some_and(|(h, m)| h == hash && m == model);
+            if !current && !message.content.trim().is_empty() {
+                stale.push(index);
+            }
+        }
+        (batch, stale)
+    };
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    
+    let inputs: Vec<String> = stale.iter().map(|index| batch[*index].content.clone()).collect();
+    let embeddings = if inputs.is_empty() {
+        Vec::new()
+    } else {
+        OllamaService::new(None).embed(&model, &inputs).await?
+    };
+    
+    let conn = init_conversations_db(app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    for (index, message) in batch.iter().enumerate() {
+        if message.content.trim().is_empty() {
+            semantic_search::remove_message_index(&conn, message.id)
+                .map_err(|e| format!("Failed to update search index: {}", e))?;
+            continue;
+        }
+        let embedding = stale.iter().position(|i| *i == index).map(|position| embeddings[position].as_slice());
+        let hash = knowledge::content_hash(message.content.as_bytes());
+        semantic_search::store_message_index(&conn, message, &model, &hash, embedding)?;
+    }
+    
+    let remaining = semantic_search::queue_length(&conn)
+        .map_err(|e| format!("Failed to read embedding queue: {}", e))?;
+    println!("Indexed {} messages ({} embedded), {} still queued", batch.len(), inputs.len(), remaining);
+    Ok(batch.len())
+}
+
+// finds messages by meaning and by keywords, the two rankings are fused and the best hit of each
+// conversation is returned, conversations in the trash are left out
+#[tauri::command]
+pub async fn semantic_search_conversations(app_handle: AppHandle, query: String, limit: Option<usize>) -> Result<Vec<SemanticSearchHit>, String> {
+    println!("semantic_search_conversations called with: {}", query);
+    
+    let settings = settings::load_settings(&settings::open_preferences_db(&get_app_data_dir(&app_handle)?)?)?;
+    let limit = limit.unwrap_or(20).clamp(1, SEARCH_CANDIDATES);
+    
+    // without an embedding the search still has its keyword half
+    let query_embedding = match OllamaService::new(None).embed(&settings.embedding_model, std::slice::from_ref(&query)).await {
+        Ok(mut embeddings) => Some(embeddings.remove(0)),
+        Err(e) => {
+            println!("Searching by keywords only, failed to embed the query: {}", e);
+            None
+        }
+    };
+    
+    let conn = init_conversations_db(&app_handle)
+        .map_err(|e| format!("Failed to open database: {}", e))?;
+    let vector = match &query_embedding {
+        Some(embedding) => semantic_search::vector_ranking(&conn, &settings.embedding_model, embedding, SEARCH_CANDIDATES)?,
+        None => Vec::new(),
+    };
+    let keyword = if crypto::status(&conn)?.enabled {
+        semantic_search::encrypted_keyword_ranking(&conn, &query, SEARCH_CANDIDATES)?
+    } else {
+        semantic_search::keyword_ranking(&conn, &query, SEARCH_CANDIDATES)
+            .map_err(|e| format!("Failed to search messages: {}", e))?
+    };
+    
+    let mut stmt = conn.prepare(
+        "SELECT m.conversation_id, c.title, m.role, m.timestamp, m.content
+         FROM messages m JOIN conversations c ON c.id = m.conversation_id
+         WHERE m.id = ?1 AND c.deleted_at IS NULL"
+    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    
+    let mut hits: Vec<SemanticSearchHit> = Vec::new();
+    for (message_id, score, similarity, keyword_rank) in semantic_search::fuse(&vector, &keyword) {
+        if hits.len() >= limit {
+            break;
+        }
+        let row = stmt.query_row(params![message_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, String>(3)?, open_content(row, 4)?))
+        });
+        let (conversation_id, conversation_title, role, timestamp, content) = match row {
+            Ok(row) => row,
+            Err(rusqlite::Error::QueryReturnedNoRows) => continue,
+            Err(e) => return Err(format!("Failed to load message: {}", e)),
+        };
+        if hits.iter().any(|hit| hit.conversation_id == conversation_id) {
+            continue;
+        }
+        hits.push(SemanticSearchHit {
+            conversation_id,
+            conversation_title,
+            message_id,
+            role,
+            timestamp,
+            snippet: truncate_preview(&content, 200),
+            score,
+            similarity,
+            keyword_rank,
+        });
+    }
+    
+    Ok(hits)
+}
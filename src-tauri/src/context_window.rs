@@ -0,0 +1,181 @@
+use crate::ollama_service::ChatMessage;
+use crate::settings::AppSettings;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+// tokens the chat template adds around every message
+const MESSAGE_OVERHEAD: i64 = 4;
+// share of the window at which the frontend is warned
+const WARNING_RATIO: f64 = 0.9;
+// Ollama's own num_ctx when neither the request nor the modelfile sets one
+const OLLAMA_DEFAULT_WINDOW: i64 = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrimStrategy {
+    DropOldest,
+    KeepLast(usize), // system messages plus the last N messages
+    Summarize,
+}
+
+impl TrimStrategy {
+    pub fn from_settings(settings: &AppSettings) -> Self {
+        match settings.context_strategy.as_str() {
+            "keep_last" => TrimStrategy::KeepLast(settings.context_keep_last.max(1) as usize),
+            "summarize" => TrimStrategy::Summarize,
+            _ => TrimStrategy::DropOldest,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrimStrategy::DropOldest => "drop_oldest",
+            TrimStrategy::KeepLast(_) => "keep_last",
+            TrimStrategy::Summarize => "summarize",
+        }
+    }
+}
+
+// sent to the frontend with the "context-window" event before every request, and again once the reply
+// reports how many tokens the model actually read
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ContextUsage {
+    pub prompt_tokens: i64,
+    pub context_window: i64,
+    pub reserved_tokens: i64, // kept free for the reply
+    pub trimmed_messages: usize,
+    pub strategy: String,
+    pub near_limit: bool,
+    pub measured: bool, // prompt_tokens comes from the model rather than the estimate
+}
+
+// the history that will be sent, plus what had to be left out to make it fit
+pub struct FittedHistory {
+    pub messages: Vec<ChatMessage>,
+    pub dropped: Vec<ChatMessage>,
+    pub prompt_tokens: i64,
+}
+
+// the model's trained context length, GGUF metadata keys it by architecture, e.g. "llama.context_length"
+pub fn model_context_length(model_info: &HashMap<String, Value>) -> Option<i64> {
+    model_info
+        .iter()
+        .find(|(key, _)| key.ends_with(".context_length"))
+        .and_then(|(_, value)| value.as_i64())
+}
+
+// num_ctx set in a modelfile shows up in the "parameters" text of /api/show
+pub fn modelfile_num_ctx(parameters: Option<&str>) -> Option<i64> {
+    parameters?.lines().find_map(|line| {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("num_ctx"), Some(value)) => value.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+// the window the request will actually run with, an explicit num_ctx wins, then the modelfile, then the
+// configured limit capped by what the model was trained for
+pub fn effective_window(
+    options: Option<&HashMap<String, Value>>,
+    modelfile_num_ctx: Option<i64>,
+    model_length: Option<i64>,
+    settings: &AppSettings,
+) -> i64 {
+    if let Some(num_ctx) = options.and_then(|o| o.get("num_ctx")).and_then(|n| n.as_i64()) {
+        return num_ctx;
+    }
+    if let Some(num_ctx) = modelfile_num_ctx {
+        return num_ctx;
+    }
+    let limit = if settings.context_window_limit > 0 { Some(settings.context_window_limit) } else { None };
+    match (model_length, limit) {
+        (Some(length), Some(limit)) => length.min(limit),
+        (Some(length), None) => length,
+        (None, Some(limit)) => limit,
+        (None, None) => OLLAMA_DEFAULT_WINDOW,
+    }
+}
+
+pub fn reserved_tokens(window: i64, settings: &AppSettings) -> i64 {
+    // never reserve more than half the window, small models would have nothing left for the prompt
+    settings.context_reserve_tokens.clamp(0, window / 2)
+}
+
+pub fn message_tokens(message: &ChatMessage, count: &impl Fn(&str) -> i64) -> i64 {
+    count(&message.content) + MESSAGE_OVERHEAD
+}
+
+// drops messages until the history fits the budget, leading system messages and the latest message are always kept
+pub fn fit_history(
+    messages: Vec<ChatMessage>,
+    budget: i64,
+    strategy: &TrimStrategy,
+    count: impl Fn(&str) -> i64,
+) -> FittedHistory {
+    let system_len = messages.iter().take_while(|m| m.role == "system").count();
+    let mut system = messages;
+    let mut turns = system.split_off(system_len);
+    let mut dropped = Vec::new();
+
+    if let TrimStrategy::KeepLast(keep) = strategy {
+        let excess = turns.len().saturating_sub(*keep);
+        dropped.extend(turns.drain(..excess));
+    }
+
+    let system_tokens: i64 = system.iter().map(|m| message_tokens(m, &count)).sum();
+    let mut turn_tokens: Vec<i64> = turns.iter().map(|m| message_tokens(m, &count)).collect();
+    let mut total = system_tokens + turn_tokens.iter().sum::<i64>();
+
+    while total > budget && turns.len() > 1 {
+        dropped.push(turns.remove(0));
+        total -= turn_tokens.remove(0);
+    }
+    // a reply right after the cut would have no prompt, drop it with its turn
+    while !dropped.is_empty() && turns.len() > 1 && turns[0].role == "assistant" {
+        dropped.push(turns.remove(0));
+        total -= turn_tokens.remove(0);
+    }
+
+    system.extend(turns);
+    FittedHistory {
+        messages: system,
+        dropped,
+        prompt_tokens: total,
+    }
+}
+
+pub fn usage(fitted: &FittedHistory, window: i64, reserved: i64, strategy: &TrimStrategy) -> ContextUsage {
+    ContextUsage {
+        prompt_tokens: fitted.prompt_tokens,
+        context_window: window,
+        reserved_tokens: reserved,
+        trimmed_messages: fitted.dropped.len(),
+        strategy: strategy.name().to_string(),
+        near_limit: fitted.prompt_tokens + reserved >= (window as f64 * WARNING_RATIO) as i64,
+        measured: false,
+    }
+}
+
+// the estimate corrected by Ollama's prompt_eval_count, None when it adds nothing
+// Ollama leaves out the prefix it still had cached, so a lower count says nothing about the window
+pub fn measured(usage: &ContextUsage, prompt_eval_count: Option<i64>) -> Option<ContextUsage> {
+    let prompt_tokens = prompt_eval_count.filter(|count| *count > usage.prompt_tokens)?;
+    Some(ContextUsage {
+        prompt_tokens,
+        near_limit: prompt_tokens + usage.reserved_tokens >= (usage.context_window as f64 * WARNING_RATIO) as i64,
+        measured: true,
+        ..usage.clone()
+    })
+}
+
+// the summary goes right after the leading system messages, where the dropped turns used to start
+pub fn insert_summary(messages: &mut Vec<ChatMessage>, summary: &str) {
+    let position = messages.iter().take_while(|m| m.role == "system").count();
+    messages.insert(position, ChatMessage {
+        role: "system".to_string(),
+        content: format!("Summary of the earlier conversation:\n{}", summary.trim()),
+        images: None,
+    });
+}
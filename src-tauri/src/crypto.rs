@@ -0,0 +1,456 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use argon2::Argon2;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+// application-level encryption of message content with AES-256-GCM
+// a random data key encrypts the content and is itself wrapped by a key derived from the
+// passphrase or keyfile with Argon2id, so changing the secret only rewraps the data key
+// titles stay in plaintext so the sidebar can list and sort conversations while locked
+// attachment blobs are files rather than columns, a sealed one carries an extension so its state is never
+// guessed from its bytes
+
+const CIPHER_PREFIX: &str = "enc:v1:";
+// marks plaintext that happens to start like a stored value, so it is never mistaken for ciphertext
+const ESCAPE_PREFIX: &str = "enc:raw:";
+const NONCE_LEN: usize = 12;
+pub const SEALED_BLOB_EXTENSION: &str = "enc";
+
+enum KeyState {
+    Disabled,
+    Locked,
+    Unlocked(Key<Aes256Gcm>),
+}
+
+// the DB helpers are plain functions on a Connection, so the unlocked key lives here for the whole process
+static KEY_STATE: RwLock<KeyState> = RwLock::new(KeyState::Disabled);
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EncryptionStatus {
+    pub enabled: bool,
+    pub unlocked: bool,
+    pub key_source: Option<String>, // "passphrase" | "keyfile"
+    pub created_at: Option<String>,
+}
+
+// the secret used to unlock, a keyfile is read and used as the passphrase bytes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "kind", content = "value", rename_all = "lowercase")]
+pub enum Secret {
+    Passphrase(String),
+    Keyfile(String),
+}
+
+impl Secret {
+    fn source(&self) -> &'static str {
+        match self {
+            Secret::Passphrase(_) => "passphrase",
+            Secret::Keyfile(_) => "keyfile",
+        }
+    }
+
+    fn material(&self) -> Result<Vec<u8>, String> {
+        match self {
+            Secret::Passphrase(passphrase) if passphrase.chars().count() < 8 => {
+                Err("Passphrase must be at least 8 characters".to_string())
+            }
+            Secret::Passphrase(passphrase) => Ok(passphrase.as_bytes().to_vec()),
+            Secret::Keyfile(path) => {
+                let bytes = std::fs::read(path).map_err(|e| format!("Failed to read keyfile: {}", e))?;
+                if bytes.len() < 32 {
+                    return Err("Keyfile must contain at least 32 bytes".to_string());
+                }
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+pub fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS encryption_keys (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            key_source TEXT NOT NULL,
+            salt TEXT NOT NULL,
+            wrapped_key TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn derive_wrapping_key(secret: &Secret, salt: &[u8]) -> Result<Key<Aes256Gcm>, String> {
+    let mut key = Key::<Aes256Gcm>::default();
+    Argon2::default()
+        .hash_password_into(&secret.material()?, salt, &mut key)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+// the nonce followed by the ciphertext
+fn encrypt_bytes(key: &Key<Aes256Gcm>, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(key)
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| "Encryption failed".to_string())?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+fn decrypt_bytes(key: &Key<Aes256Gcm>, sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < NONCE_LEN {
+        return Err("Corrupt encrypted value".to_string());
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt value".to_string())
+}
+
+fn encrypt_with(key: &Key<Aes256Gcm>, plaintext: &[u8]) -> Result<String, String> {
+    Ok(BASE64.encode(encrypt_bytes(key, plaintext)?))
+}
+
+fn decrypt_with(key: &Key<Aes256Gcm>, encoded: &str) -> Result<Vec<u8>, String> {
+    let sealed = BASE64.decode(encoded).map_err(|_| "Corrupt encrypted value".to_string())?;
+    decrypt_bytes(key, &sealed)
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(CIPHER_PREFIX)
+}
+
+// plaintext is stored as is unless it starts with one of the prefixes
+fn escape(plaintext: &str) -> String {
+    if plaintext.starts_with(CIPHER_PREFIX) || plaintext.starts_with(ESCAPE_PREFIX) {
+        format!("{}{}", ESCAPE_PREFIX, plaintext)
+    } else {
+        plaintext.to_string()
+    }
+}
+
+fn unescape(stored: &str) -> &str {
+    stored.strip_prefix(ESCAPE_PREFIX).unwrap_or(stored)
+}
+
+fn encrypt_value(key: &Key<Aes256Gcm>, plaintext: &[u8]) -> Result<String, String> {
+    Ok(format!("{}{}", CIPHER_PREFIX, encrypt_with(key, plaintext)?))
+}
+
+fn decrypt_value(key: &Key<Aes256Gcm>, encoded: &str) -> Result<String, String> {
+    String::from_utf8(decrypt_with(key, encoded)?)
+        .map_err(|_| "Decrypted value is not valid text".to_string())
+}
+
+// encrypts content before it is written, plaintext is only escaped when encryption is off
+pub fn seal(plaintext: &str) -> Result<String, String> {
+    match &*KEY_STATE.read().unwrap() {
+        KeyState::Disabled => Ok(escape(plaintext)),
+        KeyState::Locked => Err("The conversation database is locked".to_string()),
+        KeyState::Unlocked(key) => encrypt_value(key, plaintext.as_bytes()),
+    }
+}
+
+// decrypts content after it is read, values written before encryption was enabled pass through
+pub fn open(stored: &str) -> Result<String, String> {
+    if let Some(plaintext) = stored.strip_prefix(ESCAPE_PREFIX) {
+        return Ok(plaintext.to_string());
+    }
+    let Some(encoded) = stored.strip_prefix(CIPHER_PREFIX) else {
+        return Ok(stored.to_string());
+    };
+    match &*KEY_STATE.read().unwrap() {
+        KeyState::Unlocked(key) => decrypt_value(key, encoded),
+        // nothing is encrypted while encryption is off, this is plaintext from before values were escaped
+        KeyState::Disabled => Ok(stored.to_string()),
+        KeyState::Locked => Err("The conversation database is locked".to_string()),
+    }
+}
+
+// encrypts a blob before it is written, returns the bytes to store and whether they are sealed
+pub fn seal_blob(bytes: &[u8]) -> Result<(Vec<u8>, bool), String> {
+    match &*KEY_STATE.read().unwrap() {
+        KeyState::Disabled => Ok((bytes.to_vec(), false)),
+        KeyState::Locked => Err("The conversation database is locked".to_string()),
+        KeyState::Unlocked(key) => Ok((encrypt_bytes(key, bytes)?, true)),
+    }
+}
+
+pub fn open_blob(stored: Vec<u8>, sealed: bool) -> Result<Vec<u8>, String> {
+    if !sealed {
+        return Ok(stored);
+    }
+    match &*KEY_STATE.read().unwrap() {
+        KeyState::Unlocked(key) => decrypt_bytes(key, &stored),
+        _ => Err("The conversation database is locked".to_string()),
+    }
+}
+
+pub fn status(conn: &Connection) -> Result<EncryptionStatus, String> {
+    let meta: Option<(String, String)> = conn.query_row(
+        "SELECT key_source, created_at FROM encryption_keys WHERE id = 1",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional().map_err(|e| e.to_string())?;
+
+    Ok(EncryptionStatus {
+        enabled: meta.is_some(),
+        unlocked: matches!(&*KEY_STATE.read().unwrap(), KeyState::Unlocked(_)),
+        key_source: meta.as_ref().map(|(source, _)| source.clone()),
+        created_at: meta.map(|(_, created_at)| created_at),
+    })
+}
+
+// called at startup, an encrypted database starts locked until unlock is called
+pub fn load_state(conn: &Connection) -> Result<(), String> {
+    let enabled = status(conn)?.enabled;
+    let mut state = KEY_STATE.write().unwrap();
+    *state = match (&*state, enabled) {
+        (KeyState::Unlocked(key), true) => KeyState::Unlocked(*key),
+        (_, true) => KeyState::Locked,
+        (_, false) => KeyState::Disabled,
+    };
+    Ok(())
+}
+
+pub fn unlock(conn: &Connection, secret: &Secret) -> Result<(), String> {
+    let (salt, wrapped): (String, String) = conn.query_row(
+        "SELECT salt, wrapped_key FROM encryption_keys WHERE id = 1",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional().map_err(|e| e.to_string())?
+        .ok_or("Encryption is not enabled")?;
+
+    let salt = BASE64.decode(salt).map_err(|_| "Corrupt key salt".to_string())?;
+    let wrapping_key = derive_wrapping_key(secret, &salt)?;
+    let data_key = decrypt_with(&wrapping_key, &wrapped)
+        .map_err(|_| "Wrong passphrase or keyfile".to_string())?;
+
+    *KEY_STATE.write().unwrap() = KeyState::Unlocked(*Key::<Aes256Gcm>::from_slice(&data_key));
+    Ok(())
+}
+
+pub fn lock(conn: &Connection) -> Result<(), String> {
+    if status(conn)?.enabled {
+        *KEY_STATE.write().unwrap() = KeyState::Locked;
+    }
+    Ok(())
+}
+
+// copies the key out so the lock isn't held while the state is replaced
+fn unlocked_key() -> Option<Key<Aes256Gcm>> {
+    match &*KEY_STATE.read().unwrap() {
+        KeyState::Unlocked(key) => Some(*key),
+        _ => None,
+    }
+}
+
+// wraps a data key with the secret and stores it, replacing any previous key
+fn store_key(conn: &Connection, secret: &Secret, data_key: &Key<Aes256Gcm>) -> Result<(), String> {
+    let salt: [u8; 16] = rand::random();
+    let wrapping_key = derive_wrapping_key(secret, &salt)?;
+    conn.execute(
+        "INSERT INTO encryption_keys (id, key_source, salt, wrapped_key, created_at) VALUES (1, ?1, ?2, ?3, ?4)
+         ON CONFLICT(id) DO UPDATE SET
+            key_source = excluded.key_source,
+            salt = excluded.salt,
+            wrapped_key = excluded.wrapped_key,
+            created_at = excluded.created_at",
+        params![secret.source(), BASE64.encode(salt), encrypt_with(&wrapping_key, data_key)?, Utc::now().to_rfc3339()],
+    ).map_err(|e| format!("Failed to store encryption key: {}", e))?;
+    Ok(())
+}
+
+// every column that holds conversation content
+const ENCRYPTED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("messages", "id", "content"),
+    ("conversations", "id", "preview"),
+    ("conversation_summaries", "id", "content"),
+    ("messages", "id", "citations"),
+    ("attachments", "id", "name"),
+    ("attachments", "id", "text"),
+    ("message_embeddings", "message_id", "embedding"),
+    ("message_embeddings", "message_id", "content_hash"), // a plain hash would confirm guesses of a message
+];
+
+// rewrites every encrypted column through the transform, used for enabling, rotating and disabling
+fn rewrite_content(conn: &Connection, transform: &dyn Fn(&str) -> Result<String, String>) -> Result<usize, String> {
+    let mut rewritten = 0;
+    for (table, key_column, column) in ENCRYPTED_COLUMNS {
+        let rows: Vec<(rusqlite::types::Value, String)> = {
+            let mut stmt = conn.prepare(&format!("SELECT {0}, {1} FROM {2} WHERE {1} IS NOT NULL", key_column, column, table))
+                .map_err(|e| e.to_string())?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(|e| e.to_string())?;
+            rows.collect::<rusqlite::Result<Vec<_>>>().map_err(|e| e.to_string())?
+        };
+        let mut update = conn.prepare(&format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, key_column))
+            .map_err(|e| e.to_string())?;
+        for (key, value) in rows {
+            update.execute(params![transform(&value)?, key]).map_err(|e| e.to_string())?;
+            rewritten += 1;
+        }
+    }
+    Ok(rewritten)
+}
+
+// a blob rewritten next to its final name, moved into place once the database has committed
+struct StagedBlob {
+    staged: PathBuf,
+    target: PathBuf,
+    previous: PathBuf,
+}
+
+// gets the stored bytes of a blob and whether they are sealed, returns the same for the rewritten blob
+type BlobTransform<'a> = dyn Fn(Vec<u8>, bool) -> Result<(Vec<u8>, bool), String> + 'a;
+
+// rewrites every blob below the directory through the transform, nothing is replaced until the blobs are settled
+fn stage_blobs(blob_dir: &Path, transform: &BlobTransform<'_>) -> Result<Vec<StagedBlob>, String> {
+    let mut staged = Vec::new();
+    let result = (|| {
+        let Ok(fans) = std::fs::read_dir(blob_dir) else {
+            return Ok(());
+        };
+        for fan in fans.flatten() {
+            for blob in std::fs::read_dir(fan.path()).into_iter().flatten().flatten() {
+                let path = blob.path();
+                let Some(name) = path.file_name().and_then(|n| n.to_str()).map(|n| n.to_string()) else {
+                    continue;
+                };
+                if name.ends_with(".partial") {
+                    continue;
+                }
+                let sealed_suffix = format!(".{}", SEALED_BLOB_EXTENSION);
+                let hash = name.strip_suffix(&sealed_suffix).unwrap_or(&name);
+                let stored = std::fs::read(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                let (bytes, sealed) = transform(stored, name.ends_with(&sealed_suffix))?;
+                let target = match sealed {
+                    true => path.with_file_name(format!("{}{}", hash, sealed_suffix)),
+                    false => path.with_file_name(hash),
+                };
+                let staged_path = path.with_file_name(format!("{}.rewrite.partial", hash));
+                std::fs::write(&staged_path, bytes)
+                    .map_err(|e| format!("Failed to write {}: {}", staged_path.display(), e))?;
+                staged.push(StagedBlob { staged: staged_path, target, previous: path });
+            }
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(()) => Ok(staged),
+        Err(e) => settle_blobs(staged, Err(e)).map(|_| Vec::new()),
+    }
+}
+
+// moves the staged blobs into place once the database rewrite committed, or drops them when it failed
+// a failed rename leaves that blob unreadable, which only loses the attachment and not the conversation
+fn settle_blobs(staged: Vec<StagedBlob>, result: Result<usize, String>) -> Result<usize, String> {
+    for blob in staged {
+        if result.is_err() {
+            let _ = std::fs::remove_file(&blob.staged);
+        } else if let Err(e) = std::fs::rename(&blob.staged, &blob.target) {
+            println!("Failed to replace {}: {}", blob.target.display(), e);
+        } else if blob.previous != blob.target {
+            let _ = std::fs::remove_file(&blob.previous);
+        }
+    }
+    result
+}
+
+// encrypts an existing plaintext database and its attachment blobs in place
+pub fn enable(conn: &Connection, secret: &Secret, blob_dir: &Path) -> Result<usize, String> {
+    if status(conn)?.enabled {
+        return Err("Encryption is already enabled".to_string());
+    }
+    let data_key = Aes256Gcm::generate_key(OsRng);
+
+    let blobs = stage_blobs(blob_dir, &|stored, sealed| match sealed {
+        true => Ok((stored, true)),
+        false => Ok((encrypt_bytes(&data_key, &stored)?, true)),
+    })?;
+    let result = (|| {
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        store_key(&tx, secret, &data_key)?;
+        // every value is plaintext while encryption is off, even one that looks like ciphertext
+        let rewritten = rewrite_content(&tx, &|value| encrypt_value(&data_key, unescape(value).as_bytes()))?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(rewritten)
+    })();
+    let rewritten = settle_blobs(blobs, result)?;
+
+    *KEY_STATE.write().unwrap() = KeyState::Unlocked(data_key);
+    vacuum(conn);
+    Ok(rewritten)
+}
+
+// re-encrypts everything under a fresh data key wrapped by the new secret, the database must be unlocked
+pub fn rotate(conn: &Connection, new_secret: &Secret, blob_dir: &Path) -> Result<usize, String> {
+    let old_key = unlocked_key().ok_or("Unlock the database before rotating its key")?;
+    let new_key = Aes256Gcm::generate_key(OsRng);
+
+    let blobs = stage_blobs(blob_dir, &|stored, sealed| {
+        let plaintext = match sealed {
+            true => decrypt_bytes(&old_key, &stored)?,
+            false => stored,
+        };
+        Ok((encrypt_bytes(&new_key, &plaintext)?, true))
+    })?;
+    let result = (|| {
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        store_key(&tx, new_secret, &new_key)?;
+        let rewritten = rewrite_content(&tx, &|value| {
+            let plaintext = match value.strip_prefix(CIPHER_PREFIX) {
+                Some(encoded) => decrypt_with(&old_key, encoded)?,
+                None => unescape(value).as_bytes().to_vec(),
+            };
+            encrypt_value(&new_key, &plaintext)
+        })?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(rewritten)
+    })();
+    let rewritten = settle_blobs(blobs, result)?;
+
+    *KEY_STATE.write().unwrap() = KeyState::Unlocked(new_key);
+    vacuum(conn);
+    Ok(rewritten)
+}
+
+// decrypts everything back to plaintext and forgets the key
+pub fn disable(conn: &Connection, blob_dir: &Path) -> Result<usize, String> {
+    let key = unlocked_key().ok_or("Unlock the database before turning encryption off")?;
+
+    let blobs = stage_blobs(blob_dir, &|stored, sealed| match sealed {
+        true => Ok((decrypt_bytes(&key, &stored)?, false)),
+        false => Ok((stored, false)),
+    })?;
+    let result = (|| {
+        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let rewritten = rewrite_content(&tx, &|value| match value.strip_prefix(CIPHER_PREFIX) {
+            Some(encoded) => Ok(escape(&decrypt_value(&key, encoded)?)),
+            None => Ok(value.to_string()),
+        })?;
+        tx.execute("DELETE FROM encryption_keys", []).map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(rewritten)
+    })();
+    let rewritten = settle_blobs(blobs, result)?;
+
+    *KEY_STATE.write().unwrap() = KeyState::Disabled;
+    Ok(rewritten)
+}
+
+// rewritten rows leave their old contents in free pages until the file is vacuumed
+fn vacuum(conn: &Connection) {
+    if let Err(e) = conn.execute_batch("VACUUM;") {
+        println!("Failed to vacuum database after rewriting encrypted content: {}", e);
+    }
+}
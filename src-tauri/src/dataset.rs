@@ -0,0 +1,98 @@
+use crate::commands::Conversation;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+// options for turning conversations into a chat fine-tuning dataset
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DatasetOptions {
+    pub conversation_ids: Option<Vec<String>>,
+    pub tag: Option<String>,
+    pub search: Option<String>, // matched against titles and message content
+    pub system_prompt: Option<String>, // prepended to examples that don't start with a system message
+    pub exclude_thumbs_down: bool,
+    pub only_thumbs_up: bool, // keep only examples whose replies were all rated up
+    pub validation_fraction: f64,
+    pub seed: String, // changes which conversations land in the validation split
+}
+
+impl Default for DatasetOptions {
+    fn default() -> Self {
+        Self {
+            conversation_ids: None,
+            tag: None,
+            search: None,
+            system_prompt: None,
+            exclude_thumbs_down: true,
+            only_thumbs_up: false,
+            validation_fraction: 0.1,
+            seed: "jarvish".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DatasetSummary {
+    pub train_path: String,
+    pub validation_path: Option<String>,
+    pub train_examples: usize,
+    pub validation_examples: usize,
+    pub skipped_conversations: usize,
+}
+
+// builds one {"messages": [...]} line from the active branch of a conversation
+// the example is cut before the first thumbs-down reply, along with the prompt that led to it,
+// and partial replies from cancelled or failed streams end the example as well
+pub fn build_example(conversation: &Conversation, options: &DatasetOptions) -> Option<String> {
+    let mut turns: Vec<(&str, &str, i64)> = Vec::new();
+
+    for message in &conversation.messages {
+        if message.status != "complete" {
+            break;
+        }
+        if options.exclude_thumbs_down && message.rating < 0 {
+            if message.role == "assistant" && turns.last().map(|(role, _, _)| *role == "user").unwrap_or(false) {
+                turns.pop();
+            }
+            break;
+        }
+        match message.role.as_str() {
+            "system" | "user" | "assistant" => turns.push((&message.role, &message.content, message.rating)),
+            _ => continue,
+        }
+    }
+
+    // training examples have to end on a reply
+    while turns.last().map(|(role, _, _)| *role != "assistant").unwrap_or(false) {
+        turns.pop();
+    }
+    if turns.is_empty() {
+        return None;
+    }
+    if options.only_thumbs_up && turns.iter().any(|(role, _, rating)| *role == "assistant" && *rating <= 0) {
+        return None;
+    }
+
+    let mut messages = Vec::new();
+    if let Some(system) = &options.system_prompt {
+        if turns.first().map(|(role, _, _)| *role != "system").unwrap_or(true) {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+    }
+    for (role, content, _) in turns {
+        messages.push(json!({ "role": role, "content": content }));
+    }
+
+    Some(json!({ "messages": messages }).to_string())
+}
+
+// deterministic split so re-exporting the same selection keeps examples on the same side
+pub fn is_validation(conversation_id: &str, options: &DatasetOptions) -> bool {
+    if options.validation_fraction <= 0.0 {
+        return false;
+    }
+    let digest = Sha256::digest(format!("{}:{}", options.seed, conversation_id).as_bytes());
+    let bucket = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) as f64 / u32::MAX as f64;
+    bucket < options.validation_fraction
+}
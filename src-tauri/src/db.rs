@@ -0,0 +1,19 @@
+use rusqlite::{Connection, Result as SqlResult};
+
+// schema helpers shared by the modules that own a part of the conversations database
+
+// adds a column to an existing table if it is missing, returns true when the column was just created
+pub fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> SqlResult<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(|name| name.ok())
+        .any(|name| name == column);
+
+    if exists {
+        return Ok(false);
+    }
+
+    conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    Ok(true)
+}
@@ -0,0 +1,261 @@
+use crate::commands::Conversation;
+use crate::message_tree::MessageNode;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// bump when the JSON export layout changes so importers can tell versions apart
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+    Text,
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            "html" => Ok(ExportFormat::Html),
+            "text" | "txt" => Ok(ExportFormat::Text),
+            other => Err(format!("Unknown export format: {}", other)),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+            ExportFormat::Text => "txt",
+        }
+    }
+}
+
+// everything needed to export one conversation, the active branch plus the full message tree
+pub struct ConversationExport {
+    pub conversation: Conversation,
+    pub all_messages: Vec<MessageNode>,
+    pub tags: Vec<String>,
+    pub folders: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExportStats {
+    pub message_count: usize,
+    pub branch_message_count: usize,
+    pub messages_by_role: HashMap<String, usize>,
+    pub word_count: usize,
+    pub token_count: i64,
+}
+
+// the lossless JSON layout, also read back by the importer
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JsonExport {
+    pub format_version: u32,
+    pub exported_at: String,
+    pub conversation: Conversation,
+    pub all_messages: Vec<MessageNode>,
+    pub tags: Vec<String>,
+    pub folders: Vec<String>,
+    pub stats: ExportStats,
+}
+
+pub fn render(format: ExportFormat, export: &ConversationExport) -> Result<String, String> {
+    match format {
+        ExportFormat::Markdown => Ok(render_markdown(export)),
+        ExportFormat::Json => render_json(export),
+        ExportFormat::Html => Ok(render_html(export)),
+        ExportFormat::Text => Ok(render_text(export)),
+    }
+}
+
+pub fn stats(export: &ConversationExport) -> ExportStats {
+    let messages = &export.conversation.messages;
+    let mut messages_by_role: HashMap<String, usize> = HashMap::new();
+    for message in messages {
+        *messages_by_role.entry(message.role.clone()).or_default() += 1;
+    }
+
+    ExportStats {
+        message_count: messages.len(),
+        branch_message_count: export.all_messages.len().saturating_sub(messages.len()),
+        messages_by_role,
+        word_count: messages.iter().map(|m| m.content.split_whitespace().count()).sum(),
+        token_count: export.conversation.token_count,
+    }
+}
+
+// a file name made from the title, safe on every platform
+pub fn file_name(conversation: &Conversation, format: ExportFormat) -> String {
+    let mut stem: String = conversation.title
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .take(60)
+        .collect();
+    stem = stem.trim_matches('_').to_string();
+    if stem.is_empty() {
+        stem = "conversation".to_string();
+    }
+    format!("{}-{}.{}", stem, conversation.id, format.extension())
+}
+
+fn role_label(role: &str) -> String {
+    let mut chars = role.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn render_markdown(export: &ConversationExport) -> String {
+    let conv = &export.conversation;
+    let mut out = format!("# {}\n\n", conv.title);
+    out.push_str(&format!("- **Model:** {}\n", conv.model));
+    out.push_str(&format!("- **Created:** {}\n", conv.created_at));
+    out.push_str(&format!("- **Updated:** {}\n", conv.updated_at));
+    if !export.tags.is_empty() {
+        out.push_str(&format!("- **Tags:** {}\n", export.tags.join(", ")));
+    }
+    out.push('\n');
+
+    // message content is already markdown, so code fences pass through untouched
+    for message in &conv.messages {
+        out.push_str(&format!("## {}\n\n", role_label(&message.role)));
+        out.push_str(message.content.trim_end());
+        out.push_str("\n\n");
+    }
+    out
+}
+
+fn render_json(export: &ConversationExport) -> Result<String, String> {
+    let json = JsonExport {
+        format_version: EXPORT_FORMAT_VERSION,
+        exported_at: Utc::now().to_rfc3339(),
+        conversation: export.conversation.clone(),
+        all_messages: export.all_messages.clone(),
+        tags: export.tags.clone(),
+        folders: export.folders.clone(),
+        stats: stats(export),
+    };
+    serde_json::to_string_pretty(&json).map_err(|e| format!("Failed to serialize conversation: {}", e))
+}
+
+fn render_text(export: &ConversationExport) -> String {
+    let conv = &export.conversation;
+    let mut out = format!("{}\nModel: {}\nCreated: {}\n\n", conv.title, conv.model, conv.created_at);
+    for message in &conv.messages {
+        out.push_str(&format!("{}:\n{}\n\n", role_label(&message.role), message.content.trim_end()));
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#039;")
+}
+
+// turns message markdown into HTML, fenced code blocks become <pre> and the rest stays as text paragraphs
+fn markdown_to_html(content: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut code: Option<(String, Vec<&str>)> = None;
+
+    let flush_paragraph = |paragraph: &mut Vec<&str>, html: &mut String| {
+        if !paragraph.is_empty() {
+            let lines: Vec<String> = paragraph.iter().map(|line| escape_html(line)).collect();
+            html.push_str(&format!("<p>{}</p>\n", lines.join("<br>\n")));
+            paragraph.clear();
+        }
+    };
+
+    for line in content.lines() {
+        let fence = line.trim_start().starts_with("```");
+        match code.take() {
+            Some((language, lines)) if fence => {
+                let class = if language.is_empty() {
+                    String::new()
+                } else {
+                    format!(" class=\"language-{}\"", escape_html(&language))
+                };
+                html.push_str(&format!("<pre><code{}>{}</code></pre>\n", class, escape_html(&lines.join("\n"))));
+            }
+            Some((language, mut lines)) => {
+                lines.push(line);
+                code = Some((language, lines));
+            }
+            None if fence => {
+                flush_paragraph(&mut paragraph, &mut html);
+                let language = line.trim_start().trim_start_matches('`').trim().to_string();
+                code = Some((language, Vec::new()));
+            }
+            None if line.trim().is_empty() => flush_paragraph(&mut paragraph, &mut html),
+            None => paragraph.push(line),
+        }
+    }
+
+    // an unterminated fence still renders as code
+    if let Some((_, lines)) = code {
+        html.push_str(&format!("<pre><code>{}</code></pre>\n", escape_html(&lines.join("\n"))));
+    }
+    flush_paragraph(&mut paragraph, &mut html);
+    html
+}
+
+fn render_html(export: &ConversationExport) -> String {
+    let conv = &export.conversation;
+    let mut body = String::new();
+    for message in &conv.messages {
+        body.push_str(&format!(
+            "<section class=\"message {}\">\n<h2>{}</h2>\n{}</section>\n",
+            escape_html(&message.role),
+            escape_html(&role_label(&message.role)),
+            markdown_to_html(&message.content)
+        ));
+    }
+
+    let tags = if export.tags.is_empty() {
+        String::new()
+    } else {
+        format!(" &middot; {}", escape_html(&export.tags.join(", ")))
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: -apple-system, "Segoe UI", sans-serif; max-width: 860px; margin: 2rem auto; padding: 0 1rem; color: #1f2328; }}
+header p {{ color: #656d76; }}
+.message {{ border-radius: 8px; padding: 0.5rem 1rem; margin: 1rem 0; }}
+.message.user {{ background: #eef4ff; }}
+.message.assistant {{ background: #f6f8fa; }}
+.message.system {{ background: #fff8e6; }}
+.message h2 {{ font-size: 0.9rem; text-transform: uppercase; color: #656d76; }}
+pre {{ background: #1f2328; color: #f0f3f6; padding: 0.75rem; border-radius: 6px; overflow-x: auto; }}
+</style>
+</head>
+<body>
+<header>
+<h1>{title}</h1>
+<p>{model} &middot; {created}{tags}</p>
+</header>
+{body}</body>
+</html>
+"#,
+        title = escape_html(&conv.title),
+        model = escape_html(&conv.model),
+        created = escape_html(&conv.created_at),
+        tags = tags,
+        body = body
+    )
+}
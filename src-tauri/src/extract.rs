@@ -0,0 +1,899 @@
+use lopdf::Document;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+// plain text out of binary and markup documents, split into sections that remember where they came
+// from (a page, a heading, a chapter) so excerpts and citations can point back into the file
+
+// files above this are refused before anything is parsed
+pub const MAX_DOCUMENT_BYTES: u64 = 50 * 1024 * 1024;
+// text past this is dropped and the document marked truncated
+const MAX_EXTRACTED_CHARS: usize = 2_000_000;
+// no single archive member is inflated past this, so a zip bomb fails instead of filling memory
+const MAX_ENTRY_BYTES: u64 = 64 * 1024 * 1024;
+// longer lines are wrapped, pdf pages often come out as a single line the chunker couldn't split
+const MAX_LINE_CHARS: usize = 500;
+// word 97-2003 files and password protected office files are both OLE compound files
+const OLE_MAGIC: &[u8] = &[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1];
+// epub "encryption" that only obfuscates embedded fonts, the text itself is readable
+const FONT_OBFUSCATION: &[&str] = &["http://www.idpf.org/2008/embedding", "http://ns.adobe.com/pdf/enc#RC"];
+
+const HTML_SKIPPED_TAGS: &[&str] = &["script", "style", "noscript", "template", "svg", "iframe", "object"];
+const HTML_BLOCK_TAGS: &[&str] = &[
+    "p", "div", "section", "article", "header", "footer", "nav", "aside", "main", "blockquote", "pre",
+    "table", "ul", "ol", "dl", "dd", "dt", "figure", "figcaption", "h4", "h5", "h6", "hr", "form", "address",
+];
+const HTML_LINE_TAGS: &[&str] = &["br", "li", "tr"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentFormat {
+    Pdf,
+    Docx,
+    Odt,
+    Html,
+    Epub,
+}
+
+impl DocumentFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "pdf" => Some(DocumentFormat::Pdf),
+            "docx" => Some(DocumentFormat::Docx),
+            "odt" => Some(DocumentFormat::Odt),
+            "html" | "htm" | "xhtml" => Some(DocumentFormat::Html),
+            "epub" => Some(DocumentFormat::Epub),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExtractedSection {
+    pub anchor: Option<String>, // "page 3", a heading or "chapter 2", None for text before the first heading
+    pub level: usize,           // 1 for pages and chapters, the heading level otherwise
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ExtractedDocument {
+    pub format: DocumentFormat,
+    pub title: Option<String>,
+    pub sections: Vec<ExtractedSection>,
+    pub truncated: bool,
+}
+
+impl ExtractedDocument {
+    // the whole text with each anchor in brackets, the form a document takes when it is inlined into a prompt
+    pub fn text(&self) -> String {
+        let parts: Vec<String> = self
+            .sections
+            .iter()
+            .map(|section| match &section.anchor {
+                Some(anchor) if !section.text.is_empty() => format!("[{}]\n{}", anchor, section.text),
+                Some(anchor) => format!("[{}]", anchor),
+                None => section.text.clone(),
+            })
+            .collect();
+        parts.join("\n\n")
+    }
+
+    // anchors become markdown headings so the chunker keeps them as the heading of every chunk
+    pub fn to_markdown(&self) -> String {
+        let parts: Vec<String> = self
+            .sections
+            .iter()
+            .map(|section| match &section.anchor {
+                Some(anchor) => format!("{} {}\n\n{}", "#".repeat(section.level.clamp(1, 6)), anchor, section.text),
+                None => section.text.clone(),
+            })
+            .collect();
+        parts.join("\n\n")
+    }
+}
+
+// collects sections until the character budget runs out
+struct SectionBuilder {
+    sections: Vec<ExtractedSection>,
+    chars: usize,
+    truncated: bool,
+}
+
+impl SectionBuilder {
+    fn new() -> Self {
+        SectionBuilder { sections: Vec::new(), chars: 0, truncated: false }
+    }
+
+    // false once the budget is used up, callers stop reading then
+    fn add(&mut self, anchor: Option<String>, level: usize, text: &str) -> bool {
+        if self.truncated {
+            return false;
+        }
+        let mut text = clean_text(text);
+        let anchor = anchor.map(|a| collapse_whitespace(&a)).filter(|a| !a.is_empty());
+        if text.is_empty() && anchor.is_none() {
+            return true;
+        }
+
+        let length = text.chars().count();
+        if self.chars + length > MAX_EXTRACTED_CHARS {
+            text = text.chars().take(MAX_EXTRACTED_CHARS - self.chars).collect();
+            self.truncated = true;
+        }
+        self.chars += text.chars().count();
+        self.sections.push(ExtractedSection { anchor, level, text });
+        !self.truncated
+    }
+
+    fn finish(self, format: DocumentFormat, title: Option<String>, name: &str) -> Result<ExtractedDocument, String> {
+        if self.sections.iter().all(|s| s.text.is_empty()) {
+            return Err(format!("No text found in {}", name));
+        }
+        let title = title.map(|t| collapse_whitespace(&t)).filter(|t| !t.is_empty());
+        Ok(ExtractedDocument { format, title, sections: self.sections, truncated: self.truncated })
+    }
+}
+
+pub fn is_extractable(path: &Path) -> bool {
+    DocumentFormat::from_path(path).is_some()
+}
+
+pub fn extract_file(path: &Path) -> Result<ExtractedDocument, String> {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_else(|| path.display().to_string());
+    let Some(format) = DocumentFormat::from_path(path) else {
+        return Err(format!("Unsupported document type for {}, expected PDF, DOCX, ODT, HTML or EPUB", name));
+    };
+
+    let size = std::fs::metadata(path)
+        .map_err(|e| format!("Failed to read {}: {}", name, e))?
+        .len();
+    if size > MAX_DOCUMENT_BYTES {
+        return Err(format!(
+            "{} is {} MB, documents larger than {} MB are not extracted",
+            name,
+            size / (1024 * 1024),
+            MAX_DOCUMENT_BYTES / (1024 * 1024)
+        ));
+    }
+
+    match format {
+        DocumentFormat::Pdf => extract_pdf(path, &name),
+        DocumentFormat::Docx => extract_docx(path, &name),
+        DocumentFormat::Odt => extract_odt(path, &name),
+        DocumentFormat::Html => {
+            let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+            let (title, sections) = html_to_sections(&String::from_utf8_lossy(&bytes));
+            let mut builder = SectionBuilder::new();
+            for section in sections {
+                if !builder.add(section.anchor, section.level, &section.text) {
+                    break;
+                }
+            }
+            builder.finish(DocumentFormat::Html, title, &name)
+        }
+        DocumentFormat::Epub => extract_epub(path, &name),
+    }
+}
+
+fn extract_pdf(path: &Path, name: &str) -> Result<ExtractedDocument, String> {
+    // lopdf already tries the empty password, which is all most "protected" pdfs use
+    let document = Document::load(path).map_err(|e| match e {
+        lopdf::Error::InvalidPassword | lopdf::Error::Decryption(_) => format!("{} is password protected", name),
+        e => format!("Failed to parse {}: {}", name, e),
+    })?;
+    if document.is_encrypted() {
+        return Err(format!("{} is password protected", name));
+    }
+
+    let mut builder = SectionBuilder::new();
+    for page in document.get_pages().keys() {
+        let text = match document.extract_text_with_limit(&[*page], MAX_ENTRY_BYTES as usize) {
+            Ok(text) => text,
+            Err(e) => {
+                println!("Failed to extract page {} of {}: {}", page, name, e);
+                continue;
+            }
+        };
+        if !builder.add(Some(format!("page {}", page)), 1, &text) {
+            break;
+        }
+    }
+    if builder.sections.iter().all(|s| s.text.is_empty()) {
+        return Err(format!("No text found in {}, scanned PDFs need OCR first", name));
+    }
+    builder.finish(DocumentFormat::Pdf, None, name)
+}
+
+fn open_archive(path: &Path, name: &str) -> Result<ZipArchive<File>, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    let mut magic = [0u8; 8];
+    if file.read_exact(&mut magic).is_ok() && magic == OLE_MAGIC {
+        return Err(format!(
+            "{} is password protected or an old binary Office file, save it as a regular document first",
+            name
+        ));
+    }
+    ZipArchive::new(file).map_err(|e| format!("Failed to open {}: {}", name, e))
+}
+
+// None when the archive has no such member
+fn read_entry(archive: &mut ZipArchive<File>, entry: &str, name: &str) -> Result<Option<String>, String> {
+    let file = match archive.by_name(entry) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) => return Ok(None),
+        Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)) => {
+            return Err(format!("{} is password protected", name))
+        }
+        Err(e) => return Err(format!("Failed to read {} from {}: {}", entry, name, e)),
+    };
+
+    let mut bytes = Vec::new();
+    file.take(MAX_ENTRY_BYTES + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read {} from {}: {}", entry, name, e))?;
+    if bytes.len() as u64 > MAX_ENTRY_BYTES {
+        return Err(format!("{} in {} is too large to extract", entry, name));
+    }
+    Ok(Some(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn require_entry(archive: &mut ZipArchive<File>, entry: &str, name: &str) -> Result<String, String> {
+    read_entry(archive, entry, name)?.ok_or_else(|| format!("{} has no {}, it may be damaged", name, entry))
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name().as_ref()).into_owned()
+}
+
+// attribute lookup by local name, namespace prefixes differ between producers
+fn attribute(element: &BytesStart, key: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|a| a.key.local_name().as_ref() == key.as_bytes())
+        .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+}
+
+// character data of a text or entity reference event
+fn event_text(event: &Event) -> Option<String> {
+    match event {
+        Event::Text(text) => text.decode().ok().map(|t| t.into_owned()),
+        Event::CData(data) => data.decode().ok().map(|t| t.into_owned()),
+        Event::GeneralRef(reference) => {
+            if let Ok(Some(c)) = reference.resolve_char_ref() {
+                return Some(c.to_string());
+            }
+            let entity = reference.decode().ok()?;
+            decode_entity(&entity).map(String::from)
+        }
+        _ => None,
+    }
+}
+
+// text of the first element with this local name, used for dc:title in metadata files
+fn first_element_text(xml: &str, element: &str) -> Option<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut inside = false;
+    let mut text = String::new();
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if local_name(&e) == element => inside = true,
+            Ok(Event::End(e)) if inside && e.local_name().as_ref() == element.as_bytes() => return Some(text),
+            Ok(Event::Eof) | Err(_) => return None,
+            Ok(event) if inside => text.push_str(&event_text(&event).unwrap_or_default()),
+            Ok(_) => {}
+        }
+    }
+}
+
+// style ids whose display name marks a heading, word localizes the ids but not the names
+fn docx_heading_styles(styles: &str) -> HashMap<String, usize> {
+    let mut headings = HashMap::new();
+    let mut reader = Reader::from_str(styles);
+    let mut current: Option<String> = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) if local_name(&e) == "style" => current = attribute(&e, "styleId"),
+            Ok(Event::Empty(e)) if local_name(&e) == "name" => {
+                if let (Some(id), Some(name)) = (&current, attribute(&e, "val")) {
+                    if let Some(level) = heading_level(&name) {
+                        headings.insert(id.clone(), level);
+                    }
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    headings
+}
+
+// "heading 2" and "Heading2" give 2, "Title" gives 1
+fn heading_level(style: &str) -> Option<usize> {
+    let style = style.to_lowercase().replace(' ', "");
+    if style == "title" {
+        return Some(1);
+    }
+    let level = style.strip_prefix("heading")?.parse::<usize>().ok()?;
+    (1..=6).contains(&level).then_some(level)
+}
+
+fn extract_docx(path: &Path, name: &str) -> Result<ExtractedDocument, String> {
+    let mut archive = open_archive(path, name)?;
+    let document = require_entry(&mut archive, "word/document.xml", name)?;
+    let headings = read_entry(&mut archive, "word/styles.xml", name)?
+        .map(|styles| docx_heading_styles(&styles))
+        .unwrap_or_default();
+    let title = read_entry(&mut archive, "docProps/core.xml", name)?.and_then(|core| first_element_text(&core, "title"));
+
+    let mut builder = SectionBuilder::new();
+    let mut section: (Option<String>, usize) = (None, 1);
+    let mut body = String::new();
+    let mut paragraph = String::new();
+    let mut paragraph_level: Option<usize> = None;
+    let mut in_text = false;
+
+    let mut reader = Reader::from_str(&document);
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Failed to parse {}: {}", name, e))?;
+        match &event {
+            Event::Start(e) | Event::Empty(e) => match local_name(e).as_str() {
+                "p" => paragraph_level = None,
+                "pStyle" => paragraph_level = attribute(e, "val").and_then(|id| headings.get(&id).copied()),
+                "outlineLvl" if paragraph_level.is_none() => {
+                    paragraph_level = attribute(e, "val").and_then(|v| v.parse::<usize>().ok()).map(|l| l + 1);
+                }
+                "t" => in_text = matches!(event, Event::Start(_)),
+                "tab" => paragraph.push('\t'),
+                "br" | "cr" => paragraph.push('\n'),
+                _ => {}
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                b"t" => in_text = false,
+                b"p" => {
+                    let text = std::mem::take(&mut paragraph);
+                    match paragraph_level.take() {
+                        Some(level) if !text.trim().is_empty() => {
+                            let (anchor, previous_level) = std::mem::replace(&mut section, (Some(text), level.min(6)));
+                            if !builder.add(anchor, previous_level, &std::mem::take(&mut body)) {
+                                break;
+                            }
+                        }
+                        _ => {
+                            body.push_str(text.trim_end());
+                            body.push('\n');
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => {
+                builder.add(section.0.take(), section.1, &body);
+                break;
+            }
+            _ if in_text => paragraph.push_str(&event_text(&event).unwrap_or_default()),
+            _ => {}
+        }
+    }
+    builder.finish(DocumentFormat::Docx, title, name)
+}
+
+fn extract_odt(path: &Path, name: &str) -> Result<ExtractedDocument, String> {
+    let mut archive = open_archive(path, name)?;
+    if read_entry(&mut archive, "META-INF/manifest.xml", name)?.is_some_and(|m| m.contains("encryption-data")) {
+        return Err(format!("{} is password protected", name));
+    }
+    let content = require_entry(&mut archive, "content.xml", name)?;
+    let title = read_entry(&mut archive, "meta.xml", name)?.and_then(|meta| first_element_text(&meta, "title"));
+
+    let mut builder = SectionBuilder::new();
+    let mut section: (Option<String>, usize) = (None, 1);
+    let mut body = String::new();
+    let mut paragraph = String::new();
+    let mut heading_level: Option<usize> = None;
+    let mut paragraph_depth = 0usize;
+    // notes, comments and tracked deletions sit inside paragraphs but aren't part of the running text
+    let mut skipped_depth = 0usize;
+
+    let mut reader = Reader::from_str(&content);
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Failed to parse {}: {}", name, e))?;
+        match &event {
+            Event::Start(_) if skipped_depth > 0 => skipped_depth += 1,
+            Event::End(_) if skipped_depth > 0 => skipped_depth -= 1,
+            _ if skipped_depth > 0 => {}
+            Event::Start(e) | Event::Empty(e) => {
+                let is_start = matches!(event, Event::Start(_));
+                match local_name(e).as_str() {
+                    "p" if is_start => paragraph_depth += 1,
+                    "note" | "annotation" | "tracked-changes" if is_start => skipped_depth = 1,
+                    "h" if is_start => {
+                        paragraph_depth += 1;
+                        heading_level = Some(attribute(e, "outline-level").and_then(|l| l.parse().ok()).unwrap_or(1));
+                    }
+                    "s" => {
+                        let count = attribute(e, "c").and_then(|c| c.parse::<usize>().ok()).unwrap_or(1);
+                        paragraph.push_str(&" ".repeat(count.min(100)));
+                    }
+                    "tab" => paragraph.push('\t'),
+                    "line-break" => paragraph.push('\n'),
+                    _ => {}
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"h" => {
+                    paragraph_depth = paragraph_depth.saturating_sub(1);
+                    let text = std::mem::take(&mut paragraph);
+                    let level = heading_level.take().unwrap_or(1).clamp(1, 6);
+                    let (anchor, previous_level) = std::mem::replace(&mut section, (Some(text), level));
+                    if !builder.add(anchor, previous_level, &std::mem::take(&mut body)) {
+                        break;
+                    }
+                }
+                b"p" => {
+                    paragraph_depth = paragraph_depth.saturating_sub(1);
+                    body.push_str(std::mem::take(&mut paragraph).trim_end());
+                    body.push('\n');
+                }
+                _ => {}
+            },
+            Event::Eof => {
+                builder.add(section.0.take(), section.1, &body);
+                break;
+            }
+            _ if paragraph_depth > 0 => paragraph.push_str(&event_text(&event).unwrap_or_default()),
+            _ => {}
+        }
+    }
+    builder.finish(DocumentFormat::Odt, title, name)
+}
+
+// resolves a manifest href against the folder of the package document
+fn resolve_href(base: &str, href: &str) -> String {
+    let href = percent_decode(href.split('#').next().unwrap_or(href));
+    let mut parts: Vec<&str> = base.split('/').filter(|p| !p.is_empty()).collect();
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() {
+            let hex = [bytes[i + 1], bytes[i + 2]];
+            decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).unwrap_or("00"), 16).unwrap_or(0));
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn extract_epub(path: &Path, name: &str) -> Result<ExtractedDocument, String> {
+    let mut archive = open_archive(path, name)?;
+
+    if let Some(encryption) = read_entry(&mut archive, "META-INF/encryption.xml", name)? {
+        let mut reader = Reader::from_str(&encryption);
+        loop {
+            match reader.read_event() {
+                Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(&e) == "EncryptionMethod" => {
+                    let algorithm = attribute(&e, "Algorithm").unwrap_or_default();
+                    if !FONT_OBFUSCATION.contains(&algorithm.as_str()) {
+                        return Err(format!("{} is DRM protected", name));
+                    }
+                }
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+        }
+    }
+
+    let container = require_entry(&mut archive, "META-INF/container.xml", name)?;
+    let mut reader = Reader::from_str(&container);
+    let package_path = loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) if local_name(&e) == "rootfile" => {
+                if let Some(full_path) = attribute(&e, "full-path") {
+                    break full_path;
+                }
+            }
+            Ok(Event::Eof) | Err(_) => return Err(format!("{} has no package document, it may be damaged", name)),
+            _ => {}
+        }
+    };
+    let package = require_entry(&mut archive, &package_path, name)?;
+    let base = package_path.rsplit_once('/').map(|(dir, _)| dir).unwrap_or("");
+
+    let title = first_element_text(&package, "title");
+    let mut manifest: HashMap<String, String> = HashMap::new();
+    let mut spine: Vec<String> = Vec::new();
+    let mut reader = Reader::from_str(&package);
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => match local_name(&e).as_str() {
+                "item" => {
+                    if let (Some(id), Some(href)) = (attribute(&e, "id"), attribute(&e, "href")) {
+                        manifest.insert(id, resolve_href(base, &href));
+                    }
+                }
+                "itemref" => spine.extend(attribute(&e, "idref")),
+                _ => {}
+            },
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(format!("Failed to parse {}: {}", name, e)),
+            _ => {}
+        }
+    }
+
+    let mut builder = SectionBuilder::new();
+    'chapters: for (index, id) in spine.iter().enumerate() {
+        let Some(href) = manifest.get(id) else { continue };
+        let Some(chapter) = read_entry(&mut archive, href, name)? else {
+            println!("Skipping missing chapter {} in {}", href, name);
+            continue;
+        };
+        let (_, sections) = html_to_sections(&chapter);
+        for section in sections {
+            let anchor = section.anchor.or_else(|| Some(format!("chapter {}", index + 1)));
+            if !builder.add(anchor, section.level, &section.text) {
+                break 'chapters;
+            }
+        }
+    }
+    builder.finish(DocumentFormat::Epub, title, name)
+}
+
+fn decode_entity(entity: &str) -> Option<&'static str> {
+    Some(match entity {
+        "amp" => "&",
+        "lt" => "<",
+        "gt" => ">",
+        "quot" => "\"",
+        "apos" => "'",
+        "nbsp" => " ",
+        "shy" => "",
+        "ndash" => "–",
+        "mdash" => "—",
+        "hellip" => "…",
+        "lsquo" => "‘",
+        "rsquo" => "’",
+        "ldquo" => "“",
+        "rdquo" => "”",
+        "laquo" => "«",
+        "raquo" => "»",
+        "bull" => "•",
+        "middot" => "·",
+        "copy" => "©",
+        "reg" => "®",
+        "trade" => "™",
+        "euro" => "€",
+        "pound" => "£",
+        "deg" => "°",
+        "times" => "×",
+        "divide" => "÷",
+        "plusmn" => "±",
+        "larr" => "←",
+        "rarr" => "→",
+        _ => return None,
+    })
+}
+
+// replaces &name; and &#number; references, unknown ones are left as written
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest[1..].find(';').map(|i| i + 1).filter(|&i| i <= 10) else {
+            decoded.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let replacement = match entity.strip_prefix('#') {
+            Some(number) => {
+                let code = match number.strip_prefix(['x', 'X']) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                };
+                code.and_then(char::from_u32).map(String::from)
+            }
+            None => decode_entity(entity).map(String::from),
+        };
+        match replacement {
+            Some(replacement) => {
+                decoded.push_str(&replacement);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// html walker, lenient on purpose since real pages are rarely well formed
+struct HtmlText {
+    sections: Vec<ExtractedSection>,
+    anchor: Option<String>,
+    level: usize,
+    text: String,
+    heading: Option<(usize, String)>,
+    title: Option<String>,
+    in_title: bool,
+    skipped: Option<String>,
+    preformatted: usize,
+    pending_space: bool,
+}
+
+impl HtmlText {
+    fn target(&mut self) -> &mut String {
+        match &mut self.heading {
+            Some((_, heading)) => heading,
+            None => &mut self.text,
+        }
+    }
+
+    fn push_text(&mut self, raw: &str) {
+        let text = decode_entities(raw);
+        if self.in_title {
+            self.title.get_or_insert_with(String::new).push_str(&text);
+            return;
+        }
+        if self.preformatted > 0 {
+            self.target().push_str(&text);
+            return;
+        }
+        for c in text.chars() {
+            if c.is_whitespace() {
+                self.pending_space = true;
+                continue;
+            }
+            let pending = std::mem::take(&mut self.pending_space);
+            let target = self.target();
+            if pending && !target.is_empty() && !target.ends_with(char::is_whitespace) {
+                target.push(' ');
+            }
+            target.push(c);
+        }
+    }
+
+    fn break_line(&mut self, blank: bool) {
+        self.pending_space = false;
+        let target = self.target();
+        let trimmed = target.trim_end_matches([' ', '\t']).len();
+        target.truncate(trimmed);
+        if target.is_empty() {
+            return;
+        }
+        if !target.ends_with('\n') {
+            target.push('\n');
+        }
+        if blank && !target.ends_with("\n\n") {
+            target.push('\n');
+        }
+    }
+
+    fn open(&mut self, tag: &str, self_closing: bool) {
+        if HTML_SKIPPED_TAGS.contains(&tag) && !self_closing {
+            self.skipped = Some(tag.to_string());
+        } else if tag == "title" && !self_closing {
+            self.in_title = true;
+        } else if let Some(level) = section_heading(tag) {
+            self.heading = Some((level, String::new()));
+        } else if tag == "pre" {
+            self.break_line(true);
+            self.preformatted += 1;
+        } else if tag == "li" {
+            self.break_line(false);
+            self.target().push_str("- ");
+        } else if tag == "td" || tag == "th" {
+            let target = self.target();
+            if !target.is_empty() && !target.ends_with('\n') {
+                target.push('\t');
+            }
+            self.pending_space = false;
+        } else if HTML_LINE_TAGS.contains(&tag) {
+            self.break_line(false);
+        } else if HTML_BLOCK_TAGS.contains(&tag) {
+            self.break_line(true);
+        }
+    }
+
+    fn close(&mut self, tag: &str) {
+        if tag == "title" {
+            self.in_title = false;
+        } else if let Some(level) = section_heading(tag) {
+            if let Some((_, heading)) = self.heading.take() {
+                let previous = (self.anchor.replace(heading), self.level);
+                self.level = level;
+                self.sections.push(ExtractedSection { anchor: previous.0, level: previous.1, text: std::mem::take(&mut self.text) });
+            }
+        } else if tag == "pre" {
+            self.preformatted = self.preformatted.saturating_sub(1);
+            self.break_line(true);
+        } else if HTML_LINE_TAGS.contains(&tag) {
+            self.break_line(false);
+        } else if HTML_BLOCK_TAGS.contains(&tag) {
+            self.break_line(true);
+        }
+    }
+}
+
+// h1 to h3 start a new section, smaller headings stay part of the text
+fn section_heading(tag: &str) -> Option<usize> {
+    match tag {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        _ => None,
+    }
+}
+
+// byte offset of the first match, compared in place so no lowercased copy of the rest is made
+// the needle starts with '<', so a match always starts on a char boundary
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+// the page title and its sections, split at h1 to h3
+fn html_to_sections(html: &str) -> (Option<String>, Vec<ExtractedSection>) {
+    let mut walker = HtmlText {
+        sections: Vec::new(),
+        anchor: None,
+        level: 1,
+        text: String::new(),
+        heading: None,
+        title: None,
+        in_title: false,
+        skipped: None,
+        preformatted: 0,
+        pending_space: false,
+    };
+
+    let mut rest = html;
+    while !rest.is_empty() {
+        // scripts and styles may contain '<', so jump straight to their closing tag
+        if let Some(skipped) = walker.skipped.take() {
+            let closing = format!("</{}", skipped);
+            match find_ignore_ascii_case(rest, &closing) {
+                Some(end) => rest = &rest[end..],
+                None => break,
+            }
+        }
+
+        let Some(start) = rest.find('<') else {
+            walker.push_text(rest);
+            break;
+        };
+        walker.push_text(&rest[..start]);
+        rest = &rest[start..];
+
+        // comments, doctype, cdata and processing instructions
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map(|end| &comment[end + 3..]).unwrap_or("");
+            continue;
+        }
+        if rest.starts_with("<!") || rest.starts_with("<?") {
+            rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+            continue;
+        }
+
+        // a '<' that doesn't start a tag is just text
+        if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') {
+            walker.push_text("<");
+            rest = &rest[1..];
+            continue;
+        }
+
+        // find the end of the tag, '>' inside quoted attribute values doesn't count
+        let mut quote: Option<char> = None;
+        let mut end = None;
+        for (i, c) in rest.char_indices().skip(1) {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (None, '"') | (None, '\'') => quote = Some(c),
+                (None, '>') => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let Some(end) = end else { break };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let tag_name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == ':' || *c == '-')
+            .collect::<String>()
+            .to_lowercase();
+        if closing {
+            walker.close(&tag_name);
+        } else {
+            walker.open(&tag_name, self_closing);
+            if self_closing {
+                walker.close(&tag_name);
+            }
+        }
+    }
+
+    if let Some((_, heading)) = walker.heading.take() {
+        walker.text.push_str(&heading);
+    }
+    let mut sections = walker.sections;
+    sections.push(ExtractedSection { anchor: walker.anchor, level: walker.level, text: walker.text });
+    (walker.title, sections)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// breaks a line at the last space before every MAX_LINE_CHARS characters
+fn wrap_line(line: &str, wrapped: &mut String) {
+    let mut rest = line;
+    while rest.chars().count() > MAX_LINE_CHARS {
+        let limit = rest.char_indices().nth(MAX_LINE_CHARS).map(|(i, _)| i).unwrap_or(rest.len());
+        let split = rest[..limit].rfind(' ').filter(|&i| i > 0).unwrap_or(limit);
+        wrapped.push_str(rest[..split].trim_end());
+        wrapped.push('\n');
+        rest = rest[split..].trim_start();
+    }
+    wrapped.push_str(rest);
+}
+
+// trims trailing spaces and runs of blank lines, drops control characters and wraps long lines
+fn clean_text(text: &str) -> String {
+    let mut cleaned = String::with_capacity(text.len());
+    let mut blank_lines = 0;
+    for line in text.replace("\r\n", "\n").replace('\r', "\n").lines() {
+        let line: String = line
+            .chars()
+            .map(|c| if c == '\u{a0}' { ' ' } else { c })
+            .filter(|c| !c.is_control() || *c == '\t')
+            .collect();
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if !cleaned.is_empty() {
+            cleaned.push_str(if blank_lines > 0 { "\n\n" } else { "\n" });
+        }
+        blank_lines = 0;
+        wrap_line(line, &mut cleaned);
+    }
+    cleaned
+}
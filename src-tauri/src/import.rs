@@ -0,0 +1,288 @@
+use crate::export::JsonExport;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// a message from another app, linked to its parent by the source's own keys so branches survive
+#[derive(Debug, Clone)]
+pub struct ImportedMessage {
+    pub key: String,
+    pub parent_key: Option<String>,
+    pub role: String,
+    pub content: String,
+    pub timestamp: String,
+    pub model: Option<String>,
+    pub status: String,
+    pub rating: i64,
+}
+
+// a conversation ready to be stored, messages are ordered so parents always come before children
+#[derive(Debug, Clone)]
+pub struct ImportedConversation {
+    pub id: String,
+    pub title: String,
+    pub model: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub messages: Vec<ImportedMessage>,
+    pub active_key: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ImportFileReport {
+    pub path: String,
+    pub imported: usize,
+    pub skipped: usize, // already imported earlier
+    pub failed: usize,
+    pub errors: Vec<String>,
+}
+
+// ids are derived from the source so importing the same export twice finds the existing rows
+fn stable_id(source: &str, key: &str) -> String {
+    let digest = Sha256::digest(format!("{}:{}", source, key).as_bytes());
+    format!("import-{}-{}", source, &format!("{:x}", digest)[..16])
+}
+
+fn unix_to_rfc3339(value: Option<&Value>) -> Option<String> {
+    let seconds = value?.as_f64()?;
+    DateTime::<Utc>::from_timestamp(seconds.trunc() as i64, (seconds.fract() * 1e9) as u32)
+        .map(|dt| dt.to_rfc3339())
+}
+
+// message content may be a plain string or a list of typed parts, only the text parts are kept
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| match part {
+                Value::String(text) => Some(text.clone()),
+                Value::Object(_) => part.get("text").and_then(|t| t.as_str()).map(|t| t.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+// picks the importer from the file extension and shape of the JSON
+pub fn parse_file(path: &Path) -> Result<Vec<Result<ImportedConversation, String>>, String> {
+    let raw = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+    let is_jsonl = path.extension().and_then(|e| e.to_str()).map(|e| e.eq_ignore_ascii_case("jsonl")).unwrap_or(false);
+    if is_jsonl {
+        return Ok(parse_jsonl(&raw));
+    }
+
+    let json: Value = serde_json::from_str(&raw)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+
+    match &json {
+        Value::Array(items) if items.iter().any(|item| item.get("mapping").is_some()) => {
+            Ok(items.iter().map(parse_chatgpt_conversation).collect())
+        }
+        Value::Object(_) if json.get("mapping").is_some() => Ok(vec![parse_chatgpt_conversation(&json)]),
+        Value::Object(_) if json.get("format_version").is_some() => Ok(vec![parse_jarvish_export(json)]),
+        Value::Array(items) if items.iter().any(|item| item.get("messages").is_some()) => {
+            Ok(items.iter().map(|item| parse_message_log(item, &item.to_string())).collect())
+        }
+        _ => Err(format!("Unrecognized export format in {}", path.display())),
+    }
+}
+
+// ChatGPT's conversations.json, each conversation is a tree of nodes in "mapping"
+fn parse_chatgpt_conversation(item: &Value) -> Result<ImportedConversation, String> {
+    let source_id = item.get("conversation_id")
+        .or_else(|| item.get("id"))
+        .and_then(|v| v.as_str())
+        .ok_or("Conversation has no id")?;
+    let mapping = item.get("mapping")
+        .and_then(|m| m.as_object())
+        .ok_or("Conversation has no message mapping")?;
+
+    let created_at = unix_to_rfc3339(item.get("create_time")).unwrap_or_else(|| Utc::now().to_rfc3339());
+    let updated_at = unix_to_rfc3339(item.get("update_time")).unwrap_or_else(|| created_at.clone());
+    let default_model = item.get("default_model_slug").and_then(|m| m.as_str()).unwrap_or("chatgpt");
+
+    // hidden system/tool nodes and empty messages are dropped and their children reattached to the nearest kept ancestor
+    let kept: HashSet<&str> = mapping
+        .iter()
+        .filter(|(_, node)| {
+            let Some(message) = node.get("message").filter(|m| !m.is_null()) else { return false };
+            let role = message.pointer("/author/role").and_then(|r| r.as_str()).unwrap_or("");
+            let hidden = message.pointer("/metadata/is_visually_hidden_from_conversation").and_then(|h| h.as_bool()).unwrap_or(false);
+            let text = message.pointer("/content/parts").map(content_text).unwrap_or_default();
+            matches!(role, "user" | "assistant" | "system") && !hidden && !text.trim().is_empty()
+        })
+        .map(|(key, _)| key.as_str())
+        .collect();
+
+    let parent_of = |key: &str| -> Option<&str> {
+        let mut current = mapping.get(key)?.get("parent")?.as_str();
+        while let Some(candidate) = current {
+            if kept.contains(candidate) {
+                return Some(candidate);
+            }
+            current = mapping.get(candidate)?.get("parent").and_then(|p| p.as_str());
+        }
+        None
+    };
+
+    let mut children: HashMap<Option<&str>, Vec<&str>> = HashMap::new();
+    for key in &kept {
+        children.entry(parent_of(key)).or_default().push(key);
+    }
+    for list in children.values_mut() {
+        list.sort_by(|a, b| {
+            let time = |key: &str| mapping[key].pointer("/message/create_time").and_then(|t| t.as_f64()).unwrap_or(0.0);
+            time(a).total_cmp(&time(b)).then_with(|| a.cmp(b))
+        });
+    }
+
+    // walk from the roots so parents are emitted before their children
+    let mut messages = Vec::new();
+    let mut stack: Vec<&str> = children.get(&None).cloned().unwrap_or_default();
+    stack.reverse();
+    while let Some(key) = stack.pop() {
+        let message = &mapping[key]["message"];
+        messages.push(ImportedMessage {
+            key: key.to_string(),
+            parent_key: parent_of(key).map(|p| p.to_string()),
+            role: message.pointer("/author/role").and_then(|r| r.as_str()).unwrap_or("user").to_string(),
+            content: message.pointer("/content/parts").map(content_text).unwrap_or_default(),
+            timestamp: unix_to_rfc3339(message.get("create_time")).unwrap_or_else(|| created_at.clone()),
+            model: message.pointer("/metadata/model_slug").and_then(|m| m.as_str()).map(|m| m.to_string()),
+            status: "complete".to_string(),
+            rating: 0,
+        });
+        if let Some(list) = children.get(&Some(key)) {
+            stack.extend(list.iter().rev());
+        }
+    }
+
+    if messages.is_empty() {
+        return Err(format!("Conversation {} has no messages", source_id));
+    }
+
+    // current_node may point at a hidden node, fall back to its nearest kept ancestor
+    let active_key = item.get("current_node")
+        .and_then(|n| n.as_str())
+        .and_then(|n| if kept.contains(n) { Some(n) } else { parent_of(n) })
+        .map(|n| n.to_string());
+
+    Ok(ImportedConversation {
+        id: stable_id("chatgpt", source_id),
+        title: item.get("title").and_then(|t| t.as_str()).filter(|t| !t.is_empty()).unwrap_or("Imported conversation").to_string(),
+        model: default_model.to_string(),
+        created_at,
+        updated_at,
+        messages,
+        active_key,
+    })
+}
+
+// OpenAI-style JSONL, one {"messages": [...]} conversation per line
+fn parse_jsonl(raw: &str) -> Vec<Result<ImportedConversation, String>> {
+    raw.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            let item: Value = serde_json::from_str(line)
+                .map_err(|e| format!("Line {}: {}", number + 1, e))?;
+            parse_message_log(&item, line).map_err(|e| format!("Line {}: {}", number + 1, e))
+        })
+        .collect()
+}
+
+// a flat list of chat messages, the dedup key is the content itself since these logs carry no ids
+fn parse_message_log(item: &Value, dedup_key: &str) -> Result<ImportedConversation, String> {
+    let entries = item.get("messages")
+        .and_then(|m| m.as_array())
+        .ok_or("Entry has no messages array")?;
+
+    let now = Utc::now().to_rfc3339();
+    let timestamp_of = |value: &Value| -> Option<String> {
+        let ts = value.get("timestamp").or_else(|| value.get("created_at")).or_else(|| value.get("create_time"))?;
+        ts.as_str().map(|s| s.to_string()).or_else(|| unix_to_rfc3339(Some(ts)))
+    };
+    let created_at = timestamp_of(item).unwrap_or_else(|| now.clone());
+    let model = item.get("model").and_then(|m| m.as_str()).unwrap_or("imported").to_string();
+
+    let mut messages: Vec<ImportedMessage> = Vec::new();
+    for entry in entries {
+        let role = entry.get("role").and_then(|r| r.as_str()).ok_or("Message has no role")?;
+        if !matches!(role, "user" | "assistant" | "system") {
+            continue;
+        }
+        let content = entry.get("content").map(content_text).unwrap_or_default();
+        let key = messages.len().to_string();
+        messages.push(ImportedMessage {
+            parent_key: messages.last().map(|m| m.key.clone()),
+            key,
+            role: role.to_string(),
+            content,
+            timestamp: timestamp_of(entry).unwrap_or_else(|| created_at.clone()),
+            model: None,
+            status: "complete".to_string(),
+            rating: 0,
+        });
+    }
+
+    if messages.is_empty() {
+        return Err("Entry has no user, assistant or system messages".to_string());
+    }
+
+    let title = item.get("title")
+        .and_then(|t| t.as_str())
+        .map(|t| t.to_string())
+        .or_else(|| messages.iter().find(|m| m.role == "user").map(|m| m.content.chars().take(50).collect()))
+        .unwrap_or_else(|| "Imported conversation".to_string());
+    let updated_at = messages.last().map(|m| m.timestamp.clone()).unwrap_or_else(|| created_at.clone());
+
+    Ok(ImportedConversation {
+        id: stable_id("log", dedup_key),
+        title,
+        model,
+        created_at,
+        updated_at,
+        active_key: messages.last().map(|m| m.key.clone()),
+        messages,
+    })
+}
+
+// our own lossless JSON export, keeps the original id so re-importing a backup is a no-op
+fn parse_jarvish_export(json: Value) -> Result<ImportedConversation, String> {
+    let export: JsonExport = serde_json::from_value(json)
+        .map_err(|e| format!("Invalid export file: {}", e))?;
+    let conv = export.conversation;
+
+    let active_key = conv.messages.last().and_then(|m| m.id).map(|id| id.to_string());
+    let messages = export.all_messages
+        .into_iter()
+        .map(|node| ImportedMessage {
+            key: node.id.to_string(),
+            parent_key: node.parent_id.map(|p| p.to_string()),
+            role: node.role,
+            content: node.content,
+            timestamp: node.timestamp,
+            model: node.model,
+            status: node.status,
+            rating: node.rating,
+        })
+        .collect();
+
+    Ok(ImportedConversation {
+        id: conv.id,
+        title: conv.title,
+        model: conv.model,
+        created_at: conv.created_at,
+        updated_at: conv.updated_at,
+        messages,
+        active_key,
+    })
+}
@@ -0,0 +1,442 @@
+use crate::chunker::{Chunk, DocumentKind};
+use crate::code_index::{self, Symbol};
+use crate::extract;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// folders of local documents, chunked and embedded through Ollama so conversations can draw on them
+// the index lives in its own database, it is derived from the folders and left out of backups,
+// re-indexing a source rebuilds it
+
+// directories that hold dependencies or build output rather than documents
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target", "dist", "build", "__pycache__", "venv", "vendor"];
+// larger text files are almost always generated or data dumps, documents like pdfs have their own limit
+const MAX_FILE_BYTES: u64 = 2 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KnowledgeSource {
+    pub id: i64,
+    pub path: String,
+    pub kind: String, // "folder" | "repository", repositories are walked respecting .gitignore
+    pub status: String, // "pending" | "indexing" | "ready" | "failed"
+    pub error: Option<String>,
+    pub embedding_model: Option<String>,
+    pub document_count: i64,
+    pub chunk_count: i64,
+    pub created_at: String,
+    pub indexed_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct IndexReport {
+    pub source_id: i64,
+    pub indexed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub chunks: usize, // chunks embedded during this run
+    pub failed: Vec<String>, // files that could not be read
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KnowledgeMatch {
+    pub chunk_id: i64,
+    pub source_id: i64,
+    pub path: String,
+    pub heading: Option<String>,
+    pub content: String,
+    pub language: Option<String>, // set for source code
+    pub start_line: i64,
+    pub end_line: i64,
+    pub score: f32,
+}
+
+// a file chunked and embedded, ready to replace what is indexed for it
+pub struct EmbeddedDocument {
+    pub hash: String,
+    pub language: Option<String>,
+    pub chunks: Vec<Chunk>,
+    pub embeddings: Vec<Vec<f32>>,
+    pub symbols: Vec<Symbol>,
+}
+
+pub fn open_knowledge_db(app_data_dir: &Path) -> Result<Connection, String> {
+    std::fs::create_dir_all(app_data_dir)
+        .map_err(|e| format!("Failed to create app data directory: {}", e))?;
+
+    let conn = Connection::open(app_data_dir.join("knowledge.db"))
+        .map_err(|e| format!("Failed to open knowledge base: {}", e))?;
+
+    conn.execute_batch(
+        "PRAGMA foreign_keys = ON;
+        CREATE TABLE IF NOT EXISTS knowledge_sources (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL UNIQUE,
+            status TEXT NOT NULL DEFAULT 'pending',
+            error TEXT,
+            embedding_model TEXT,
+            created_at TEXT NOT NULL,
+            indexed_at TEXT
+        );
+        CREATE TABLE IF NOT EXISTS knowledge_documents (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            source_id INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            embedding_model TEXT NOT NULL,
+            indexed_at TEXT NOT NULL,
+            UNIQUE (source_id, path),
+            FOREIGN KEY (source_id) REFERENCES knowledge_sources(id) ON DELETE CASCADE
+        );
+        CREATE TABLE IF NOT EXISTS knowledge_chunks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            document_id INTEGER NOT NULL,
+            chunk_index INTEGER NOT NULL,
+            heading TEXT,
+            content TEXT NOT NULL,
+            start_line INTEGER NOT NULL,
+            end_line INTEGER NOT NULL,
+            embedding BLOB NOT NULL,
+            FOREIGN KEY (document_id) REFERENCES knowledge_documents(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_knowledge_chunks_document ON knowledge_chunks (document_id);"
+    ).map_err(|e| format!("Failed to create knowledge base tables: {}", e))?;
+    code_index::create_tables(&conn)
+        .map_err(|e| format!("Failed to create symbol tables: {}", e))?;
+
+    Ok(conn)
+}
+
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+// vectors are stored as little-endian f32 blobs
+pub fn encode_embedding(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn decode_embedding(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let (mut dot, mut norm_a, mut norm_b) = (0.0f32, 0.0f32, 0.0f32);
+    for (x, y) in a.iter().zip(b) {
+        dot += x * y;
+        norm_a += x * x;
+        norm_b += y * y;
+    }
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a.sqrt() * norm_b.sqrt())
+    }
+}
+
+pub fn is_skipped_name(name: &str) -> bool {
+    name.starts_with('.') || SKIPPED_DIRS.contains(&name)
+}
+
+// a file the chunker understands and that isn't too large to be a document
+pub fn is_indexable(path: &Path) -> bool {
+    let limit = if extract::is_extractable(path) {
+        extract::MAX_DOCUMENT_BYTES
+    } else if DocumentKind::from_path(path).is_some() {
+        MAX_FILE_BYTES
+    } else {
+        return false;
+    };
+    std::fs::metadata(path).map(|m| m.len() <= limit).unwrap_or(false)
+}
+
+// whether a path inside a source sits in a hidden or dependency folder, or is hidden itself
+pub fn is_ignored(root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root)
+        .map(|relative| relative.components().any(|c| is_skipped_name(&c.as_os_str().to_string_lossy())))
+        .unwrap_or(true)
+}
+
+// every indexable file under root, hidden entries, dependency folders and symlinks are skipped
+pub fn collect_files(root: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if dir == root => return Err(format!("Failed to read {}: {}", root.display(), e)),
+            Err(e) => {
+                println!("Skipping unreadable directory {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Ok(file_type) = entry.file_type() else { continue };
+            if name.starts_with('.') || file_type.is_symlink() {
+                continue;
+            }
+            let path = entry.path();
+            if file_type.is_dir() {
+                if !is_skipped_name(&name) {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() && is_indexable(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// paths are stored relative to the source with forward slashes
+pub fn relative_path(root: &Path, file: &Path) -> String {
+    file.strip_prefix(root)
+        .unwrap_or(file)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// what gets embedded for a chunk, the path and heading help short chunks match questions about their context
+pub fn embedding_input(path: &str, chunk: &Chunk) -> String {
+    match &chunk.heading {
+        Some(heading) => format!("{} > {}\n\n{}", path, heading, chunk.content),
+        None => format!("{}\n\n{}", path, chunk.content),
+    }
+}
+
+fn map_source(row: &rusqlite::Row) -> SqlResult<KnowledgeSource> {
+    Ok(KnowledgeSource {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        status: row.get(2)?,
+        error: row.get(3)?,
+        embedding_model: row.get(4)?,
+        created_at: row.get(5)?,
+        indexed_at: row.get(6)?,
+        document_count: row.get(7)?,
+        chunk_count: row.get(8)?,
+        kind: row.get(9)?,
+    })
+}
+
+const SOURCE_QUERY: &str =
+    "SELECT s.id, s.path, s.status, s.error, s.embedding_model, s.created_at, s.indexed_at,
+        (SELECT COUNT(*) FROM knowledge_documents d WHERE d.source_id = s.id),
+        (SELECT COUNT(*) FROM knowledge_chunks c JOIN knowledge_documents d ON d.id = c.document_id
+         WHERE d.source_id = s.id),
+        s.kind
+     FROM knowledge_sources s";
+
+pub fn load_sources(conn: &Connection) -> SqlResult<Vec<KnowledgeSource>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY s.path", SOURCE_QUERY))?;
+    let rows = stmt.query_map([], map_source)?;
+    rows.collect()
+}
+
+pub fn load_source(conn: &Connection, source_id: i64) -> SqlResult<Option<KnowledgeSource>> {
+    conn.query_row(&format!("{} WHERE s.id = ?1", SOURCE_QUERY), params![source_id], map_source)
+        .optional()
+}
+
+pub fn add_source(conn: &Connection, path: &Path) -> Result<i64, String> {
+    let root = path
+        .canonicalize()
+        .map_err(|e| format!("Failed to resolve {}: {}", path.display(), e))?;
+    if !root.is_dir() {
+        return Err(format!("{} is not a folder", root.display()));
+    }
+    let root = root.to_string_lossy().to_string();
+
+    let existing: Option<i64> = conn.query_row(
+        "SELECT id FROM knowledge_sources WHERE path = ?1",
+        params![&root],
+        |row| row.get(0),
+    ).optional().map_err(|e| format!("Failed to check sources: {}", e))?;
+    if existing.is_some() {
+        return Err(format!("{} is already in the knowledge base", root));
+    }
+
+    let kind = if code_index::is_repository(Path::new(&root)) {
+        code_index::KIND_REPOSITORY
+    } else {
+        code_index::KIND_FOLDER
+    };
+    conn.execute(
+        "INSERT INTO knowledge_sources (path, kind, created_at) VALUES (?1, ?2, ?3)",
+        params![&root, kind, Utc::now().to_rfc3339()],
+    ).map_err(|e| format!("Failed to add source: {}", e))?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn remove_source(conn: &Connection, source_id: i64) -> Result<(), String> {
+    let removed = conn.execute("DELETE FROM knowledge_sources WHERE id = ?1", params![source_id])
+        .map_err(|e| format!("Failed to remove source: {}", e))?;
+    if removed == 0 {
+        return Err(format!("Knowledge source {} not found", source_id));
+    }
+    Ok(())
+}
+
+pub fn set_source_status(conn: &Connection, source_id: i64, status: &str, error: Option<&str>) -> Result<(), String> {
+    conn.execute(
+        "UPDATE knowledge_sources SET status = ?1, error = ?2 WHERE id = ?3",
+        params![status, error, source_id],
+    ).map_err(|e| format!("Failed to update source: {}", e))?;
+    Ok(())
+}
+
+pub fn finish_source(conn: &Connection, source_id: i64, model: &str) -> Result<(), String> {
+    conn.execute(
+        "UPDATE knowledge_sources SET status = 'ready', error = NULL, embedding_model = ?1, indexed_at = ?2
+         WHERE id = ?3",
+        params![model, Utc::now().to_rfc3339(), source_id],
+    ).map_err(|e| format!("Failed to update source: {}", e))?;
+    Ok(())
+}
+
+// relative path to (content hash, embedding model) for every indexed document of a source
+pub fn document_states(conn: &Connection, source_id: i64) -> SqlResult<HashMap<String, (String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, content_hash, embedding_model FROM knowledge_documents WHERE source_id = ?1"
+    )?;
+    let rows = stmt.query_map(params![source_id], |row| {
+        Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?)))
+    })?;
+    rows.collect()
+}
+
+// replaces a document's chunks and symbols with freshly embedded ones
+pub fn store_document(
+    conn: &Connection,
+    source_id: i64,
+    path: &str,
+    model: &str,
+    document: &EmbeddedDocument,
+) -> Result<(), String> {
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+    tx.execute(
+        "INSERT INTO knowledge_documents (source_id, path, content_hash, embedding_model, language, indexed_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(source_id, path) DO UPDATE SET
+            content_hash = excluded.content_hash,
+            embedding_model = excluded.embedding_model,
+            language = excluded.language,
+            indexed_at = excluded.indexed_at",
+        params![source_id, path, &document.hash, model, &document.language, Utc::now().to_rfc3339()],
+    ).map_err(|e| format!("Failed to save document: {}", e))?;
+    let document_id: i64 = tx.query_row(
+        "SELECT id FROM knowledge_documents WHERE source_id = ?1 AND path = ?2",
+        params![source_id, path],
+        |row| row.get(0),
+    ).map_err(|e| format!("Failed to load document: {}", e))?;
+
+    tx.execute("DELETE FROM knowledge_chunks WHERE document_id = ?1", params![document_id])
+        .map_err(|e| format!("Failed to clear chunks: {}", e))?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT INTO knowledge_chunks (document_id, chunk_index, heading, content, start_line, end_line, embedding)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"
+        ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+        for (index, (chunk, embedding)) in document.chunks.iter().zip(&document.embeddings).enumerate() {
+            stmt.execute(params![
+                document_id,
+                index as i64,
+                &chunk.heading,
+                &chunk.content,
+                chunk.start_line as i64,
+                chunk.end_line as i64,
+                encode_embedding(embedding),
+            ]).map_err(|e| format!("Failed to save chunk: {}", e))?;
+        }
+    }
+    code_index::store_symbols(&tx, document_id, &document.symbols)?;
+
+    tx.commit().map_err(|e| format!("Failed to commit transaction: {}", e))?;
+    Ok(())
+}
+
+// drops documents whose files are gone from the source folder
+pub fn remove_missing_documents(conn: &Connection, source_id: i64, present: &HashSet<String>) -> Result<usize, String> {
+    let indexed = document_states(conn, source_id)
+        .map_err(|e| format!("Failed to load documents: {}", e))?;
+    let mut removed = 0;
+    for path in indexed.keys().filter(|path| !present.contains(*path)) {
+        removed += conn.execute(
+            "DELETE FROM knowledge_documents WHERE source_id = ?1 AND path = ?2",
+            params![source_id, path],
+        ).map_err(|e| format!("Failed to remove document: {}", e))?;
+    }
+    Ok(removed)
+}
+
+// drops a deleted file, or every file under a deleted folder
+pub fn remove_documents_under(conn: &Connection, source_id: i64, path: &str) -> Result<usize, String> {
+    conn.execute(
+        "DELETE FROM knowledge_documents
+         WHERE source_id = ?1 AND (path = ?2 OR substr(path, 1, length(?2) + 1) = ?2 || '/')",
+        params![source_id, path],
+    ).map_err(|e| format!("Failed to remove documents: {}", e))
+}
+
+// every chunk embedded with the query's model scored against it, paired with its document id,
+// optionally only from one kind of source or a single source
+pub fn scored_chunks(
+    conn: &Connection,
+    model: &str,
+    query: &[f32],
+    source_kind: Option<&str>,
+    source_id: Option<i64>,
+) -> SqlResult<Vec<(i64, KnowledgeMatch)>> {
+    let mut stmt = conn.prepare(
+        "SELECT c.id, d.source_id, s.path, d.path, c.heading, c.content, c.start_line, c.end_line, c.embedding,
+            d.language, d.id
+         FROM knowledge_chunks c
+         JOIN knowledge_documents d ON d.id = c.document_id
+         JOIN knowledge_sources s ON s.id = d.source_id
+         WHERE d.embedding_model = ?1 AND (?2 IS NULL OR s.kind = ?2) AND (?3 IS NULL OR s.id = ?3)"
+    )?;
+    let rows = stmt.query_map(params![model, source_kind, source_id], |row| {
+        let root: String = row.get(2)?;
+        let path: String = row.get(3)?;
+        let embedding: Vec<u8> = row.get(8)?;
+        Ok((row.get(10)?, KnowledgeMatch {
+            chunk_id: row.get(0)?,
+            source_id: row.get(1)?,
+            path: Path::new(&root).join(&path).to_string_lossy().to_string(),
+            heading: row.get(4)?,
+            content: row.get(5)?,
+            language: row.get(9)?,
+            start_line: row.get(6)?,
+            end_line: row.get(7)?,
+            score: cosine_similarity(query, &decode_embedding(&embedding)),
+        }))
+    })?;
+    rows.collect()
+}
+
+// exact nearest neighbours over every chunk embedded with the query's model, a linear scan is
+// fast enough for a personal knowledge base and needs no index to keep in sync
+// chunks defining a symbol the query names get a boost on top of their similarity
+pub fn search(conn: &Connection, model: &str, query: &[f32], query_text: &str, limit: usize) -> SqlResult<Vec<KnowledgeMatch>> {
+    let mut scored = scored_chunks(conn, model, query, None, None)?;
+    code_index::boost_symbol_matches(conn, query_text, &mut scored)?;
+    let mut matches: Vec<KnowledgeMatch> = scored.into_iter().map(|(_, m)| m).collect();
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    matches.truncate(limit);
+    Ok(matches)
+}
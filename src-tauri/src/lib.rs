@@ -1,6 +1,29 @@
 mod commands;
+mod db;
 mod ollama_service;
 mod logger;
+mod message_tree;
+mod settings;
+mod export;
+mod import;
+mod dataset;
+mod backup;
+mod crypto;
+mod tokenizer;
+mod context_window;
+mod summary;
+mod titles;
+mod chunker;
+mod knowledge;
+mod retrieval;
+mod semantic_search;
+mod watcher;
+mod extract;
+mod attachments;
+mod vision;
+mod code_index;
+mod templates;
+mod personas;
 
 use std::sync::Mutex;
 use tokio::sync::Mutex as AsyncMutex;
@@ -20,6 +43,19 @@ pub struct StreamState {
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            if let Err(e) = commands::load_encryption_state(app.handle()) {
+                log_message(&e, Some("warn".to_string()));
+            }
+            if let Err(e) = commands::recover_interrupted_streams(app.handle()) {
+                log_message(&e, Some("warn".to_string()));
+            }
+            commands::start_trash_purger(app.handle().clone());
+            commands::start_backup_scheduler(app.handle().clone());
+            commands::start_embedding_worker(app.handle().clone());
+            commands::start_knowledge_watcher(app.handle().clone());
+            Ok(())
+        })
         .manage(ConversationState { 
             context: Mutex::new(None),
             stream_state: AsyncMutex::new(StreamState {
@@ -39,9 +75,73 @@ pub fn run() {
             commands::save_conversation,
             commands::get_conversations,
             commands::load_conversation,
+            commands::edit_message,
+            commands::switch_branch,
+            commands::regenerate_message,
             commands::delete_conversation,
+            commands::restore_conversation,
+            commands::empty_trash,
+            commands::get_app_settings,
+            commands::save_app_settings,
             commands::create_new_conversation,
-            commands::update_conversation_title
+            commands::update_conversation_title,
+            commands::set_conversation_pinned,
+            commands::set_conversation_archived,
+            commands::get_tags,
+            commands::create_tag,
+            commands::update_tag,
+            commands::delete_tag,
+            commands::add_conversation_tag,
+            commands::remove_conversation_tag,
+            commands::get_folders,
+            commands::create_folder,
+            commands::rename_folder,
+            commands::delete_folder,
+            commands::add_conversation_to_folder,
+            commands::remove_conversation_from_folder,
+            commands::export_conversation,
+            commands::export_conversations,
+            commands::import_conversations,
+            commands::rate_message,
+            commands::export_finetune_dataset,
+            commands::create_backup,
+            commands::restore_backup,
+            commands::get_encryption_status,
+            commands::unlock_database,
+            commands::lock_database,
+            commands::enable_encryption,
+            commands::rotate_encryption_key,
+            commands::disable_encryption,
+            commands::count_tokens,
+            commands::get_conversation_summary,
+            commands::update_conversation_summary,
+            commands::regenerate_conversation_summary,
+            commands::retitle_conversations,
+            commands::get_knowledge_sources,
+            commands::add_knowledge_source,
+            commands::remove_knowledge_source,
+            commands::reindex_knowledge_source,
+            commands::search_knowledge_base,
+            commands::semantic_search_conversations,
+            commands::extract_document,
+            commands::attach_file,
+            commands::attach_image_data,
+            commands::remove_attachment,
+            commands::get_attachment_data,
+            commands::search_code,
+            commands::find_code_symbols,
+            commands::get_prompt_templates,
+            commands::create_prompt_template,
+            commands::update_prompt_template,
+            commands::delete_prompt_template,
+            commands::render_prompt_template,
+            commands::export_prompt_templates,
+            commands::import_prompt_templates,
+            commands::get_personas,
+            commands::create_persona,
+            commands::update_persona,
+            commands::delete_persona,
+            commands::set_conversation_persona
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
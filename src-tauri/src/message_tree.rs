@@ -0,0 +1,111 @@
+use crate::attachments::Attachment;
+use crate::retrieval::Citation;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// a single row of the messages table, messages form a tree through parent_id
+// so editing an earlier prompt or regenerating a reply adds a sibling instead of overwriting
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MessageNode {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub role: String,
+    pub content: String,
+    pub timestamp: String,
+    pub model: Option<String>,
+    pub status: String,
+    #[serde(default)]
+    pub rating: i64,
+    #[serde(default)]
+    pub token_count: Option<i64>, // reported by Ollama for replies, None when it has to be counted
+    #[serde(default)]
+    pub citations: Vec<Citation>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+pub struct MessageTree {
+    nodes: HashMap<i64, MessageNode>,
+    children: HashMap<Option<i64>, Vec<i64>>,
+}
+
+impl MessageTree {
+    pub fn new(nodes: Vec<MessageNode>) -> Self {
+        let mut children: HashMap<Option<i64>, Vec<i64>> = HashMap::new();
+        for node in &nodes {
+            children.entry(node.parent_id).or_default().push(node.id);
+        }
+        // ids are autoincrement, so sorting by id keeps siblings in creation order
+        for ids in children.values_mut() {
+            ids.sort_unstable();
+        }
+
+        Self {
+            nodes: nodes.into_iter().map(|node| (node.id, node)).collect(),
+            children,
+        }
+    }
+
+    // every message in the tree, oldest first
+    pub fn nodes(&self) -> Vec<&MessageNode> {
+        let mut nodes: Vec<&MessageNode> = self.nodes.values().collect();
+        nodes.sort_unstable_by_key(|node| node.id);
+        nodes
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        self.nodes.contains_key(&id)
+    }
+
+    pub fn children(&self, parent_id: Option<i64>) -> &[i64] {
+        self.children.get(&parent_id).map(|ids| ids.as_slice()).unwrap_or(&[])
+    }
+
+    // all messages sharing a parent with the given message, including itself
+    pub fn siblings(&self, id: i64) -> Vec<i64> {
+        match self.nodes.get(&id) {
+            Some(node) => self.children(node.parent_id).to_vec(),
+            None => Vec::new(),
+        }
+    }
+
+    // follows the newest child down from a message until reaching a leaf
+    pub fn latest_leaf(&self, from: i64) -> i64 {
+        let mut current = from;
+        while let Some(&child) = self.children(Some(current)).last() {
+            current = child;
+        }
+        current
+    }
+
+    // the leaf used when a conversation has no stored active branch
+    pub fn default_leaf(&self) -> Option<i64> {
+        self.children(None).last().map(|&root| self.latest_leaf(root))
+    }
+
+    // messages from the root down to the given message
+    pub fn path_to(&self, leaf: i64) -> Vec<&MessageNode> {
+        let mut path = Vec::new();
+        let mut current = self.nodes.get(&leaf);
+        while let Some(node) = current {
+            path.push(node);
+            current = node.parent_id.and_then(|parent| self.nodes.get(&parent));
+        }
+        path.reverse();
+        path
+    }
+
+    // finds a child of the parent with identical role and content, used to match re-saved messages
+    pub fn find_child(&self, parent_id: Option<i64>, role: &str, content: &str) -> Option<i64> {
+        self.children(parent_id)
+            .iter()
+            .rev()
+            .copied()
+            .find(|id| {
+                self.nodes
+                    .get(id)
+                    .map(|node| node.role == role && node.content == content)
+                    .unwrap_or(false)
+            })
+    }
+}
@@ -22,6 +22,16 @@ pub struct ModelDetails {
     pub parameter_size: Option<String>,
     pub quantization_level: Option<String>,
 }
+
+// a single turn sent to /api/chat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+}
+
 pub struct OllamaService {
     base_url: String,
     client: reqwest::Client,
@@ -88,6 +98,30 @@ impl OllamaService {
         Ok(model_info)
     }
 
+    // the full GGUF metadata, verbose includes the tokenizer vocabulary that /api/show leaves out by default
+    pub async fn get_model_metadata(&self, model_name: &str) -> Result<HashMap<String, Value>, String> {
+        let payload = json!({
+            "model": model_name,
+            "verbose": true
+        });
+
+        let response = self.client
+            .post(format!("{}/api/show", self.base_url))
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to get model metadata: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("Request failed with status: {}", response.status()));
+        }
+
+        let model_info: ModelInfo = response.json().await
+            .map_err(|e| format!("Failed to parse model metadata: {}", e))?;
+
+        Ok(model_info.model_info.unwrap_or_default())
+    }
+
     pub async fn generate_stream(
         &self,
         model: &str,
@@ -157,6 +191,114 @@ impl OllamaService {
         Ok(response)
     }
 
+    // streams a reply to a full message history, used for saved conversations so the history can be trimmed
+    pub async fn chat_stream(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        format: Option<&str>,
+        options: Option<HashMap<String, Value>>,
+    ) -> Result<reqwest::Response, String> {
+        println!("Generating chat stream for model: {} with {} messages", model, messages.len());
+
+        let mut payload = json!({
+            "model": model,
+            "messages": messages,
+            "stream": true
+        });
+
+        if let Some(fmt) = format {
+            payload["format"] = json!(fmt);
+        }
+        if let Some(opts) = options {
+            payload["options"] = json!(opts);
+        }
+
+        let response = self.client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| {
+                let error_msg = format!("Failed to send request: {}", e);
+                println!("{}", error_msg);
+                error_msg
+            })?;
+
+        if !response.status().is_success() {
+            let error_msg = format!("Request failed with status: {}", response.status());
+            println!("{}", error_msg);
+            return Err(error_msg);
+        }
+
+        Ok(response)
+    }
+
+    // a single non-streamed reply, for background jobs like summaries
+    pub async fn chat(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        options: Option<HashMap<String, Value>>,
+    ) -> Result<String, String> {
+        let mut payload = json!({
+            "model": model,
+            "messages": messages,
+            "stream": false
+        });
+
+        if let Some(opts) = options {
+            payload["options"] = json!(opts);
+        }
+
+        let response = self.client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("Request failed with status: {}", response.status()));
+        }
+
+        let body: Value = response.json().await
+            .map_err(|e| format!("Failed to parse chat response: {}", e))?;
+        body.pointer("/message/content")
+            .and_then(|c| c.as_str())
+            .map(|c| c.to_string())
+            .ok_or_else(|| "Chat response has no message".to_string())
+    }
+
+    // one vector per input, in order, through /api/embed
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>, String> {
+        let payload = json!({
+            "model": model,
+            "input": inputs,
+            "truncate": true
+        });
+
+        let response = self.client
+            .post(format!("{}/api/embed", self.base_url))
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to send request: {}", e))?;
+
+        if !response.status().is_success() {
+            return Err(format!("Embedding with {} failed with status: {}", model, response.status()));
+        }
+
+        let body: Value = response.json().await
+            .map_err(|e| format!("Failed to parse embed response: {}", e))?;
+        let embeddings: Vec<Vec<f32>> = serde_json::from_value(body["embeddings"].clone())
+            .map_err(|e| format!("Failed to parse embeddings: {}", e))?;
+        if embeddings.len() != inputs.len() {
+            return Err(format!("Expected {} embeddings, got {}", inputs.len(), embeddings.len()));
+        }
+        Ok(embeddings)
+    }
+
     pub async fn check_health(&self) -> bool {
         match self.client // GET http://localhost:11434/api/tags
             .get(&format!("{}/api/tags", self.base_url))
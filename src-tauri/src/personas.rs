@@ -0,0 +1,255 @@
+use crate::db::ensure_column;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+// a system prompt and sampling parameters saved under a name, attached to a conversation they are
+// applied by the backend on every turn of it, so the frontend never has to send them along
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Persona {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub system_prompt: String,
+    pub model: Option<String>, // used for every turn of the conversation, whatever model is selected
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub num_ctx: Option<i64>,
+    pub stop: Vec<String>, // stop sequences
+    pub conversation_count: i64,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+// what the frontend sends to create or update a persona
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PersonaDraft {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub system_prompt: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f64>,
+    #[serde(default)]
+    pub top_p: Option<f64>,
+    #[serde(default)]
+    pub num_ctx: Option<i64>,
+    #[serde(default)]
+    pub stop: Vec<String>,
+}
+
+pub fn create_table(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS personas (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            description TEXT NOT NULL DEFAULT '',
+            system_prompt TEXT NOT NULL DEFAULT '',
+            model TEXT,
+            temperature REAL,
+            top_p REAL,
+            num_ctx INTEGER,
+            stop TEXT NOT NULL DEFAULT '[]',
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );"
+    )?;
+    ensure_column(conn, "conversations", "persona_id", "INTEGER REFERENCES personas(id) ON DELETE SET NULL")?;
+    Ok(())
+}
+
+// trims the draft and checks its parameters are in the range Ollama accepts
+pub fn validate(draft: PersonaDraft) -> Result<PersonaDraft, String> {
+    let name = draft.name.trim().to_string();
+    if name.is_empty() {
+        return Err("Persona name cannot be empty".to_string());
+    }
+    if draft.temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+        return Err("Temperature must be between 0 and 2".to_string());
+    }
+    if draft.top_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+        return Err("Top P must be between 0 and 1".to_string());
+    }
+    if draft.num_ctx.is_some_and(|n| n < 1) {
+        return Err("Context length must be at least 1 token".to_string());
+    }
+    Ok(PersonaDraft {
+        name,
+        description: draft.description.trim().to_string(),
+        system_prompt: draft.system_prompt.trim().to_string(),
+        model: draft.model.map(|m| m.trim().to_string()).filter(|m| !m.is_empty()),
+        temperature: draft.temperature,
+        top_p: draft.top_p,
+        num_ctx: draft.num_ctx,
+        stop: draft.stop.into_iter().filter(|s| !s.is_empty()).collect(),
+    })
+}
+
+fn map_persona(row: &rusqlite::Row) -> SqlResult<Persona> {
+    let stop: String = row.get(8)?;
+    Ok(Persona {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        description: row.get(2)?,
+        system_prompt: row.get(3)?,
+        model: row.get(4)?,
+        temperature: row.get(5)?,
+        top_p: row.get(6)?,
+        num_ctx: row.get(7)?,
+        stop: serde_json::from_str(&stop).unwrap_or_default(),
+        created_at: row.get(9)?,
+        updated_at: row.get(10)?,
+        conversation_count: row.get(11)?,
+    })
+}
+
+const PERSONA_QUERY: &str =
+    "SELECT p.id, p.name, p.description, p.system_prompt, p.model, p.temperature, p.top_p, p.num_ctx, p.stop,
+        p.created_at, p.updated_at,
+        (SELECT COUNT(*) FROM conversations c WHERE c.persona_id = p.id AND c.deleted_at IS NULL)
+     FROM personas p";
+
+pub fn load_personas(conn: &Connection) -> SqlResult<Vec<Persona>> {
+    let mut stmt = conn.prepare(&format!("{} ORDER BY p.name COLLATE NOCASE", PERSONA_QUERY))?;
+    let rows = stmt.query_map([], map_persona)?;
+    rows.collect()
+}
+
+pub fn load_persona(conn: &Connection, persona_id: i64) -> SqlResult<Option<Persona>> {
+    conn.query_row(&format!("{} WHERE p.id = ?1", PERSONA_QUERY), params![persona_id], map_persona)
+        .optional()
+}
+
+// the persona attached to a conversation, None for conversations without one or not saved yet
+pub fn for_conversation(conn: &Connection, conversation_id: &str) -> SqlResult<Option<Persona>> {
+    conn.query_row(
+        &format!("{} JOIN conversations c ON c.persona_id = p.id WHERE c.id = ?1", PERSONA_QUERY),
+        params![conversation_id],
+        map_persona,
+    ).optional()
+}
+
+fn find_by_name(conn: &Connection, name: &str) -> Result<Option<i64>, String> {
+    conn.query_row("SELECT id FROM personas WHERE name = ?1", params![name], |row| row.get(0))
+        .optional()
+        .map_err(|e| format!("Failed to check personas: {}", e))
+}
+
+fn encode_stop(stop: &[String]) -> Result<String, String> {
+    serde_json::to_string(stop).map_err(|e| format!("Failed to encode stop sequences: {}", e))
+}
+
+pub fn create_persona(conn: &Connection, draft: PersonaDraft) -> Result<i64, String> {
+    let draft = validate(draft)?;
+    if find_by_name(conn, &draft.name)?.is_some() {
+        return Err(format!("A persona named \"{}\" already exists", draft.name));
+    }
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO personas (name, description, system_prompt, model, temperature, top_p, num_ctx, stop, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?9)",
+        params![
+            &draft.name,
+            &draft.description,
+            &draft.system_prompt,
+            &draft.model,
+            draft.temperature,
+            draft.top_p,
+            draft.num_ctx,
+            encode_stop(&draft.stop)?,
+            &now,
+        ],
+    ).map_err(|e| format!("Failed to create persona: {}", e))?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn update_persona(conn: &Connection, persona_id: i64, draft: PersonaDraft) -> Result<(), String> {
+    let draft = validate(draft)?;
+    if find_by_name(conn, &draft.name)?.is_some_and(|id| id != persona_id) {
+        return Err(format!("A persona named \"{}\" already exists", draft.name));
+    }
+    let updated = conn.execute(
+        "UPDATE personas SET name = ?1, description = ?2, system_prompt = ?3, model = ?4, temperature = ?5,
+            top_p = ?6, num_ctx = ?7, stop = ?8, updated_at = ?9
+         WHERE id = ?10",
+        params![
+            &draft.name,
+            &draft.description,
+            &draft.system_prompt,
+            &draft.model,
+            draft.temperature,
+            draft.top_p,
+            draft.num_ctx,
+            encode_stop(&draft.stop)?,
+            Utc::now().to_rfc3339(),
+            persona_id,
+        ],
+    ).map_err(|e| format!("Failed to update persona: {}", e))?;
+    if updated == 0 {
+        return Err(format!("Persona {} not found", persona_id));
+    }
+    Ok(())
+}
+
+// conversations using the persona are left without one
+pub fn delete_persona(conn: &Connection, persona_id: i64) -> Result<(), String> {
+    let deleted = conn.execute("DELETE FROM personas WHERE id = ?1", params![persona_id])
+        .map_err(|e| format!("Failed to delete persona: {}", e))?;
+    if deleted == 0 {
+        return Err(format!("Persona {} not found", persona_id));
+    }
+    Ok(())
+}
+
+// attaches a persona to a conversation, or detaches it with None, a conversation that only exists in the
+// frontend so far is saved first, the persona's model becomes the conversation's model
+pub fn attach(conn: &Connection, conversation_id: &str, persona: Option<&Persona>) -> Result<(), String> {
+    let now = Utc::now().to_rfc3339();
+    let model = persona.and_then(|p| p.model.clone());
+    conn.execute(
+        "INSERT INTO conversations (id, title, model, created_at, updated_at, persona_id)
+         VALUES (?1, 'New Conversation', COALESCE(?2, ''), ?3, ?3, ?4)
+         ON CONFLICT(id) DO UPDATE SET
+            persona_id = excluded.persona_id,
+            model = COALESCE(?2, conversations.model)",
+        params![conversation_id, &model, &now, persona.map(|p| p.id)],
+    ).map_err(|e| format!("Failed to attach persona: {}", e))?;
+    Ok(())
+}
+
+// the persona's system prompt unless the request brings its own
+pub fn system_prompt(persona: Option<&Persona>, requested: Option<String>) -> Option<String> {
+    requested.or_else(|| persona.map(|p| p.system_prompt.clone()).filter(|s| !s.is_empty()))
+}
+
+// the persona's parameters as Ollama options, options sent with the request take precedence
+pub fn options(persona: Option<&Persona>, requested: Option<HashMap<String, Value>>) -> Option<HashMap<String, Value>> {
+    let Some(persona) = persona else {
+        return requested;
+    };
+    let mut merged = HashMap::new();
+    if let Some(temperature) = persona.temperature {
+        merged.insert("temperature".to_string(), Value::from(temperature));
+    }
+    if let Some(top_p) = persona.top_p {
+        merged.insert("top_p".to_string(), Value::from(top_p));
+    }
+    if let Some(num_ctx) = persona.num_ctx {
+        merged.insert("num_ctx".to_string(), Value::from(num_ctx));
+    }
+    if !persona.stop.is_empty() {
+        merged.insert("stop".to_string(), Value::from(persona.stop.clone()));
+    }
+    merged.extend(requested.unwrap_or_default());
+    if merged.is_empty() {
+        None
+    } else {
+        Some(merged)
+    }
+}
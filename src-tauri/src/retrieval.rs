@@ -0,0 +1,90 @@
+use crate::knowledge::KnowledgeMatch;
+use crate::ollama_service::ChatMessage;
+use serde::{Deserialize, Serialize};
+
+// knowledge base excerpts injected into a prompt, each under a numbered marker the model is asked
+// to cite, the same markers map back to files and line ranges in the citations sent to the frontend
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Citation {
+    pub marker: String, // "[1]", as it appears in the prompt and in the answer
+    pub chunk_id: i64,
+    pub path: String,
+    pub heading: Option<String>,
+    pub start_line: i64,
+    pub end_line: i64,
+    pub score: f32,
+}
+
+// sent with the "citations" event before the reply starts streaming
+#[derive(Serialize, Clone, Debug)]
+pub struct CitationsEvent {
+    pub conversation_id: Option<String>,
+    pub message_id: Option<i64>,
+    pub citations: Vec<Citation>,
+    pub error: Option<String>, // retrieval failed and the prompt went out without excerpts
+}
+
+fn marker(index: usize) -> String {
+    format!("[{}]", index + 1)
+}
+
+pub fn relevant(matches: Vec<KnowledgeMatch>, min_score: f32) -> Vec<KnowledgeMatch> {
+    matches.into_iter().filter(|m| m.score >= min_score).collect()
+}
+
+pub fn citations(matches: &[KnowledgeMatch]) -> Vec<Citation> {
+    matches
+        .iter()
+        .enumerate()
+        .map(|(index, m)| Citation {
+            marker: marker(index),
+            chunk_id: m.chunk_id,
+            path: m.path.clone(),
+            heading: m.heading.clone(),
+            start_line: m.start_line,
+            end_line: m.end_line,
+            score: m.score,
+        })
+        .collect()
+}
+
+pub fn context_block(matches: &[KnowledgeMatch]) -> String {
+    let excerpts: Vec<String> = matches
+        .iter()
+        .enumerate()
+        .map(|(index, m)| {
+            let location = match &m.heading {
+                Some(heading) => format!("{} lines {}-{}, {}", m.path, m.start_line, m.end_line, heading),
+                None => format!("{} lines {}-{}", m.path, m.start_line, m.end_line),
+            };
+            // code keeps its indentation and is fenced so the model reads it as code
+            let content = match &m.language {
+                Some(language) => format!("```{}\n{}\n```", language, m.content.trim_matches('\n').trim_end()),
+                None => m.content.trim().to_string(),
+            };
+            format!("{} {}\n{}", marker(index), location, content)
+        })
+        .collect();
+    format!(
+        "Excerpts from the user's documents that may help with the next question. \
+         Use them when they are relevant and cite each excerpt you rely on by its marker, like [1]. \
+         If they do not cover the question, say so instead of guessing.\n\n{}",
+        excerpts.join("\n\n")
+    )
+}
+
+// the excerpts go after the leading system messages, which the context window never trims
+pub fn insert_context(messages: &mut Vec<ChatMessage>, context: &str) {
+    let position = messages.iter().take_while(|m| m.role == "system").count();
+    messages.insert(position, ChatMessage {
+        role: "system".to_string(),
+        content: context.to_string(),
+        images: None,
+    });
+}
+
+// /api/generate has no message list, the excerpts are put in front of the prompt instead
+pub fn augment_prompt(prompt: &str, context: &str) -> String {
+    format!("{}\n\nQuestion: {}", context, prompt)
+}
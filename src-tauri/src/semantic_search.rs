@@ -0,0 +1,279 @@
+use crate::crypto;
+use crate::knowledge;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// every message is embedded in the background and indexed for full-text search, a search ranks
+// messages by both and fuses the two rankings
+// messages are queued by triggers, so every write path feeds the index and the queue survives a restart
+// the full-text index holds plaintext, it is only kept while encryption is off
+
+// rank offset of reciprocal rank fusion, keeps the top few results of either ranking from dominating
+const RRF_K: f64 = 60.0;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SemanticSearchHit {
+    pub conversation_id: String,
+    pub conversation_title: String,
+    pub message_id: i64,
+    pub role: String,
+    pub timestamp: String,
+    pub snippet: String,
+    pub score: f64,
+    pub similarity: Option<f32>, // cosine similarity, None when only the keywords matched
+    pub keyword_rank: Option<usize>, // 1-based position among full-text matches
+}
+
+pub struct QueuedMessage {
+    pub id: i64,
+    pub content: String,
+    pub encrypted: bool, // stored encrypted, kept out of the full-text index
+}
+
+pub fn create_tables(conn: &Connection) -> SqlResult<()> {
+    let existed: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'embedding_queue')",
+        [],
+        |row| row.get(0),
+    )?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS message_embeddings (
+            message_id INTEGER PRIMARY KEY,
+            model TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            embedding TEXT NOT NULL,
+            FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+        );
+        CREATE TABLE IF NOT EXISTS embedding_queue (
+            message_id INTEGER PRIMARY KEY,
+            queued_at TEXT NOT NULL,
+            FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+        );
+        CREATE VIRTUAL TABLE IF NOT EXISTS message_search USING fts5(content, tokenize = 'porter unicode61');
+
+        CREATE TRIGGER IF NOT EXISTS messages_queue_insert AFTER INSERT ON messages BEGIN
+            INSERT OR IGNORE INTO embedding_queue (message_id, queued_at) VALUES (new.id, datetime('now'));
+        END;
+        CREATE TRIGGER IF NOT EXISTS messages_queue_update AFTER UPDATE OF content ON messages
+        WHEN old.content IS NOT new.content BEGIN
+            INSERT OR IGNORE INTO embedding_queue (message_id, queued_at) VALUES (new.id, datetime('now'));
+        END;
+        CREATE TRIGGER IF NOT EXISTS messages_search_delete AFTER DELETE ON messages BEGIN
+            DELETE FROM message_search WHERE rowid = old.id;
+        END;"
+    )?;
+
+    // messages written before the index existed
+    if !existed {
+        enqueue_all(conn)?;
+    }
+    Ok(())
+}
+
+// queues messages embedded with another model, run when the worker starts
+pub fn enqueue_stale(conn: &Connection, model: &str) -> SqlResult<usize> {
+    conn.execute(
+        "INSERT OR IGNORE INTO embedding_queue (message_id, queued_at)
+         SELECT message_id, datetime('now') FROM message_embeddings WHERE model <> ?1",
+        params![model],
+    )
+}
+
+pub fn enqueue_all(conn: &Connection) -> SqlResult<usize> {
+    conn.execute(
+        "INSERT OR IGNORE INTO embedding_queue (message_id, queued_at) SELECT id, datetime('now') FROM messages",
+        [],
+    )
+}
+
+// the full-text index is plaintext, it is emptied before encryption is turned on
+pub fn clear_keyword_index(conn: &Connection) -> SqlResult<usize> {
+    conn.execute("DELETE FROM message_search", [])
+}
+
+// the oldest queued messages that have finished streaming, fails while the database is locked
+pub fn next_batch(conn: &Connection, size: usize) -> Result<Vec<QueuedMessage>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT q.message_id, m.content FROM embedding_queue q
+         JOIN messages m ON m.id = q.message_id
+         WHERE m.status <> 'streaming'
+         ORDER BY q.message_id
+         LIMIT ?1"
+    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt.query_map(params![size as i64], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| format!("Failed to read embedding queue: {}", e))?;
+
+    let mut batch = Vec::new();
+    for row in rows {
+        let (id, stored) = row.map_err(|e| format!("Failed to read embedding queue: {}", e))?;
+        batch.push(QueuedMessage {
+            id,
+            content: crypto::open(&stored)?,
+            encrypted: crypto::is_encrypted(&stored),
+        });
+    }
+    Ok(batch)
+}
+
+// content hash and model a message was last embedded with
+pub fn embedded_state(conn: &Connection, message_id: i64) -> Result<Option<(String, String)>, String> {
+    let state: Option<(String, String)> = conn.query_row(
+        "SELECT content_hash, model FROM message_embeddings WHERE message_id = ?1",
+        params![message_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional().map_err(|e| format!("Failed to load embedding: {}", e))?;
+    state.map(|(hash, model)| Ok((crypto::open(&hash)?, model))).transpose()
+}
+
+// records a processed message, embedding is None when the stored one is still current
+pub fn store_message_index(
+    conn: &Connection,
+    message: &QueuedMessage,
+    model: &str,
+    hash: &str,
+    embedding: Option<&[f32]>,
+) -> Result<(), String> {
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+    if let Some(embedding) = embedding {
+        let encoded = crypto::seal(&BASE64.encode(knowledge::encode_embedding(embedding)))?;
+        tx.execute(
+            "INSERT INTO message_embeddings (message_id, model, content_hash, embedding) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT(message_id) DO UPDATE SET
+                model = excluded.model,
+                content_hash = excluded.content_hash,
+                embedding = excluded.embedding",
+            params![message.id, model, crypto::seal(hash)?, encoded],
+        ).map_err(|e| format!("Failed to save embedding: {}", e))?;
+    }
+
+    tx.execute("DELETE FROM message_search WHERE rowid = ?1", params![message.id])
+        .map_err(|e| format!("Failed to update search index: {}", e))?;
+    if !message.encrypted {
+        tx.execute(
+            "INSERT INTO message_search (rowid, content) VALUES (?1, ?2)",
+            params![message.id, &message.content],
+        ).map_err(|e| format!("Failed to update search index: {}", e))?;
+    }
+
+    tx.execute("DELETE FROM embedding_queue WHERE message_id = ?1", params![message.id])
+        .map_err(|e| format!("Failed to update embedding queue: {}", e))?;
+    tx.commit().map_err(|e| format!("Failed to commit transaction: {}", e))?;
+    Ok(())
+}
+
+// empty messages have nothing to embed, they only leave the queue and the index
+pub fn remove_message_index(conn: &Connection, message_id: i64) -> SqlResult<()> {
+    conn.execute("DELETE FROM message_embeddings WHERE message_id = ?1", params![message_id])?;
+    conn.execute("DELETE FROM message_search WHERE rowid = ?1", params![message_id])?;
+    conn.execute("DELETE FROM embedding_queue WHERE message_id = ?1", params![message_id])?;
+    Ok(())
+}
+
+pub fn queue_length(conn: &Connection) -> SqlResult<i64> {
+    conn.query_row("SELECT COUNT(*) FROM embedding_queue", [], |row| row.get(0))
+}
+
+fn query_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+// every word quoted so punctuation in the query can't be read as FTS5 syntax, any word may match
+fn fts_query(text: &str) -> Option<String> {
+    let words = query_words(text);
+    if words.is_empty() {
+        return None;
+    }
+    Some(words.iter().map(|word| format!("\"{}\"", word)).collect::<Vec<_>>().join(" OR "))
+}
+
+// message ids ordered by bm25, trashed conversations are filtered out when the hits are loaded
+pub fn keyword_ranking(conn: &Connection, query: &str, limit: usize) -> SqlResult<Vec<i64>> {
+    let Some(fts) = fts_query(query) else {
+        return Ok(Vec::new());
+    };
+    let mut stmt = conn.prepare("SELECT rowid FROM message_search WHERE message_search MATCH ?1 ORDER BY rank LIMIT ?2")?;
+    let rows = stmt.query_map(params![fts, limit as i64], |row| row.get(0))?;
+    rows.collect()
+}
+
+// the full-text index is empty while encryption is on, decrypted messages are matched in memory instead
+// and ranked by how many query words they contain
+pub fn encrypted_keyword_ranking(conn: &Connection, query: &str, limit: usize) -> Result<Vec<i64>, String> {
+    let words = query_words(query);
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare(
+        "SELECT m.id, m.content FROM messages m
+         JOIN conversations c ON c.id = m.conversation_id
+         WHERE c.deleted_at IS NULL AND m.status <> 'streaming'"
+    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| format!("Failed to query messages: {}", e))?;
+
+    let mut scored = Vec::new();
+    for row in rows {
+        let (id, stored) = row.map_err(|e| format!("Failed to query messages: {}", e))?;
+        let content = crypto::open(&stored)?.to_lowercase();
+        let matched = words.iter().filter(|word| content.contains(word.as_str())).count();
+        if matched > 0 {
+            scored.push((id, matched));
+        }
+    }
+    scored.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+    Ok(scored.into_iter().take(limit).map(|(id, _)| id).collect())
+}
+
+// message ids with their similarity to the query, over messages embedded with the same model
+pub fn vector_ranking(conn: &Connection, model: &str, query: &[f32], limit: usize) -> Result<Vec<(i64, f32)>, String> {
+    let mut stmt = conn.prepare(
+        "SELECT e.message_id, e.embedding FROM message_embeddings e
+         JOIN messages m ON m.id = e.message_id
+         JOIN conversations c ON c.id = m.conversation_id
+         WHERE e.model = ?1 AND c.deleted_at IS NULL"
+    ).map_err(|e| format!("Failed to prepare statement: {}", e))?;
+    let rows = stmt.query_map(params![model], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| format!("Failed to query embeddings: {}", e))?;
+
+    let mut scored = Vec::new();
+    for row in rows {
+        let (id, stored) = row.map_err(|e| format!("Failed to query embeddings: {}", e))?;
+        let bytes = BASE64.decode(crypto::open(&stored)?)
+            .map_err(|e| format!("Failed to decode embedding: {}", e))?;
+        scored.push((id, knowledge::cosine_similarity(query, &knowledge::decode_embedding(&bytes))));
+    }
+    scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scored.truncate(limit);
+    Ok(scored)
+}
+
+// reciprocal rank fusion, a message ranked well by either side rises and one found by both rises most
+// returns (message id, fused score, similarity, 1-based keyword rank), best first
+pub fn fuse(vector: &[(i64, f32)], keyword: &[i64]) -> Vec<(i64, f64, Option<f32>, Option<usize>)> {
+    let mut fused: HashMap<i64, (f64, Option<f32>, Option<usize>)> = HashMap::new();
+    for (rank, (id, similarity)) in vector.iter().enumerate() {
+        let entry = fused.entry(*id).or_insert((0.0, None, None));
+        entry.0 += 1.0 / (RRF_K + rank as f64 + 1.0);
+        entry.1 = Some(*similarity);
+    }
+    for (rank, id) in keyword.iter().enumerate() {
+        let entry = fused.entry(*id).or_insert((0.0, None, None));
+        entry.0 += 1.0 / (RRF_K + rank as f64 + 1.0);
+        entry.2 = Some(rank + 1);
+    }
+
+    let mut ranked: Vec<_> = fused
+        .into_iter()
+        .map(|(id, (score, similarity, keyword_rank))| (id, score, similarity, keyword_rank))
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(b.0.cmp(&a.0)));
+    ranked
+}
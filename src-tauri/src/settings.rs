@@ -0,0 +1,149 @@
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+// backend settings that aren't editor preferences, stored as one JSON value per key
+// in the preferences database so new fields can be added without a schema change
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct AppSettings {
+    // days a conversation stays in the trash before it is purged, 0 keeps it forever
+    pub trash_retention_days: i64,
+    // hours between automatic backups, 0 turns scheduled backups off
+    pub backup_interval_hours: i64,
+    // how many automatic backups to keep before the oldest are removed
+    pub backup_retention_count: i64,
+    // where automatic backups go, defaults to a backups folder in the app data directory
+    pub backup_directory: Option<String>,
+    // what to do when a conversation outgrows the context window: "drop_oldest", "keep_last" or "summarize"
+    pub context_strategy: String,
+    // messages kept by the "keep_last" strategy, system messages are always kept on top
+    pub context_keep_last: i64,
+    // largest num_ctx requested from Ollama, 0 uses the model's full trained length
+    pub context_window_limit: i64,
+    // tokens kept free for the reply
+    pub context_reserve_tokens: i64,
+    // fold older turns of long conversations into a rolling summary used in their place
+    pub summary_enabled: bool,
+    // model that writes the summaries, defaults to the conversation's model
+    pub summary_model: Option<String>,
+    // latest messages always sent verbatim
+    pub summary_keep_recent: i64,
+    // older messages that have to pile up before the summary is rolled forward
+    pub summary_batch_size: i64,
+    // ask a model for a title after the first exchange instead of truncating the first message
+    pub title_generation_enabled: bool,
+    // a small model is plenty, defaults to the conversation's model
+    pub title_model: Option<String>,
+    // Ollama model that embeds knowledge base chunks and queries, changing it needs a re-index
+    pub embedding_model: String,
+    // upper bound on the size of a knowledge base chunk, in estimated tokens
+    pub knowledge_chunk_tokens: i64,
+    // re-index knowledge base files as they are created, edited or deleted, takes effect on restart
+    pub knowledge_watch_enabled: bool,
+    // add knowledge base excerpts to every prompt, a prompt can still turn it on or off for itself
+    pub retrieval_enabled: bool,
+    // excerpts added to a prompt at most
+    pub retrieval_top_k: i64,
+    // cosine similarity an excerpt needs to be added
+    pub retrieval_min_score: f64,
+    // embed every message in the background for semantic_search_conversations
+    pub semantic_search_enabled: bool,
+    // images are downscaled so their longer side fits this many pixels before they are sent, 0 keeps the size
+    pub image_max_dimension: i64,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            trash_retention_days: 30,
+            backup_interval_hours: 0,
+            backup_retention_count: 7,
+            backup_directory: None,
+            context_strategy: "drop_oldest".to_string(),
+            context_keep_last: 20,
+            context_window_limit: 8192,
+            context_reserve_tokens: 1024,
+            summary_enabled: false,
+            summary_model: None,
+            summary_keep_recent: 10,
+            summary_batch_size: 10,
+            title_generation_enabled: false,
+            title_model: None,
+            embedding_model: "nomic-embed-text".to_string(),
+            knowledge_chunk_tokens: 400,
+            knowledge_watch_enabled: true,
+            retrieval_enabled: false,
+            retrieval_top_k: 5,
+            retrieval_min_score: 0.35,
+            semantic_search_enabled: true,
+            image_max_dimension: 1024,
+        }
+    }
+}
+
+pub fn open_preferences_db(app_data_dir: &Path) -> Result<Connection, String> {
+    std::fs::create_dir_all(app_data_dir)
+        .map_err(|e| format!("Failed to create app data directory: {}", e))?;
+
+    let conn = Connection::open(app_data_dir.join("editor_preferences.db"))
+        .map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS app_settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(conn)
+}
+
+// missing or unreadable keys fall back to their defaults
+pub fn load_settings(conn: &Connection) -> Result<AppSettings, String> {
+    let mut stmt = conn.prepare("SELECT key, value FROM app_settings")
+        .map_err(|e| e.to_string())?;
+
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map_err(|e| e.to_string())?;
+
+    let mut stored = Map::new();
+    for row in rows {
+        let (key, value) = row.map_err(|e| e.to_string())?;
+        if let Ok(value) = serde_json::from_str::<Value>(&value) {
+            stored.insert(key, value);
+        }
+    }
+
+    let mut merged = serde_json::to_value(AppSettings::default()).map_err(|e| e.to_string())?;
+    if let Value::Object(defaults) = &mut merged {
+        for (key, value) in stored {
+            if defaults.contains_key(&key) {
+                defaults.insert(key, value);
+            }
+        }
+    }
+
+    Ok(serde_json::from_value(merged).unwrap_or_else(|e| {
+        println!("Failed to read stored settings, using defaults: {}", e);
+        AppSettings::default()
+    }))
+}
+
+pub fn save_settings(conn: &Connection, settings: &AppSettings) -> Result<(), String> {
+    let Value::Object(fields) = serde_json::to_value(settings).map_err(|e| e.to_string())? else {
+        return Err("Settings must serialize to an object".to_string());
+    };
+
+    let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+    for (key, value) in fields {
+        tx.execute(
+            "INSERT INTO app_settings (key, value) VALUES (?1, ?2)
+             ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value.to_string()],
+        ).map_err(|e| e.to_string())?;
+    }
+    tx.commit().map_err(|e| e.to_string())
+}
@@ -0,0 +1,161 @@
+use crate::crypto;
+use crate::ollama_service::ChatMessage;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+
+// rolling summaries of long conversations, each one condenses the active branch up to and including
+// through_message_id so the prompt can be the summary plus the turns after it
+// they live beside the message tree rather than in it, a summary is never a node the user branches from
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConversationSummary {
+    pub id: i64,
+    pub conversation_id: String,
+    pub kind: String, // always "summary", lets the frontend render it next to messages
+    pub through_message_id: i64,
+    pub content: String,
+    pub model: String,
+    pub message_count: i64, // messages condensed into this summary
+    pub stale: bool, // an earlier message changed, regenerated on the next refresh
+    pub edited: bool,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+pub fn create_table(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS conversation_summaries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id TEXT NOT NULL,
+            through_message_id INTEGER NOT NULL UNIQUE,
+            content TEXT NOT NULL,
+            model TEXT NOT NULL,
+            message_count INTEGER NOT NULL,
+            stale INTEGER NOT NULL DEFAULT 0,
+            edited INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            FOREIGN KEY (conversation_id) REFERENCES conversations(id) ON DELETE CASCADE,
+            FOREIGN KEY (through_message_id) REFERENCES messages(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_summaries_conversation ON conversation_summaries (conversation_id);"
+    )
+}
+
+fn map_summary(row: &rusqlite::Row) -> SqlResult<ConversationSummary> {
+    let content: String = row.get(4)?;
+    Ok(ConversationSummary {
+        id: row.get(0)?,
+        conversation_id: row.get(1)?,
+        kind: "summary".to_string(),
+        through_message_id: row.get(2)?,
+        model: row.get(3)?,
+        content: crypto::open(&content)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, e.into()))?,
+        message_count: row.get(5)?,
+        stale: row.get::<_, i64>(6)? != 0,
+        edited: row.get::<_, i64>(7)? != 0,
+        created_at: row.get(8)?,
+        updated_at: row.get(9)?,
+    })
+}
+
+const SUMMARY_COLUMNS: &str =
+    "id, conversation_id, through_message_id, model, content, message_count, stale, edited, created_at, updated_at";
+
+pub fn load_summaries(conn: &Connection, conversation_id: &str) -> SqlResult<Vec<ConversationSummary>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM conversation_summaries WHERE conversation_id = ?1",
+        SUMMARY_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![conversation_id], map_summary)?;
+    rows.collect()
+}
+
+pub fn load_summary(conn: &Connection, summary_id: i64) -> SqlResult<Option<ConversationSummary>> {
+    conn.query_row(
+        &format!("SELECT {} FROM conversation_summaries WHERE id = ?1", SUMMARY_COLUMNS),
+        params![summary_id],
+        map_summary,
+    ).optional()
+}
+
+// the summary that reaches furthest along the given branch, with its index in the path
+pub fn latest_on_path(summaries: Vec<ConversationSummary>, path_ids: &[i64]) -> Option<(usize, ConversationSummary)> {
+    summaries
+        .into_iter()
+        .filter_map(|summary| {
+            path_ids.iter().position(|id| *id == summary.through_message_id).map(|index| (index, summary))
+        })
+        .max_by_key(|(index, _)| *index)
+}
+
+// inserts a new summary or replaces the one ending at the same message
+pub fn store_summary(
+    conn: &Connection,
+    conversation_id: &str,
+    through_message_id: i64,
+    content: &str,
+    model: &str,
+    message_count: i64,
+) -> Result<(), String> {
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO conversation_summaries
+            (conversation_id, through_message_id, content, model, message_count, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+         ON CONFLICT(through_message_id) DO UPDATE SET
+            content = excluded.content,
+            model = excluded.model,
+            message_count = excluded.message_count,
+            stale = 0,
+            edited = 0,
+            updated_at = excluded.updated_at",
+        params![conversation_id, through_message_id, crypto::seal(content)?, model, message_count, &now],
+    ).map_err(|e| format!("Failed to save summary: {}", e))?;
+    Ok(())
+}
+
+pub fn update_summary_content(conn: &Connection, summary_id: i64, content: &str) -> Result<(), String> {
+    let updated = conn.execute(
+        "UPDATE conversation_summaries SET content = ?1, edited = 1, stale = 0, updated_at = ?2 WHERE id = ?3",
+        params![crypto::seal(content)?, Utc::now().to_rfc3339(), summary_id],
+    ).map_err(|e| format!("Failed to update summary: {}", e))?;
+    if updated == 0 {
+        return Err(format!("Summary {} not found", summary_id));
+    }
+    Ok(())
+}
+
+// asks for a summary that folds the new turns into the previous summary, if there is one
+pub fn rolling_summary_request(previous: Option<&str>, turns: &[ChatMessage]) -> Vec<ChatMessage> {
+    let transcript: Vec<String> = turns
+        .iter()
+        .map(|m| format!("{}: {}", m.role, m.content))
+        .collect();
+    let content = match previous {
+        Some(previous) => format!(
+            "Summary so far:\n{}\n\nNew messages:\n{}",
+            previous.trim(),
+            transcript.join("\n\n")
+        ),
+        None => transcript.join("\n\n"),
+    };
+    vec![
+        ChatMessage {
+            role: "system".to_string(),
+            content: "Write a summary of the conversation below that can replace the original messages. \
+                      If a summary so far is given, update it with the new messages instead of starting over. \
+                      Keep names, facts, decisions, code identifiers and open questions. \
+                      Reply with the summary only."
+                .to_string(),
+            images: None,
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content,
+            images: None,
+        },
+    ]
+}
@@ -0,0 +1,341 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// reusable prompts with {{variable}} placeholders, filled in before sending, shared between people as
+// template pack files
+
+// bump when the template pack layout changes so importers can tell versions apart
+pub const TEMPLATE_PACK_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PromptTemplate {
+    pub id: i64,
+    pub name: String,
+    pub description: String,
+    pub body: String,
+    pub model: Option<String>, // used instead of the selected model when set
+    pub options: Option<HashMap<String, Value>>, // Ollama options like temperature
+    pub variables: Vec<String>, // placeholder names in order of first use
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+// what the frontend sends to create or update a template, and what a pack file holds per template
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TemplateDraft {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub body: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub options: Option<HashMap<String, Value>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TemplatePack {
+    pub format_version: u32,
+    pub exported_at: String,
+    pub templates: Vec<TemplateDraft>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RenderedTemplate {
+    pub prompt: String,
+    pub model: Option<String>,
+    pub options: Option<HashMap<String, Value>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TemplateImportReport {
+    pub created: usize,
+    pub replaced: usize,
+    pub skipped: Vec<String>, // names that already existed, or templates that failed validation
+}
+
+pub fn create_table(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS prompt_templates (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            description TEXT NOT NULL DEFAULT '',
+            body TEXT NOT NULL,
+            model TEXT,
+            options TEXT,
+            created_at TEXT NOT NULL,
+            updated_at TEXT NOT NULL
+        );"
+    )
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+// a placeholder is {{name}} with optional spaces inside the braces, `\{{` keeps the braces literal
+enum Segment<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+fn segments(body: &str) -> Result<Vec<Segment<'_>>, String> {
+    let mut segments = Vec::new();
+    let mut rest = body;
+    while let Some(open) = rest.find("{{") {
+        if rest[..open].ends_with('\\') {
+            segments.push(Segment::Text(&rest[..open - 1]));
+            segments.push(Segment::Text("{{"));
+            rest = &rest[open + 2..];
+            continue;
+        }
+        let Some(close) = rest[open + 2..].find("}}") else {
+            return Err(format!("Unclosed placeholder at \"{}\"", preview(&rest[open..])));
+        };
+        let name = rest[open + 2..open + 2 + close].trim();
+        if name.is_empty() || !name.chars().all(is_name_char) {
+            return Err(format!(
+                "Invalid placeholder \"{}\", names may only contain letters, digits, '_', '-' and '.'",
+                &rest[open..open + 4 + close]
+            ));
+        }
+        segments.push(Segment::Text(&rest[..open]));
+        segments.push(Segment::Variable(name));
+        rest = &rest[open + 4 + close..];
+    }
+    segments.push(Segment::Text(rest));
+    Ok(segments)
+}
+
+fn preview(text: &str) -> String {
+    text.chars().take(30).collect()
+}
+
+// placeholder names in order of first use, an error for malformed placeholders
+pub fn variables(body: &str) -> Result<Vec<String>, String> {
+    let mut seen = HashSet::new();
+    Ok(segments(body)?
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Variable(name) if seen.insert(name) => Some(name.to_string()),
+            _ => None,
+        })
+        .collect())
+}
+
+// fills in every placeholder, values that no placeholder uses are ignored, an empty value is still a value
+pub fn render(body: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    let segments = segments(body)?;
+    let mut missing: Vec<&str> = Vec::new();
+    for segment in &segments {
+        if let Segment::Variable(name) = segment {
+            if !values.contains_key(*name) && !missing.contains(name) {
+                missing.push(name);
+            }
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!("Missing values for template variables: {}", missing.join(", ")));
+    }
+
+    Ok(segments
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text,
+            Segment::Variable(name) => values[name].as_str(),
+        })
+        .collect())
+}
+
+// trims the draft and checks it can be saved
+pub fn validate(draft: TemplateDraft) -> Result<TemplateDraft, String> {
+    let name = draft.name.trim().to_string();
+    if name.is_empty() {
+        return Err("Template name cannot be empty".to_string());
+    }
+    if draft.body.trim().is_empty() {
+        return Err(format!("Template \"{}\" has an empty body", name));
+    }
+    variables(&draft.body).map_err(|e| format!("Template \"{}\": {}", name, e))?;
+    Ok(TemplateDraft {
+        name,
+        description: draft.description.trim().to_string(),
+        body: draft.body,
+        model: draft.model.map(|m| m.trim().to_string()).filter(|m| !m.is_empty()),
+        options: draft.options.filter(|o| !o.is_empty()),
+    })
+}
+
+fn encode_options(options: &Option<HashMap<String, Value>>) -> Result<Option<String>, String> {
+    options
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| format!("Failed to encode template options: {}", e))
+}
+
+fn map_template(row: &rusqlite::Row) -> SqlResult<PromptTemplate> {
+    let body: String = row.get(3)?;
+    let options: Option<String> = row.get(5)?;
+    Ok(PromptTemplate {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        description: row.get(2)?,
+        // a template stored by an older build with a placeholder this one rejects still loads
+        variables: variables(&body).unwrap_or_default(),
+        body,
+        model: row.get(4)?,
+        options: options.and_then(|o| serde_json::from_str(&o).ok()),
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+const TEMPLATE_COLUMNS: &str = "id, name, description, body, model, options, created_at, updated_at";
+
+pub fn load_templates(conn: &Connection) -> SqlResult<Vec<PromptTemplate>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM prompt_templates ORDER BY name COLLATE NOCASE",
+        TEMPLATE_COLUMNS
+    ))?;
+    let rows = stmt.query_map([], map_template)?;
+    rows.collect()
+}
+
+pub fn load_template(conn: &Connection, template_id: i64) -> SqlResult<Option<PromptTemplate>> {
+    conn.query_row(
+        &format!("SELECT {} FROM prompt_templates WHERE id = ?1", TEMPLATE_COLUMNS),
+        params![template_id],
+        map_template,
+    ).optional()
+}
+
+fn find_by_name(conn: &Connection, name: &str) -> Result<Option<i64>, String> {
+    conn.query_row(
+        "SELECT id FROM prompt_templates WHERE name = ?1",
+        params![name],
+        |row| row.get(0),
+    ).optional().map_err(|e| format!("Failed to check templates: {}", e))
+}
+
+pub fn create_template(conn: &Connection, draft: TemplateDraft) -> Result<i64, String> {
+    let draft = validate(draft)?;
+    if find_by_name(conn, &draft.name)?.is_some() {
+        return Err(format!("A template named \"{}\" already exists", draft.name));
+    }
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO prompt_templates (name, description, body, model, options, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)",
+        params![&draft.name, &draft.description, &draft.body, &draft.model, encode_options(&draft.options)?, &now],
+    ).map_err(|e| format!("Failed to create template: {}", e))?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn update_template(conn: &Connection, template_id: i64, draft: TemplateDraft) -> Result<(), String> {
+    let draft = validate(draft)?;
+    if find_by_name(conn, &draft.name)?.is_some_and(|id| id != template_id) {
+        return Err(format!("A template named \"{}\" already exists", draft.name));
+    }
+    let updated = conn.execute(
+        "UPDATE prompt_templates SET name = ?1, description = ?2, body = ?3, model = ?4, options = ?5, updated_at = ?6
+         WHERE id = ?7",
+        params![
+            &draft.name,
+            &draft.description,
+            &draft.body,
+            &draft.model,
+            encode_options(&draft.options)?,
+            Utc::now().to_rfc3339(),
+            template_id,
+        ],
+    ).map_err(|e| format!("Failed to update template: {}", e))?;
+    if updated == 0 {
+        return Err(format!("Template {} not found", template_id));
+    }
+    Ok(())
+}
+
+pub fn delete_template(conn: &Connection, template_id: i64) -> Result<(), String> {
+    let deleted = conn.execute("DELETE FROM prompt_templates WHERE id = ?1", params![template_id])
+        .map_err(|e| format!("Failed to delete template: {}", e))?;
+    if deleted == 0 {
+        return Err(format!("Template {} not found", template_id));
+    }
+    Ok(())
+}
+
+pub fn to_pack(templates: &[PromptTemplate]) -> TemplatePack {
+    TemplatePack {
+        format_version: TEMPLATE_PACK_VERSION,
+        exported_at: Utc::now().to_rfc3339(),
+        templates: templates
+            .iter()
+            .map(|t| TemplateDraft {
+                name: t.name.clone(),
+                description: t.description.clone(),
+                body: t.body.clone(),
+                model: t.model.clone(),
+                options: t.options.clone(),
+            })
+            .collect(),
+    }
+}
+
+// a template pack, or a single template exported on its own
+pub fn read_pack(path: &Path) -> Result<Vec<TemplateDraft>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let value: Value = serde_json::from_str(&text)
+        .map_err(|e| format!("{} is not valid JSON: {}", path.display(), e))?;
+
+    if value.get("templates").is_some() {
+        let pack: TemplatePack = serde_json::from_value(value)
+            .map_err(|e| format!("{} is not a template pack: {}", path.display(), e))?;
+        if pack.format_version > TEMPLATE_PACK_VERSION {
+            return Err(format!(
+                "{} was exported by a newer version (format {}), update the app to import it",
+                path.display(), pack.format_version
+            ));
+        }
+        return Ok(pack.templates);
+    }
+    serde_json::from_value::<TemplateDraft>(value)
+        .map(|draft| vec![draft])
+        .map_err(|e| format!("{} is not a template pack: {}", path.display(), e))
+}
+
+// templates whose name is taken are replaced when asked to, otherwise skipped and reported
+pub fn import_drafts(conn: &Connection, drafts: Vec<TemplateDraft>, replace: bool) -> Result<TemplateImportReport, String> {
+    let tx = conn.unchecked_transaction()
+        .map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let mut report = TemplateImportReport::default();
+
+    for draft in drafts {
+        let draft = match validate(draft) {
+            Ok(draft) => draft,
+            Err(e) => {
+                report.skipped.push(e);
+                continue;
+            }
+        };
+        match find_by_name(&tx, &draft.name)? {
+            Some(id) if replace => {
+                update_template(&tx, id, draft)?;
+                report.replaced += 1;
+            }
+            Some(_) => report.skipped.push(format!("\"{}\" already exists", draft.name)),
+            None => {
+                create_template(&tx, draft)?;
+                report.created += 1;
+            }
+        }
+    }
+
+    tx.commit().map_err(|e| format!("Failed to commit transaction: {}", e))?;
+    Ok(report)
+}
@@ -0,0 +1,122 @@
+use crate::ollama_service::ChatMessage;
+use serde::{Deserialize, Serialize};
+
+const MAX_TITLE_CHARS: usize = 50;
+// how much of each message the title model sees
+const MAX_EXCERPT_CHARS: usize = 1500;
+
+// where a conversation's title came from, generated, imported and user titles are never replaced by the
+// truncated fallback
+pub const SOURCE_DEFAULT: &str = "default";
+pub const SOURCE_FALLBACK: &str = "fallback";
+pub const SOURCE_GENERATED: &str = "generated";
+pub const SOURCE_IMPORTED: &str = "imported";
+pub const SOURCE_USER: &str = "user"; // also every title from before titles were generated
+pub const SOURCE_FAILED: &str = "failed"; // the model failed once, it is asked again after the next reply
+pub const SOURCE_GAVE_UP: &str = "gave_up"; // the retry failed too, only retitling asks again
+
+// whether a finished reply should have the model title its conversation
+pub fn needs_title(source: &str) -> bool {
+    matches!(source, SOURCE_DEFAULT | SOURCE_FALLBACK | SOURCE_FAILED)
+}
+
+// the source stored when the model gave no usable title
+pub fn failed_source(previous: &str) -> &'static str {
+    match previous {
+        SOURCE_FAILED | SOURCE_GAVE_UP => SOURCE_GAVE_UP,
+        _ => SOURCE_FAILED,
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RetitleSummary {
+    pub generated: usize,
+    pub fallback: usize, // the model failed or gave an unusable title
+    pub skipped: usize, // no user message to title from
+    pub failed: Vec<String>,
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> Option<String> {
+    let mut chars = text.char_indices();
+    chars.nth(max_chars).map(|(end, _)| text[..end].to_string())
+}
+
+// the first message cut at a character boundary, used when no model title is available
+pub fn fallback_title(first_message: &str) -> String {
+    let cleaned = first_message.split_whitespace().collect::<Vec<_>>().join(" ");
+    if cleaned.is_empty() {
+        return "New Conversation".to_string();
+    }
+    match truncate_chars(&cleaned, MAX_TITLE_CHARS) {
+        Some(truncated) => format!("{}...", truncated.trim_end()),
+        None => cleaned,
+    }
+}
+
+pub fn title_request(first_exchange: &[ChatMessage]) -> Vec<ChatMessage> {
+    let transcript: Vec<String> = first_exchange
+        .iter()
+        .map(|m| {
+            let excerpt = truncate_chars(&m.content, MAX_EXCERPT_CHARS).unwrap_or_else(|| m.content.clone());
+            format!("{}: {}", m.role, excerpt)
+        })
+        .collect();
+    vec![
+        ChatMessage {
+            role: "system".to_string(),
+            content: "Write a concise title of at most six words for the conversation below. \
+                      Use the conversation's language. Reply with the title only, without quotes or punctuation at the end."
+                .to_string(),
+            images: None,
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: transcript.join("\n\n"),
+            images: None,
+        },
+    ]
+}
+
+// small models like to wrap titles in quotes, prefix them or explain themselves, keep just the title
+pub fn clean_title(raw: &str) -> Option<String> {
+    let line = raw.lines().map(|l| l.trim()).find(|l| !l.is_empty())?;
+    let line = line
+        .strip_prefix("Title:")
+        .or_else(|| line.strip_prefix("title:"))
+        .unwrap_or(line)
+        .trim()
+        .trim_matches(|c: char| matches!(c, '"' | '\'' | '*' | '#' | '`' | '“' | '”'))
+        .trim_end_matches(['.', ':'])
+        .trim();
+    if line.is_empty() {
+        return None;
+    }
+    Some(match truncate_chars(line, MAX_TITLE_CHARS) {
+        Some(truncated) => format!("{}...", truncated.trim_end()),
+        None => line.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_title_cuts_multibyte_text_at_a_character_boundary() {
+        let first_message = "日本語のテキスト".repeat(10);
+        let title = fallback_title(&first_message);
+        assert_eq!(title, format!("{}...", first_message.chars().take(MAX_TITLE_CHARS).collect::<String>()));
+        assert_eq!(title.chars().count(), MAX_TITLE_CHARS + 3);
+    }
+
+    #[test]
+    fn fallback_title_of_whitespace_is_new_conversation() {
+        assert_eq!(fallback_title(" \n\t "), "New Conversation");
+    }
+
+    #[test]
+    fn clean_title_cuts_multibyte_text_at_a_character_boundary() {
+        let raw = "é".repeat(60);
+        assert_eq!(clean_title(&raw), Some(format!("{}...", "é".repeat(MAX_TITLE_CHARS))));
+    }
+}
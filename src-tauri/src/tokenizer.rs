@@ -0,0 +1,276 @@
+use crate::ollama_service::OllamaService;
+use fancy_regex::Regex;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Arc, Mutex};
+
+// counts tokens with the model's own vocabulary, read from the GGUF metadata Ollama exposes through /api/show
+// models whose tokenizer isn't supported fall back to a character-class heuristic
+
+// pre-tokenizer used by llama 3, qwen2 and most recent byte-level BPE models
+const LLAMA3_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+const GPT2_PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+
+enum Vocabulary {
+    // byte-level BPE, pieces are merged by the rank of their merge rule
+    Bpe {
+        pattern: Regex,
+        merges: HashMap<(String, String), usize>,
+        byte_chars: Box<[char; 256]>,
+    },
+    // sentencepiece, pieces are merged by the score of the resulting token, unknown characters fall back to bytes
+    SentencePiece {
+        scores: HashMap<String, f32>,
+    },
+}
+
+pub struct Tokenizer {
+    vocabulary: Vocabulary,
+}
+
+// None marks a model whose tokenizer couldn't be loaded so it isn't fetched again
+static TOKENIZERS: Mutex<Option<HashMap<String, Option<Arc<Tokenizer>>>>> = Mutex::new(None);
+
+impl Tokenizer {
+    pub fn from_model_info(info: &HashMap<String, Value>) -> Option<Self> {
+        let strings = |key: &str| -> Option<Vec<String>> {
+            info.get(key)?.as_array()?.iter().map(|v| v.as_str().map(|s| s.to_string())).collect()
+        };
+        let tokens = strings("tokenizer.ggml.tokens")?;
+
+        let vocabulary = match info.get("tokenizer.ggml.model").and_then(|m| m.as_str())? {
+            "gpt2" => {
+                let merges = strings("tokenizer.ggml.merges")?
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(rank, merge)| {
+                        let (left, right) = merge.split_once(' ')?;
+                        Some(((left.to_string(), right.to_string()), rank))
+                    })
+                    .collect();
+                let pattern = match info.get("tokenizer.ggml.pre").and_then(|p| p.as_str()) {
+                    Some("gpt-2") | Some("default") | None => GPT2_PATTERN,
+                    _ => LLAMA3_PATTERN,
+                };
+                Vocabulary::Bpe {
+                    pattern: Regex::new(pattern).ok()?,
+                    merges,
+                    byte_chars: Box::new(byte_chars()),
+                }
+            }
+            "llama" => {
+                let scores = info.get("tokenizer.ggml.scores")?.as_array()?;
+                Vocabulary::SentencePiece {
+                    scores: tokens
+                        .into_iter()
+                        .zip(scores.iter().map(|s| s.as_f64().unwrap_or(0.0) as f32))
+                        .collect(),
+                }
+            }
+            _ => return None,
+        };
+
+        Some(Self { vocabulary })
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match &self.vocabulary {
+            Vocabulary::Bpe { pattern, merges, byte_chars } => pattern
+                .find_iter(text)
+                .filter_map(|m| m.ok())
+                .map(|piece| {
+                    let symbols = piece.as_str().bytes().map(|b| byte_chars[b as usize].to_string()).collect();
+                    merge_symbols(symbols, |left, right| {
+                        merges.get(&(left.to_string(), right.to_string())).map(|rank| -(*rank as f32))
+                    }).len()
+                })
+                .sum(),
+            Vocabulary::SentencePiece { scores } => {
+                // words are merged separately, sentencepiece tokens almost never span a word boundary
+                let normalized = format!("▁{}", text.replace(' ', "▁"));
+                split_words(&normalized)
+                    .map(|word| {
+                        let symbols = word.chars().map(|c| c.to_string()).collect();
+                        merge_symbols(symbols, |left, right| scores.get(&format!("{}{}", left, right)).copied())
+                            .iter()
+                            .map(|symbol| if scores.contains_key(symbol) { 1 } else { symbol.len() })
+                            .sum::<usize>()
+                    })
+                    .sum()
+            }
+        }
+    }
+}
+
+// an adjacent pair that can be merged, with the lengths both symbols had when it was queued
+struct Candidate {
+    priority: f32,
+    left: usize,
+    right: usize,
+    left_len: usize,
+    right_len: usize,
+}
+
+// best priority first, ties go to the leftmost pair
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.total_cmp(&other.priority).then(other.left.cmp(&self.left))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+fn candidate(symbols: &[String], left: usize, right: usize, priority: &impl Fn(&str, &str) -> Option<f32>) -> Option<Candidate> {
+    priority(&symbols[left], &symbols[right]).map(|priority| Candidate {
+        priority,
+        left,
+        right,
+        left_len: symbols[left].len(),
+        right_len: symbols[right].len(),
+    })
+}
+
+// repeatedly merges the adjacent pair with the best priority until no pair can be merged
+// pairs wait in a heap and symbols are linked to their neighbours, so a long piece isn't rescanned after
+// every merge, a queued pair whose symbols have since changed is skipped when it comes up
+fn merge_symbols(mut symbols: Vec<String>, priority: impl Fn(&str, &str) -> Option<f32>) -> Vec<String> {
+    let count = symbols.len();
+    let mut previous: Vec<Option<usize>> = (0..count).map(|i| i.checked_sub(1)).collect();
+    let mut next: Vec<Option<usize>> = (0..count).map(|i| Some(i + 1).filter(|j| *j < count)).collect();
+    let mut merged = vec![false; count];
+    let mut heap: BinaryHeap<Candidate> = (1..count)
+        .filter_map(|right| candidate(&symbols, right - 1, right, &priority))
+        .collect();
+
+    while let Some(pair) = heap.pop() {
+        let current = !merged[pair.left]
+            && !merged[pair.right]
+            && next[pair.left] == Some(pair.right)
+            && symbols[pair.left].len() == pair.left_len
+            && symbols[pair.right].len() == pair.right_len;
+        if !current {
+            continue;
+        }
+        let right = std::mem::take(&mut symbols[pair.right]);
+        symbols[pair.left].push_str(&right);
+        merged[pair.right] = true;
+        next[pair.left] = next[pair.right];
+        if let Some(after) = next[pair.left] {
+            previous[after] = Some(pair.left);
+            heap.extend(candidate(&symbols, pair.left, after, &priority));
+        }
+        if let Some(before) = previous[pair.left] {
+            heap.extend(candidate(&symbols, before, pair.left, &priority));
+        }
+    }
+
+    symbols.into_iter().zip(merged).filter(|(_, merged)| !merged).map(|(symbol, _)| symbol).collect()
+}
+
+// splits before every word marker, so each piece starts with "▁"
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    let mut starts: Vec<usize> = text.match_indices('▁').map(|(i, _)| i).collect();
+    starts.dedup_by(|next, prev| text[*prev..*next].chars().all(|c| c == '▁'));
+    starts.push(text.len());
+    (0..starts.len() - 1).map(move |i| &text[starts[i]..starts[i + 1]])
+}
+
+// the printable stand-ins GPT-2 style vocabularies use for raw bytes
+fn byte_chars() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut extra = 0;
+    for byte in 0..=255u32 {
+        let printable = (0x21..=0x7e).contains(&byte) || (0xa1..=0xac).contains(&byte) || (0xae..=0xff).contains(&byte);
+        chars[byte as usize] = if printable {
+            char::from_u32(byte).unwrap()
+        } else {
+            extra += 1;
+            char::from_u32(255 + extra).unwrap()
+        };
+    }
+    chars
+}
+
+// rough count for models without a usable tokenizer, latin words average about four characters a token,
+// CJK characters are usually a token each and runs of punctuation in code split into short pieces
+pub fn heuristic_count(text: &str) -> usize {
+    let mut tokens = 0.0;
+    let mut word_chars = 0;
+    let mut symbol_chars = 0;
+    let flush = |count: &mut usize, per_token: f64, tokens: &mut f64| {
+        if *count > 0 {
+            *tokens += (*count as f64 / per_token).ceil();
+            *count = 0;
+        }
+    };
+
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            flush(&mut symbol_chars, 2.0, &mut tokens);
+            word_chars += 1;
+        } else if c.is_whitespace() {
+            flush(&mut word_chars, 4.0, &mut tokens);
+            flush(&mut symbol_chars, 2.0, &mut tokens);
+            if c == '\n' {
+                tokens += 0.5;
+            }
+        } else if c.is_ascii() {
+            flush(&mut word_chars, 4.0, &mut tokens);
+            symbol_chars += 1;
+        } else {
+            flush(&mut word_chars, 4.0, &mut tokens);
+            flush(&mut symbol_chars, 2.0, &mut tokens);
+            tokens += if c as u32 >= 0x2e80 { 1.0 } else { 0.5 };
+        }
+    }
+    flush(&mut word_chars, 4.0, &mut tokens);
+    flush(&mut symbol_chars, 2.0, &mut tokens);
+    tokens.ceil() as usize
+}
+
+fn cached(model: &str) -> Option<Option<Arc<Tokenizer>>> {
+    TOKENIZERS.lock().unwrap().as_ref()?.get(model).cloned()
+}
+
+// fetches the model's vocabulary once and keeps it for the rest of the session
+pub async fn load(model: &str) -> Option<Arc<Tokenizer>> {
+    if let Some(tokenizer) = cached(model) {
+        return tokenizer;
+    }
+
+    let tokenizer = match OllamaService::new(None).get_model_metadata(model).await {
+        Ok(info) => Tokenizer::from_model_info(&info).map(Arc::new),
+        Err(e) => {
+            println!("Failed to load tokenizer for {}: {}", model, e);
+            return None; // not cached, Ollama may just not be running yet
+        }
+    };
+    if tokenizer.is_none() {
+        println!("No supported tokenizer for {}, token counts are estimated", model);
+    }
+
+    TOKENIZERS.lock().unwrap().get_or_insert_with(HashMap::new).insert(model.to_string(), tokenizer.clone());
+    tokenizer
+}
+
+// counts with the model's tokenizer when it has been loaded, otherwise estimates
+pub fn count_tokens(model: Option<&str>, text: &str) -> i64 {
+    let tokenizer = model.and_then(cached).flatten();
+    match tokenizer {
+        Some(tokenizer) => tokenizer.count(text) as i64,
+        None => heuristic_count(text) as i64,
+    }
+}
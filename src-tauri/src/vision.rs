@@ -0,0 +1,110 @@
+use crate::knowledge;
+use crate::ollama_service::ModelInfo;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageReader, Limits};
+use std::collections::VecDeque;
+use std::io::Cursor;
+use std::sync::Mutex;
+
+// images on their way to a model, checked against what the model can read and normalized so a
+// full resolution screenshot doesn't turn into a request of tens of megabytes
+
+const JPEG_QUALITY: u8 = 85;
+// decoding stops past this much memory, an image header can claim any size it likes
+const MAX_DECODE_BYTES: u64 = 512 * 1024 * 1024;
+// preprocessed images kept in memory, the oldest are dropped first past this size
+const CACHE_MAX_BYTES: usize = 64 * 1024 * 1024;
+
+// a conversation's images are sent again on every turn, they are converted once per content hash and
+// size limit and the base64 result reused, a failed conversion is kept too so a broken image isn't decoded
+// again, failing to read one is not
+struct CachedImage {
+    hash: String,
+    max_dimension: u32,
+    result: Result<String, String>,
+}
+
+static CACHE: Mutex<VecDeque<CachedImage>> = Mutex::new(VecDeque::new());
+
+// None when Ollama doesn't report capabilities, older versions don't
+pub fn supports_vision(info: &ModelInfo) -> Option<bool> {
+    info.capabilities.as_ref().map(|capabilities| capabilities.iter().any(|c| c == "vision"))
+}
+
+// decodes png, jpeg, webp, gif or bmp, applies the exif orientation, downscales to max_dimension and
+// re-encodes, which drops exif and every other piece of metadata along the way
+// transparent images stay png, everything else becomes jpeg
+pub fn preprocess(bytes: &[u8], max_dimension: u32) -> Result<Vec<u8>, String> {
+    let mut reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(|e| format!("Failed to decode image: {}", e))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| format!("Failed to decode image: {}", e))?;
+    image.apply_orientation(orientation);
+
+    // resize keeps the aspect ratio, the result fits inside the square
+    if max_dimension > 0 && (image.width() > max_dimension || image.height() > max_dimension) {
+        image = image.resize(max_dimension, max_dimension, FilterType::Triangle);
+    }
+
+    let mut encoded = Vec::new();
+    let result = if image.color().has_alpha() {
+        image.to_rgba8().write_with_encoder(PngEncoder::new(&mut encoded))
+    } else {
+        image.to_rgb8().write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, JPEG_QUALITY))
+    };
+    result.map_err(|e| format!("Failed to encode image: {}", e))?;
+    Ok(encoded)
+}
+
+fn cache_size(cache: &VecDeque<CachedImage>) -> usize {
+    cache.iter().map(|image| image.result.as_ref().map_or_else(String::len, String::len)).sum()
+}
+
+// preprocess through the cache, read is only called on a miss, the result is base64 encoded
+// the lock isn't held while converting, two callers racing on the same image both do the work once
+pub fn preprocess_cached(
+    hash: &str,
+    max_dimension: u32,
+    read: impl FnOnce() -> Result<Vec<u8>, String>,
+) -> Result<String, String> {
+    {
+        let cache = CACHE.lock().unwrap();
+        if let Some(image) = cache.iter().find(|image| image.hash == hash && image.max_dimension == max_dimension) {
+            return image.result.clone();
+        }
+    }
+
+    let bytes = read()?;
+    let result = preprocess(&bytes, max_dimension).map(|bytes| BASE64.encode(bytes));
+    let mut cache = CACHE.lock().unwrap();
+    cache.retain(|image| !(image.hash == hash && image.max_dimension == max_dimension));
+    cache.push_back(CachedImage { hash: hash.to_string(), max_dimension, result: result.clone() });
+    while cache.len() > 1 && cache_size(&cache) > CACHE_MAX_BYTES {
+        cache.pop_front();
+    }
+    result
+}
+
+// decrypted images don't stay in memory once the database is locked
+pub fn clear_cache() {
+    CACHE.lock().unwrap().clear();
+}
+
+// preprocess for images that arrive base64 encoded, like the images of a prompt sent by the frontend
+pub fn preprocess_base64(encoded: &str, max_dimension: u32) -> Result<String, String> {
+    let hash = knowledge::content_hash(encoded.trim().as_bytes());
+    preprocess_cached(&hash, max_dimension, || {
+        BASE64.decode(encoded.trim()).map_err(|e| format!("Failed to decode image data: {}", e))
+    })
+}
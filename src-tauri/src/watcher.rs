@@ -0,0 +1,67 @@
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+// file system watching for knowledge sources, changed paths are batched so an editor saving through
+// a temp file or a git checkout touching hundreds of files becomes a single sync
+
+// a batch is handed over once no event arrived for this long
+const QUIET_PERIOD: Duration = Duration::from_secs(2);
+// and at the latest after this long, so a steady stream of writes can't hold syncing back forever
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(30);
+
+static WATCHER: Mutex<Option<RecommendedWatcher>> = Mutex::new(None);
+
+pub fn start(sender: UnboundedSender<PathBuf>) -> Result<(), String> {
+    let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+        Ok(event) => {
+            if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+                for path in event.paths {
+                    let _ = sender.send(path);
+                }
+            }
+        }
+        Err(e) => println!("File watcher error: {}", e),
+    }).map_err(|e| format!("Failed to create file watcher: {}", e))?;
+
+    *WATCHER.lock().unwrap() = Some(watcher);
+    Ok(())
+}
+
+// does nothing when watching is turned off
+pub fn watch(path: &Path) -> Result<(), String> {
+    match WATCHER.lock().unwrap().as_mut() {
+        Some(watcher) => watcher
+            .watch(path, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", path.display(), e)),
+        None => Ok(()),
+    }
+}
+
+pub fn unwatch(path: &Path) {
+    if let Some(watcher) = WATCHER.lock().unwrap().as_mut() {
+        if let Err(e) = watcher.unwatch(path) {
+            println!("Failed to stop watching {}: {}", path.display(), e);
+        }
+    }
+}
+
+// waits for the next burst of changes and returns every path it touched, None once the watcher is gone
+pub async fn next_changes(receiver: &mut UnboundedReceiver<PathBuf>) -> Option<HashSet<PathBuf>> {
+    let mut changed = HashSet::new();
+    changed.insert(receiver.recv().await?);
+
+    let deadline = Instant::now() + MAX_BATCH_DELAY;
+    while Instant::now() < deadline {
+        match tokio::time::timeout(QUIET_PERIOD, receiver.recv()).await {
+            Ok(Some(path)) => {
+                changed.insert(path);
+            }
+            Ok(None) | Err(_) => break,
+        }
+    }
+    Some(changed)
+}
@@ -20,8 +20,9 @@ export async function getModelInfo(modelName) {
   return await invoke("get_model_info", { modelName });
 }
 
-export async function streamPrompt(prompt, model) {
-  return await invoke("stream_prompt", { prompt, model });
+// knowledge turns knowledge base retrieval on or off for this prompt, null follows the setting
+export async function streamPrompt(prompt, model, conversationId = null, knowledge = null, attachmentIds = null) {
+  return await invoke("stream_prompt", { prompt, model, conversationId, knowledge, attachmentIds });
 }
 
 export async function abortStreamRequest() {
@@ -44,16 +45,44 @@ export async function saveConversation(conversation) {
   return await invoke("save_conversation", conversation);
 }
 
-export async function getConversations() {
-  return await invoke("get_conversations");
+export async function getConversations(query = null) {
+  return await invoke("get_conversations", { query });
 }
 
 export async function loadConversation(conversationId) {
   return await invoke("load_conversation", { conversationId });
 }
 
-export async function deleteConversation(conversationId) {
-  return await invoke("delete_conversation", { conversationId });
+export async function editMessage(conversationId, messageId, content) {
+  return await invoke("edit_message", { conversationId, messageId, content });
+}
+
+export async function switchBranch(conversationId, messageId) {
+  return await invoke("switch_branch", { conversationId, messageId });
+}
+
+export async function regenerateMessage(conversationId, messageId, model = null, options = null) {
+  return await invoke("regenerate_message", { conversationId, messageId, model, options });
+}
+
+export async function deleteConversation(conversationId, permanent = false) {
+  return await invoke("delete_conversation", { conversationId, permanent });
+}
+
+export async function restoreConversation(conversationId) {
+  return await invoke("restore_conversation", { conversationId });
+}
+
+export async function emptyTrash() {
+  return await invoke("empty_trash");
+}
+
+export async function getAppSettings() {
+  return await invoke("get_app_settings");
+}
+
+export async function saveAppSettings(settings) {
+  return await invoke("save_app_settings", { settings });
 }
 
 export async function createNewConversation(model) {
@@ -64,6 +93,235 @@ export async function updateConversationTitle(conversationId, firstMessage) {
   return await invoke("update_conversation_title", { conversationId, firstMessage });
 }
 
+export async function setConversationPinned(conversationId, pinned) {
+  return await invoke("set_conversation_pinned", { conversationId, pinned });
+}
+
+export async function setConversationArchived(conversationId, archived) {
+  return await invoke("set_conversation_archived", { conversationId, archived });
+}
+
+export async function getTags() {
+  return await invoke("get_tags");
+}
+
+export async function createTag(name, color = null) {
+  return await invoke("create_tag", { name, color });
+}
+
+export async function updateTag(tagId, name, color = null) {
+  return await invoke("update_tag", { tagId, name, color });
+}
+
+export async function deleteTag(tagId) {
+  return await invoke("delete_tag", { tagId });
+}
+
+export async function addConversationTag(conversationId, tag) {
+  return await invoke("add_conversation_tag", { conversationId, tag });
+}
+
+export async function removeConversationTag(conversationId, tag) {
+  return await invoke("remove_conversation_tag", { conversationId, tag });
+}
+
+export async function getFolders() {
+  return await invoke("get_folders");
+}
+
+export async function createFolder(name, parentId = null) {
+  return await invoke("create_folder", { name, parentId });
+}
+
+export async function renameFolder(folderId, name) {
+  return await invoke("rename_folder", { folderId, name });
+}
+
+export async function deleteFolder(folderId) {
+  return await invoke("delete_folder", { folderId });
+}
+
+export async function addConversationToFolder(conversationId, folderId) {
+  return await invoke("add_conversation_to_folder", { conversationId, folderId });
+}
+
+export async function removeConversationFromFolder(conversationId, folderId) {
+  return await invoke("remove_conversation_from_folder", { conversationId, folderId });
+}
+
+export async function exportConversation(conversationId, format, path) {
+  return await invoke("export_conversation", { conversationId, format, path });
+}
+
+export async function exportConversations(conversationIds, format, directory) {
+  return await invoke("export_conversations", { conversationIds, format, directory });
+}
+
+export async function importConversations(paths) {
+  return await invoke("import_conversations", { paths });
+}
+
+export async function rateMessage(conversationId, messageId, rating) {
+  return await invoke("rate_message", { conversationId, messageId, rating });
+}
+
+export async function exportFinetuneDataset(options, directory, name = null) {
+  return await invoke("export_finetune_dataset", { options, directory, name });
+}
+
+export async function createBackup(path = null) {
+  return await invoke("create_backup", { path });
+}
+
+export async function restoreBackup(path) {
+  return await invoke("restore_backup", { path });
+}
+
+// secret is { kind: "passphrase" | "keyfile", value: passphrase or keyfile path }
+export async function getEncryptionStatus() {
+  return await invoke("get_encryption_status");
+}
+
+export async function unlockDatabase(secret) {
+  return await invoke("unlock_database", { secret });
+}
+
+export async function lockDatabase() {
+  return await invoke("lock_database");
+}
+
+export async function enableEncryption(secret) {
+  return await invoke("enable_encryption", { secret });
+}
+
+export async function rotateEncryptionKey(secret) {
+  return await invoke("rotate_encryption_key", { secret });
+}
+
+export async function disableEncryption() {
+  return await invoke("disable_encryption");
+}
+
+export async function countTokens(model, text) {
+  return await invoke("count_tokens", { model, text });
+}
+
+export async function getConversationSummary(conversationId) {
+  return await invoke("get_conversation_summary", { conversationId });
+}
+
+export async function updateConversationSummary(summaryId, content) {
+  return await invoke("update_conversation_summary", { summaryId, content });
+}
+
+export async function regenerateConversationSummary(conversationId) {
+  return await invoke("regenerate_conversation_summary", { conversationId });
+}
+
+export async function retitleConversations(conversationIds = null, overwrite = false) {
+  return await invoke("retitle_conversations", { conversationIds, overwrite });
+}
+
+export async function getKnowledgeSources() {
+  return await invoke("get_knowledge_sources");
+}
+
+export async function addKnowledgeSource(path) {
+  return await invoke("add_knowledge_source", { path });
+}
+
+export async function removeKnowledgeSource(sourceId) {
+  return await invoke("remove_knowledge_source", { sourceId });
+}
+
+export async function reindexKnowledgeSource(sourceId, force = false) {
+  return await invoke("reindex_knowledge_source", { sourceId, force });
+}
+
+export async function searchKnowledgeBase(query, limit = null) {
+  return await invoke("search_knowledge_base", { query, limit });
+}
+
+export async function searchCode(query, sourceId = null, limit = null) {
+  return await invoke("search_code", { query, sourceId, limit });
+}
+
+export async function findCodeSymbols(name, sourceId = null) {
+  return await invoke("find_code_symbols", { name, sourceId });
+}
+
+export async function getPersonas() {
+  return await invoke("get_personas");
+}
+
+export async function createPersona(persona) {
+  return await invoke("create_persona", { persona });
+}
+
+export async function updatePersona(personaId, persona) {
+  return await invoke("update_persona", { personaId, persona });
+}
+
+export async function deletePersona(personaId) {
+  return await invoke("delete_persona", { personaId });
+}
+
+export async function setConversationPersona(conversationId, personaId = null) {
+  return await invoke("set_conversation_persona", { conversationId, personaId });
+}
+
+export async function getPromptTemplates() {
+  return await invoke("get_prompt_templates");
+}
+
+export async function createPromptTemplate(template) {
+  return await invoke("create_prompt_template", { template });
+}
+
+export async function updatePromptTemplate(templateId, template) {
+  return await invoke("update_prompt_template", { templateId, template });
+}
+
+export async function deletePromptTemplate(templateId) {
+  return await invoke("delete_prompt_template", { templateId });
+}
+
+export async function renderPromptTemplate(templateId, values = {}) {
+  return await invoke("render_prompt_template", { templateId, values });
+}
+
+export async function exportPromptTemplates(path, templateIds = null) {
+  return await invoke("export_prompt_templates", { path, templateIds });
+}
+
+export async function importPromptTemplates(path, replace = false) {
+  return await invoke("import_prompt_templates", { path, replace });
+}
+
+export async function semanticSearchConversations(query, limit = null) {
+  return await invoke("semantic_search_conversations", { query, limit });
+}
+
+export async function extractDocument(path) {
+  return await invoke("extract_document", { path });
+}
+
+export async function attachFile(path, messageId = null) {
+  return await invoke("attach_file", { path, messageId });
+}
+
+export async function attachImageData(name, data, messageId = null) {
+  return await invoke("attach_image_data", { name, data, messageId });
+}
+
+export async function removeAttachment(attachmentId) {
+  return await invoke("remove_attachment", { attachmentId });
+}
+
+export async function getAttachmentData(attachmentId) {
+  return await invoke("get_attachment_data", { attachmentId });
+}
+
 export async function minimizeWindow() {
   return await getCurrentWindow().minimize();
 }
@@ -7,7 +7,8 @@ import { abortStream } from './streaming.js';
 import {
   createNewConversationFromManager,
   saveCurrentConversationFromManager,
-  hasActiveConversation
+  hasActiveConversation,
+  getCurrentConversationId
 } from './conversations.js';
 
 export function updateSaveButtonState() {
@@ -71,7 +72,7 @@ export async function sendPrompt() {
   updateSaveButtonState();
 
   try {
-    await streamPrompt(prompt, selectedModel);
+    await streamPrompt(prompt, selectedModel, getCurrentConversationId());
     logMessage("Streaming completed");
 
   } catch (error) {
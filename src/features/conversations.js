@@ -1,5 +1,6 @@
 import { 
   logMessage,
+  listen,
   getConversations, 
   loadConversation, 
   deleteConversation, 
@@ -10,13 +11,16 @@ import {
 import { appState, updateGenerationState, clearConversation } from '../core/state.js';
 import { DOM, MESSAGE_TYPES, STATUS_TYPES } from '../core/constants.js';
 import { showStatus } from '../ui/status.js';
-import { clearMessageHistory, addMessage } from '../ui/messages.js';
+import { clearMessageHistory, addMessage, setMessageAttachments, setMessageCitations } from '../ui/messages.js';
 import { createElement } from '../utils/dom.js';
 import { customConfirm } from '../ui/confirm-modal.js';
 
 class ConversationManager {
   constructor() { // initialize properties to default values. pastConversationsList is a physical DOM element
     this.conversations = [];
+    this.nextCursor = null;
+    this.totalCount = 0;
+    this.isLoadingMore = false;
     this.currentConversationId = null;
     this.pastConversationsList = document.getElementById('past-conversations-list');
     this.isInitialized = false;
@@ -38,16 +42,37 @@ class ConversationManager {
 
   async loadConversations() {
     try {
-      this.conversations = await getConversations();
+      const page = await getConversations();
+      this.conversations = page.conversations;
+      this.nextCursor = page.next_cursor;
+      this.totalCount = page.total_count;
       this.renderConversationsList();
-      logMessage(`Loaded ${this.conversations.length} conversations`);
+      logMessage(`Loaded ${this.conversations.length} of ${this.totalCount} conversations`);
     } catch (error) {
       console.error('Error loading conversations:', error);
       this.conversations = [];
+      this.nextCursor = null;
       this.renderConversationsList();
     }
   }
 
+  async loadMoreConversations() {
+    if (!this.nextCursor || this.isLoadingMore) return;
+
+    this.isLoadingMore = true;
+    try {
+      const page = await getConversations({ cursor: this.nextCursor });
+      this.conversations = this.conversations.concat(page.conversations);
+      this.nextCursor = page.next_cursor;
+      this.totalCount = page.total_count;
+      this.renderConversationsList();
+    } catch (error) {
+      console.error('Error loading more conversations:', error);
+    } finally {
+      this.isLoadingMore = false;
+    }
+  }
+
   renderConversationsList() {
     if (!this.pastConversationsList) {
       console.warn('Past conversations list element not found');
@@ -146,9 +171,11 @@ class ConversationManager {
         
         conversation.messages.forEach(message => {
           appState.currentConversation.push({
+            id: message.id,
             type: message.role,
             content: message.content,
-            timestamp: message.timestamp
+            timestamp: message.timestamp,
+            siblingIds: message.sibling_ids
           });
         });
         
@@ -184,13 +211,15 @@ class ConversationManager {
 
   displayConversationMessages(messages) {
     messages.forEach(message => {
-      addMessage(message.content, message.role);
+      const messageEl = addMessage(message.content, message.role);
+      setMessageAttachments(messageEl, message.attachments || []);
+      setMessageCitations(messageEl, message.citations || []);
     });
   }
 
   async deleteConversationById(conversationId) {
     const confirmed = await customConfirm.show(
-      'Are you sure you want to delete this conversation? This action cannot be undone.',
+      'Move this conversation to the trash? It can be restored until the trash is emptied.',
       'Delete Conversation'
     );
 
@@ -212,7 +241,7 @@ class ConversationManager {
         addMessage('Conversation deleted. Start a new conversation.', MESSAGE_TYPES.SYSTEM);
       }
 
-      showStatus('Conversation deleted', STATUS_TYPES.SUCCESS);
+      showStatus('Conversation moved to trash', STATUS_TYPES.SUCCESS);
       logMessage('Conversation deleted:', conversationId);
 
     } catch (error) {
@@ -262,7 +291,9 @@ class ConversationManager {
       const conversation = {
         id: this.currentConversationId,
         title: currentTitle,
-        messages: appState.currentConversation.map(msg => ({
+        // system notices are only shown in the UI, the backend already records prompts and replies as they stream
+        messages: appState.currentConversation.filter(msg => msg.type !== MESSAGE_TYPES.SYSTEM).map(msg => ({
+          id: msg.id ?? null,
           role: msg.type,
           content: msg.content,
           timestamp: msg.timestamp
@@ -277,10 +308,7 @@ class ConversationManager {
       
       const firstUserMessage = appState.currentConversation.find(msg => msg.type === 'user');
       if (firstUserMessage) {
-        await updateConversationTitle({
-          conversationId: this.currentConversationId,
-          firstMessage: firstUserMessage.content
-        });
+        await updateConversationTitle(this.currentConversationId, firstUserMessage.content);
       }
       
       await this.loadConversations();
@@ -321,7 +349,17 @@ class ConversationManager {
   }
 
   setupEventListeners() {
+    this.pastConversationsList?.addEventListener('scroll', () => {
+      const list = this.pastConversationsList;
+      if (list.scrollTop + list.clientHeight >= list.scrollHeight - 40) {
+        this.loadMoreConversations();
+      }
+    });
 
+    // titles written by the title model arrive after the reply has finished
+    listen('conversation-titled', () => {
+      this.refreshConversations();
+    });
   }
 
   async refreshConversations() {
@@ -1,7 +1,8 @@
-import { checkOllamaStatus, getAvailableModels } from '../core/tauri-api.js';
+import { checkOllamaStatus, getAvailableModels, getEncryptionStatus, unlockDatabase } from '../core/tauri-api.js';
 import { setAvailableModels } from '../core/state.js';
 import { addMessage } from '../ui/messages.js';
 import { showStatus } from '../ui/status.js';
+import { unlockDialog } from '../ui/unlock-modal.js';
 import { populateModelSelector, enableChatControls } from './chat.js';
 import { MESSAGES, MESSAGE_TYPES, STATUS_TYPES } from '../core/constants.js';
 
@@ -38,4 +39,23 @@ export async function initializeApp() {
     showStatus("Failed to connect to Ollama", STATUS_TYPES.ERROR);
     addMessage("Error connecting to Ollama: " + error, MESSAGE_TYPES.SYSTEM);
   }
-}
\ No newline at end of file
+}
+
+// an encrypted database starts locked, ask for the passphrase or keyfile before conversations load
+export async function unlockAtStartup() {
+  try {
+    const status = await getEncryptionStatus();
+    if (!status.enabled || status.unlocked) return;
+
+    const unlocked = await unlockDialog.show(unlockDatabase, status.key_source);
+    if (unlocked) {
+      showStatus("Conversations unlocked", STATUS_TYPES.SUCCESS);
+    } else {
+      showStatus("Conversations are locked", STATUS_TYPES.WARNING);
+      addMessage("🔒 Conversations are encrypted and still locked. Restart the app to unlock them.", MESSAGE_TYPES.SYSTEM);
+    }
+  } catch (error) {
+    console.error("Error checking encryption status:", error);
+    showStatus("Failed to check encryption status", STATUS_TYPES.ERROR);
+  }
+}
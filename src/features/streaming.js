@@ -1,6 +1,6 @@
 import { logMessage, listen, abortStreamRequest } from '../core/tauri-api.js';
 import { appState, updateGenerationState } from '../core/state.js';
-import { createAssistantMessage, updateCurrentMessage } from '../ui/messages.js';
+import { createAssistantMessage, updateCurrentMessage, setMessageCitations } from '../ui/messages.js';
 import { showStatus } from '../ui/status.js';
 import { STATUS_TYPES } from '../core/constants.js';
 import { finishGeneration } from './chat.js';
@@ -17,6 +17,31 @@ export function setupStreamingListeners() {
     updateCurrentMessage(newResponse);
   });
 
+  // sent before every request with how much of the model's context window the prompt uses, and again
+  // after the reply when the model counted more tokens than estimated
+  listen("context-window", (event) => {
+    const usage = event.payload;
+    if (usage.trimmed_messages > 0 && !usage.measured) {
+      const action = usage.strategy === "summarize" ? "Summarized" : "Left out";
+      showStatus(`${action} ${usage.trimmed_messages} older messages to fit the ${usage.context_window} token context`, STATUS_TYPES.WARNING);
+    } else if (usage.near_limit) {
+      showStatus(`Conversation is near the context limit (${usage.prompt_tokens}/${usage.context_window} tokens)`, STATUS_TYPES.WARNING);
+    }
+  });
+
+  // knowledge base excerpts added to the prompt, sent before the first token
+  listen("citations", (event) => {
+    const { citations, error } = event.payload;
+    if (error) {
+      showStatus(`Knowledge base lookup failed: ${error}`, STATUS_TYPES.WARNING);
+    }
+    if (!appState.currentAssistantMessage) {
+      const messageEl = createAssistantMessage();
+      updateGenerationState(appState.isGenerating, "", messageEl);
+    }
+    setMessageCitations(appState.currentAssistantMessage, citations);
+  });
+
   listen("ollama-complete", (event) => {
     logMessage("Stream completed");
     finishGeneration();
@@ -4,7 +4,7 @@ import { setupMenuSystem } from './ui/menus.js';
 import { setupWindowControls } from './ui/window-controls.js';
 import { setupStreamingListeners } from './features/streaming.js';
 import { setupKeyboardShortcuts } from './features/keyboard.js';
-import { initializeApp } from './features/initialization.js';
+import { initializeApp, unlockAtStartup } from './features/initialization.js';
 import { sendPrompt, startNewConversation, onModelChange } from './features/chat.js';
 import { abortStream } from './features/streaming.js';
 import { showStatus } from './ui/status.js';
@@ -69,6 +69,8 @@ document.addEventListener('DOMContentLoaded', async () => {
     
     await initializeApp();
     
+    await unlockAtStartup();
+    
     await initializeConversationManager();
     
     logMessage("Application initialized successfully");
@@ -23,6 +23,7 @@ export function addMessage(content, type = MESSAGE_TYPES.ASSISTANT) {
   if (appState.editorPreferences.autoSave && type === MESSAGE_TYPES.ASSISTANT) {
     // auto save TODO
   }
+  return messageEl;
 }
 
 export function clearMessageHistory() {
@@ -41,6 +42,38 @@ export function updateCurrentMessage(content) {
   }
 }
 
+// lists the knowledge base excerpts a reply was given under its content
+export function setMessageCitations(messageEl, citations) {
+  messageEl.querySelector(".message-citations")?.remove();
+  if (!citations.length) return;
+
+  const listEl = document.createElement("ol");
+  listEl.className = "message-citations";
+  citations.forEach(citation => {
+    const itemEl = document.createElement("li");
+    const heading = citation.heading ? ` (${citation.heading})` : "";
+    itemEl.textContent = `${citation.marker} ${citation.path}:${citation.start_line}-${citation.end_line}${heading}`;
+    listEl.appendChild(itemEl);
+  });
+  messageEl.appendChild(listEl);
+}
+
+// names the files and images sent with a message under its content
+export function setMessageAttachments(messageEl, attachments) {
+  messageEl.querySelector(".message-attachments")?.remove();
+  if (!attachments.length) return;
+
+  const listEl = document.createElement("ul");
+  listEl.className = "message-attachments";
+  attachments.forEach(attachment => {
+    const itemEl = document.createElement("li");
+    itemEl.className = `attachment ${attachment.kind}`;
+    itemEl.textContent = attachment.name;
+    listEl.appendChild(itemEl);
+  });
+  messageEl.appendChild(listEl);
+}
+
 export function createAssistantMessage() {
   const messageEl = createMessage("", MESSAGE_TYPES.ASSISTANT);
   DOM.conversationHistory.appendChild(messageEl);
@@ -0,0 +1,113 @@
+class UnlockDialog {
+  constructor() {
+    this.modal = null;
+    this.kindSelect = null;
+    this.secretInput = null;
+    this.errorElement = null;
+    this.unlock = null;
+    this.resolvePromise = null;
+    this.initialized = false;
+  }
+
+  initialize() {
+    if (this.initialized) return;
+
+    this.modal = document.getElementById('unlockModal');
+    this.kindSelect = document.getElementById('unlockKind');
+    this.secretInput = document.getElementById('unlockSecret');
+    this.errorElement = document.getElementById('unlockError');
+
+    if (!this.modal || !this.kindSelect || !this.secretInput || !this.errorElement) {
+      console.error('Unlock modal elements not found in DOM');
+      return;
+    }
+
+    this.setupEventListeners();
+    this.initialized = true;
+  }
+
+  setupEventListeners() {
+    this.kindSelect.addEventListener('change', () => this.updateInput());
+
+    this.secretInput.addEventListener('keydown', (e) => {
+      if (e.key === 'Enter') {
+        e.preventDefault();
+        this.submit();
+      }
+    });
+
+    document.addEventListener('keydown', (e) => {
+      if (e.key === 'Escape' && this.modal?.classList.contains('show')) {
+        this.hide(false);
+      }
+    });
+  }
+
+  // a passphrase is typed hidden, a keyfile is given by its path
+  updateInput() {
+    const keyfile = this.kindSelect.value === 'keyfile';
+    this.secretInput.type = keyfile ? 'text' : 'password';
+    this.secretInput.placeholder = keyfile ? 'Path to keyfile' : 'Passphrase';
+  }
+
+  // keeps asking until unlock succeeds or the user skips, resolves with whether the database was unlocked
+  // unlock is called with { kind, value } and throws the backend error on a wrong secret
+  show(unlock, keySource = 'passphrase') {
+    if (!this.initialized) {
+      this.initialize();
+    }
+
+    if (!this.modal) {
+      return Promise.resolve(false);
+    }
+
+    return new Promise((resolve) => {
+      this.unlock = unlock;
+      this.resolvePromise = resolve;
+
+      this.kindSelect.value = keySource === 'keyfile' ? 'keyfile' : 'passphrase';
+      this.secretInput.value = '';
+      this.errorElement.textContent = '';
+      this.updateInput();
+
+      this.modal.classList.add('show');
+
+      setTimeout(() => this.secretInput.focus(), 100);
+    });
+  }
+
+  async submit() {
+    const value = this.secretInput.value;
+    if (!value || !this.unlock) return;
+
+    this.errorElement.textContent = '';
+    try {
+      await this.unlock({ kind: this.kindSelect.value, value });
+      this.hide(true);
+    } catch (error) {
+      this.errorElement.textContent = String(error);
+      this.secretInput.select();
+    }
+  }
+
+  hide(unlocked) {
+    if (this.modal) {
+      this.modal.classList.remove('show');
+    }
+    if (this.secretInput) {
+      this.secretInput.value = '';
+    }
+
+    if (this.resolvePromise) {
+      this.resolvePromise(unlocked);
+      this.resolvePromise = null;
+      this.unlock = null;
+    }
+  }
+}
+
+export const unlockDialog = new UnlockDialog();
+
+if (typeof window !== 'undefined') {
+  window.unlockDialog = unlockDialog;
+}